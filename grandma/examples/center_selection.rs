@@ -0,0 +1,99 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Compares the center selection strategies on MNIST. For each one it builds a tree and reports the node count, the
+//! radii of the routing nodes, the build time and the KNN query speed. Run `data/setup_data.py` first, then
+//! `cargo run --release --example center_selection` from this directory.
+
+extern crate grandma;
+extern crate pointcloud;
+extern crate rand;
+use grandma::utils::*;
+use grandma::CenterSelection;
+use pointcloud::*;
+use std::path::Path;
+use std::time::Instant;
+
+const QUERY_COUNT: usize = 1000;
+const K: usize = 10;
+
+fn main() {
+    let file_name = "../data/mnist_complex.yml";
+    let path = Path::new(file_name);
+    if !path.exists() {
+        panic!("{} does not exist", file_name);
+    }
+    let config = CoverTreeConfig::from_path(path).unwrap();
+
+    let strategies = vec![
+        CenterSelection::Random,
+        CenterSelection::FarthestFirst,
+        CenterSelection::ApproximateMedoid,
+        CenterSelection::DensityWeighted,
+    ];
+    println!("strategy,nodes,mean_radius,max_child_radius_ratio,build_secs,queries_per_sec");
+    for center_selection in strategies {
+        let point_cloud = PointCloud::<L2>::from_config(&config.point_cloud).unwrap();
+        let mut builder = config.builder();
+        builder
+            .set_verbosity(0)
+            .set_seed(0)
+            .set_center_selection(center_selection);
+
+        let now = Instant::now();
+        let tree = builder.build(point_cloud).unwrap();
+        let build_secs = now.elapsed().as_secs_f32();
+        let reader = tree.reader();
+
+        // How tight the routing nodes are, relative to the scale they're allowed.
+        let mut radius_total = 0.0;
+        let mut ratio_max: f32 = 0.0;
+        let mut routing_nodes = 0;
+        for (scale_index, layer) in reader.layers() {
+            let scale = reader.scale(scale_index);
+            layer.for_each_node(|_pi, n| {
+                if !n.is_leaf() {
+                    radius_total += n.radius();
+                    ratio_max = ratio_max.max(n.radius() / scale);
+                    routing_nodes += 1;
+                }
+            });
+        }
+
+        let point_cloud = reader.point_cloud();
+        let queries: Vec<PointIndex> = (0..QUERY_COUNT)
+            .map(|_i| rand::random::<PointIndex>() % point_cloud.len() as PointIndex)
+            .collect();
+        let now = Instant::now();
+        for pi in &queries {
+            reader.knn(point_cloud.get_point(*pi).unwrap(), K).unwrap();
+        }
+        let queries_per_sec = QUERY_COUNT as f32 / now.elapsed().as_secs_f32();
+
+        println!(
+            "{:?},{},{},{},{},{}",
+            center_selection,
+            reader.node_count(),
+            radius_total / routing_nodes.max(1) as f32,
+            ratio_max,
+            build_secs,
+            queries_per_sec
+        );
+    }
+}
@@ -91,6 +91,15 @@ fn main() {
                 ct_reader.scale(scale_index)
             );
         }
+        println!("===== Trace =====");
+        let zeros = vec![0.0; ct_reader.point_cloud().dim()];
+        let trace = ct_reader.trace(&zeros).unwrap();
+        let trace_report: String = trace
+            .iter()
+            .map(|step| step.to_json())
+            .collect::<Vec<String>>()
+            .join(",");
+        println!("[{}]", trace_report);
     }
     /*
     println!("===== Parameters =====");
@@ -107,15 +116,7 @@ fn main() {
     println!("===== Center KNN =====");
     let query1 = ct.center_knn_query(&zeros, 5).unwrap();
     println!("{:?}", query1);
-    println!("===== Trace =====");
     assert!(query1.len() == 5);
-    let query2 = ct.insert_trace(&zeros).unwrap();
-    let trace_report: String = query2
-        .iter()
-        .map(|node| node.report_json())
-        .collect::<Vec<String>>()
-        .join(",");
-    println!("[{}]", trace_report);
     println!("===== Saving =====");
     */
 }
@@ -17,19 +17,103 @@
 * under the License.
 */
 
+use crate::query_tools::MetadataFilter;
 use crate::*;
 use data_caches::*;
 use layer::*;
 use node::*;
-use pbr::ProgressBar;
 //use pointcloud::*;
 use std::cmp::{max, min};
+use std::sync::atomic::AtomicBool;
 use std::sync::{atomic, Arc};
 
-use crossbeam_channel::{unbounded, Receiver, Sender};
-use errors::MalwareBrotResult;
+use crossbeam_channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use errors::{MalwareBrotError, MalwareBrotResult};
+use rand::rngs::StdRng;
+use rand::{thread_rng, RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use std::time::{Duration, Instant};
+
+/// Gets told how a build is going. The callbacks are all made from the thread that called `build`, as the nodes are written
+/// into the tree, so they can't hold up the rayon threads. They do nothing by default.
+pub trait BuildObserver {
+    /// A node was written into the tree. `total_nodes` is how many nodes the build knows it has to make so far, this grows
+    /// as nodes are split.
+    fn node_created(&self, _address: NodeAddress, _nodes_created: usize, _total_nodes: usize) {}
+    /// Some points were placed for good, as singletons or the points of a leaf. Once `covered_points` reaches
+    /// `total_points` every point is in the tree.
+    fn points_covered(&self, _covered_points: usize, _total_points: usize) {}
+    /// The build wrote its first node at a new lowest scale index.
+    fn layer_reached(&self, _scale_index: i32) {}
+}
+
+/// The default observer for `build`, this ignores everything.
+pub struct SilentObserver;
+
+impl BuildObserver for SilentObserver {}
+
+/// Writes the build's progress to stderr, so it doesn't get mixed into anything on stdout. This is what `build` uses when
+/// the builder's verbosity is above 1.
+pub struct ProgressPrinter {
+    start: Instant,
+}
+
+impl ProgressPrinter {
+    /// Starts the clock for the rate it reports.
+    pub fn new() -> ProgressPrinter {
+        ProgressPrinter {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl BuildObserver for ProgressPrinter {
+    fn node_created(&self, _address: NodeAddress, nodes_created: usize, total_nodes: usize) {
+        if nodes_created % 1000 == 0 || nodes_created == total_nodes {
+            eprint!(
+                "\rBuilt {} of {} nodes, {:.0} per second",
+                nodes_created,
+                total_nodes,
+                (nodes_created as f32) / self.start.elapsed().as_secs_f32()
+            );
+        }
+    }
+
+    fn points_covered(&self, covered_points: usize, total_points: usize) {
+        if covered_points == total_points {
+            eprintln!(
+                "\nCovered all {} points, took {:?}",
+                total_points,
+                self.start.elapsed()
+            );
+        }
+    }
+}
+
+/// Stops a build that's running on another thread. Clone it, hand it to `build_with`, and call `cancel` on your copy.
+/// The build then returns `MalwareBrotError::BuildCancelled`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// A token that hasn't been cancelled
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Cancels the builds that have this token, or a clone of it.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, atomic::Ordering::SeqCst);
+    }
 
-use std::time::Instant;
+    /// Has `cancel` been called on this token, or a clone of it.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(atomic::Ordering::SeqCst)
+    }
+}
 
 #[derive(Debug)]
 struct BuilderNode {
@@ -38,8 +122,11 @@ struct BuilderNode {
 }
 
 impl BuilderNode {
-    fn new<M: Metric>(parameters: &CoverTreeParameters<M>) -> MalwareBrotResult<BuilderNode> {
-        let covered = CoveredData::new(&parameters.point_cloud)?;
+    fn new<M: Metric>(
+        parameters: &CoverTreeParameters<M>,
+        indexes: Vec<PointIndex>,
+    ) -> MalwareBrotResult<BuilderNode> {
+        let covered = CoveredData::new(&parameters.point_cloud, indexes)?;
         let scale_index = (covered.max_distance()).log(parameters.scale_base).ceil() as i32;
         Ok(BuilderNode {
             scale_index,
@@ -52,23 +139,50 @@ impl BuilderNode {
         (self.scale_index, self.covered.center_index)
     }
 
+    /// The random source for picking the centers of this node's children. With a seed it only depends on the seed and the
+    /// node's address, so it doesn't matter which order rayon splits the nodes in.
+    fn rng<M: Metric>(&self, parameters: &CoverTreeParameters<M>) -> Box<dyn RngCore> {
+        match parameters.seed {
+            Some(seed) => {
+                let (si, pi) = self.address();
+                let node_seed = seed
+                    ^ (pi as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+                    ^ (si as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+                Box::new(StdRng::seed_from_u64(node_seed))
+            }
+            None => Box::new(thread_rng()),
+        }
+    }
+
     fn split_parallel<M: Metric>(
         self,
         parameters: &Arc<CoverTreeParameters<M>>,
         node_sender: &Arc<Sender<MalwareBrotResult<(i32, PointIndex, CoverNode)>>>,
+        cancellation: &CancellationToken,
     ) {
         let parameters = Arc::clone(parameters);
         let node_sender = Arc::clone(node_sender);
+        let cancellation = cancellation.clone();
         rayon::spawn(move || {
+            // The receiver is dropped once the build has failed or been cancelled, so the sends can fail. There's nobody
+            // left to tell, and the tasks still queued stop here when they see the token.
+            if cancellation.is_cancelled() {
+                let _ = node_sender.send(Err(MalwareBrotError::BuildCancelled));
+                return;
+            }
             let (si, pi) = self.address();
             match self.split(&parameters) {
                 Ok((new_node, mut new_nodes)) => {
-                    node_sender.send(Ok((si, pi, new_node))).unwrap();
+                    if node_sender.send(Ok((si, pi, new_node))).is_err() {
+                        return;
+                    }
                     while let Some(node) = new_nodes.pop() {
-                        node.split_parallel(&parameters, &node_sender);
+                        node.split_parallel(&parameters, &node_sender, &cancellation);
                     }
                 }
-                Err(e) => node_sender.send(Err(e)).unwrap(),
+                Err(e) => {
+                    let _ = node_sender.send(Err(e));
+                }
             };
         });
     }
@@ -76,10 +190,20 @@ impl BuilderNode {
     fn split<M: Metric>(
         self,
         parameters: &Arc<CoverTreeParameters<M>>,
+    ) -> MalwareBrotResult<(CoverNode, Vec<BuilderNode>)> {
+        self.split_counted(parameters, &parameters.total_nodes)
+    }
+
+    /// The `split`, but the nodes it makes are counted on `node_count` instead of the tree's `total_nodes`.
+    fn split_counted<M: Metric>(
+        self,
+        parameters: &Arc<CoverTreeParameters<M>>,
+        node_count: &atomic::AtomicUsize,
     ) -> MalwareBrotResult<(CoverNode, Vec<BuilderNode>)> {
         //println!("=====================");
         //println!("Splitting node with address {:?} and covered: {:?}", self.address(),self.covered);
 
+        let mut rng = self.rng(parameters);
         let scale_index = self.scale_index;
         let covered = self.covered;
         let mut node = CoverNode::new((scale_index, covered.center_index));
@@ -118,9 +242,7 @@ impl BuilderNode {
                 covered: close,
             };
             new_nodes.push(new_node);
-            parameters
-                .total_nodes
-                .fetch_add(1, atomic::Ordering::SeqCst);
+            node_count.fetch_add(1, atomic::Ordering::SeqCst);
             /*
             First we make the covered child. This child has the same center as it's parent and it
             covers the points that are in the "close" set.
@@ -136,7 +258,12 @@ impl BuilderNode {
             */
 
             while fars.len() > 0 {
-                let new_close = fars.pick_center(next_scale, &parameters.point_cloud)?;
+                let new_close = fars.pick_center(
+                    next_scale,
+                    &parameters.point_cloud,
+                    &mut rng,
+                    parameters.center_selection,
+                )?;
                 //println!("\t\t [{}] New Covered: {:?}",split_count, new_close);
                 if new_close.len() == 1 && parameters.use_singletons {
                     /*
@@ -155,19 +282,17 @@ impl BuilderNode {
                         covered: new_close,
                     };
                     new_nodes.push(new_node);
-                    parameters
-                        .total_nodes
-                        .fetch_add(1, atomic::Ordering::SeqCst);
+                    node_count.fetch_add(1, atomic::Ordering::SeqCst);
                 }
             }
         }
 
         if new_nodes.len() == 1 && new_nodes[0].covered.len() == 1 {
             node.remove_children();
-            parameters
-                .total_nodes
-                .fetch_sub(1, atomic::Ordering::SeqCst);
+            node_count.fetch_sub(1, atomic::Ordering::SeqCst);
             node.insert_singletons(new_nodes.pop().unwrap().covered.to_indexes());
+            // It's a leaf now, and a leaf's count doesn't include its center.
+            node.set_cover_count(node.singleton_len());
         }
 
         node.update_metasummary(&parameters.point_cloud)?;
@@ -177,7 +302,48 @@ impl BuilderNode {
     }
 }
 
+/// Builds the nodes that cover `indexes` with the center and scale of `address`, by splitting it the same way `CoverTreeBuilder::build`
+/// does, but on this thread. This is for growing a built tree, the subtree's root node is the first node returned.
+pub(crate) fn build_subtree<M: Metric>(
+    parameters: &Arc<CoverTreeParameters<M>>,
+    address: NodeAddress,
+    indexes: Vec<PointIndex>,
+) -> MalwareBrotResult<Vec<CoverNode>> {
+    let covered = CoveredData::from_indexes(address.1, indexes, &parameters.point_cloud)?;
+    let mut pending = vec![BuilderNode {
+        scale_index: address.0,
+        covered,
+    }];
+    // The tree counts the nodes when they're written.
+    let node_count = atomic::AtomicUsize::new(0);
+    let mut nodes = Vec::new();
+    while let Some(builder_node) = pending.pop() {
+        let (node, new_nodes) = builder_node.split_counted(parameters, &node_count)?;
+        nodes.push(node);
+        pending.extend(new_nodes);
+    }
+    Ok(nodes)
+}
+
+/// How the builder picks the centers of a node's children from the points its nested child doesn't cover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CenterSelection {
+    /// Picks uniformly at random. Quick, but on clustered data it can make more children with larger radii than needed.
+    Random,
+    /// Picks the point furthest from all the centers picked so far, like Gonzalez's k-centers. This spreads the centers
+    /// out, so the children overlap less.
+    FarthestFirst,
+    /// Picks the approximate medoid of the uncovered points, from a sample of candidates scored on their total distance to
+    /// a sample of the points.
+    ApproximateMedoid,
+    /// Picks the candidate that would cover the most points, from a sample of candidates scored against a sample of the
+    /// points. This takes dense clusters out first.
+    DensityWeighted,
+}
+
 /// A construction object for a covertree.
+#[derive(Debug, Clone)]
 pub struct CoverTreeBuilder {
     /// See paper or main description, governs the number of children of each node. Higher is more.
     pub scale_base: f32,
@@ -192,6 +358,14 @@ pub struct CoverTreeBuilder {
     /// Printing verbosity. 2 is the default and gives a progress bar. Still not fully pulled thru the codebase. 
     /// This should be replaced by a logging solution
     pub verbosity: u32,
+    /// Seed for picking the centers. With a seed, building the same point cloud twice gives the same tree, and the same
+    /// saved protobuf. Without one the centers are picked with `thread_rng`.
+    pub seed: Option<u64>,
+    /// How the centers of children are picked, see `CenterSelection`. The default is `Random`.
+    pub center_selection: CenterSelection,
+    /// Computes the `subtree_summary` of every node once the tree is built, so filtered queries can skip subtrees that
+    /// can't match. This is a pass over every node, so it's off by default.
+    pub summarize_subtrees: bool,
 }
 
 impl CoverTreeBuilder {
@@ -204,6 +378,9 @@ impl CoverTreeBuilder {
             use_singletons: true,
             cluster_min: 5,
             verbosity: 2,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
         }
     }
 
@@ -232,12 +409,89 @@ impl CoverTreeBuilder {
         self.verbosity = x;
         self
     }
+    ///
+    pub fn set_seed(&mut self, x: u64) -> &mut Self {
+        self.seed = Some(x);
+        self
+    }
+    ///
+    pub fn set_center_selection(&mut self, x: CenterSelection) -> &mut Self {
+        self.center_selection = x;
+        self
+    }
+    ///
+    pub fn set_summarize_subtrees(&mut self, x: bool) -> &mut Self {
+        self.summarize_subtrees = x;
+        self
+    }
     /// Pass a point cloud object when ready. 
-    /// To do, make this point cloud an Arc
+    ///
+    /// If the verbosity is above 1 this reports its progress on stderr with a `ProgressPrinter`. Use `build_with` to get
+    /// the progress yourself, or to be able to cancel the build.
     pub fn build<M: Metric>(
         &self,
         point_cloud: PointCloud<M>,
     ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        self.build_with(point_cloud, &*self.default_observer(), &CancellationToken::new())
+    }
+
+    /// Builds the tree, telling the observer how it's going. If the token is cancelled the build stops splitting nodes
+    /// and returns `MalwareBrotError::BuildCancelled`. Errors from the rayon threads are returned here too.
+    pub fn build_with<M: Metric, O: BuildObserver + ?Sized>(
+        &self,
+        point_cloud: PointCloud<M>,
+        observer: &O,
+        cancellation: &CancellationToken,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let indexes = point_cloud.reference_indexes();
+        self.build_subset_with(Arc::new(point_cloud), &indexes, observer, cancellation)
+    }
+
+    /// Builds a tree over some of the points of a point cloud, so several trees can share one loaded dataset. Queries on
+    /// the tree only find these points, but the point cloud's `len` and `reference_indexes` still cover all of them.
+    /// Duplicate indexes are ignored.
+    pub fn build_subset<M: Metric>(
+        &self,
+        point_cloud: Arc<PointCloud<M>>,
+        indexes: &[PointIndex],
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        self.build_subset_with(
+            point_cloud,
+            indexes,
+            &*self.default_observer(),
+            &CancellationToken::new(),
+        )
+    }
+
+    /// Builds a tree over the points of a point cloud whose metadata passes the filter, like all the samples of one
+    /// malware family. See `build_subset`.
+    pub fn build_filtered<M: Metric, F: MetadataFilter>(
+        &self,
+        point_cloud: Arc<PointCloud<M>>,
+        filter: &F,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let mut indexes = Vec::new();
+        for pi in point_cloud.reference_indexes() {
+            if filter.matches(&point_cloud.get_metadata(pi)?) {
+                indexes.push(pi);
+            }
+        }
+        self.build_subset(point_cloud, &indexes)
+    }
+
+    /// `build_subset` with an observer and a cancellation token, see `build_with`.
+    pub fn build_subset_with<M: Metric, O: BuildObserver + ?Sized>(
+        &self,
+        point_cloud: Arc<PointCloud<M>>,
+        indexes: &[PointIndex],
+        observer: &O,
+        cancellation: &CancellationToken,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let mut indexes = Vec::from(indexes);
+        indexes.sort();
+        indexes.dedup();
+        let total_points = indexes.len();
+
         let parameters = CoverTreeParameters {
             total_nodes: atomic::AtomicUsize::new(1),
             scale_base: self.scale_base,
@@ -245,11 +499,14 @@ impl CoverTreeBuilder {
             resolution: self.resolution,
             use_singletons: self.use_singletons,
             cluster_min: self.cluster_min,
-            point_cloud: point_cloud,
+            point_cloud,
             verbosity: self.verbosity,
+            seed: self.seed,
+            center_selection: self.center_selection,
+            summarize_subtrees: self.summarize_subtrees,
         };
 
-        let root = BuilderNode::new(&parameters)?;
+        let root = BuilderNode::new(&parameters, indexes)?;
         let root_address = root.address();
         let scale_range = root_address.0 - parameters.resolution;
         let mut layers = Vec::with_capacity(scale_range as usize);
@@ -265,56 +522,73 @@ impl CoverTreeBuilder {
 
         let node_sender = Arc::new(node_sender);
         let parameters = Arc::new(parameters);
-        root.split_parallel(&parameters, &node_sender);
-        let mut pb = ProgressBar::new(1u64);
-        if parameters.verbosity > 1 {
-            pb.format("╢▌▌░╟");
-        }
+        root.split_parallel(&parameters, &node_sender, cancellation);
 
-        let mut cover_tree = CoverTreeWriter {
-            parameters: Arc::clone(&parameters),
-            layers,
-            root_address,
-        };
+        let mut cover_tree = CoverTreeWriter::new(Arc::clone(&parameters), layers, root_address);
 
         let mut inserted_nodes: usize = 0;
-        let now = Instant::now();
+        let mut covered_points: usize = 0;
+        let mut lowest_scale_index = root_address.0 + 1;
         loop {
-            match node_receiver.recv() {
+            if cancellation.is_cancelled() {
+                return Err(MalwareBrotError::BuildCancelled);
+            }
+            match node_receiver.recv_timeout(Duration::from_millis(100)) {
                 Ok(res) => {
-                    let (scale_index, point_index, new_node) = res.unwrap();
+                    let (scale_index, point_index, new_node) = res?;
+                    // The points of a leaf are done, as are the singletons of a routing node.
+                    let newly_covered = if new_node.is_leaf() {
+                        new_node.singleton_len() + 1
+                    } else {
+                        new_node.singleton_len()
+                    };
                     unsafe {cover_tree.insert_raw(scale_index, point_index, new_node);}
                     inserted_nodes += 1;
-                    if parameters.verbosity > 1 {
-                        pb.total = parameters.total_nodes.load(atomic::Ordering::SeqCst) as u64;
-                        pb.inc();
+
+                    if scale_index < lowest_scale_index {
+                        lowest_scale_index = scale_index;
+                        observer.layer_reached(scale_index);
+                    }
+                    observer.node_created(
+                        (scale_index, point_index),
+                        inserted_nodes,
+                        parameters.total_nodes.load(atomic::Ordering::SeqCst),
+                    );
+                    if newly_covered > 0 {
+                        covered_points += newly_covered;
+                        observer.points_covered(covered_points, total_points);
                     }
                 }
-                Err(_) => {}
+                // Wake up now and then to check the token, in case the splits are slow.
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {}
             }
             // Stop if there are enough done, and there are no more outstanding parameter references
             if inserted_nodes == parameters.total_nodes.load(atomic::Ordering::SeqCst) {
                 break;
             }
         }
-        if parameters.verbosity > 1 {
-            println!("\nWriting layers...");
-        }
         cover_tree.refresh();
-        if parameters.verbosity > 1 {
-            println!(
-                "Finished building, took {:?} with {} per second",
-                now.elapsed(),
-                (inserted_nodes as f32) / now.elapsed().as_secs_f32()
-            );
+        if self.summarize_subtrees {
+            cover_tree.summarize_subtrees()?;
         }
         Ok(cover_tree)
     }
+
+    fn default_observer(&self) -> Box<dyn BuildObserver> {
+        if self.verbosity > 1 {
+            Box::new(ProgressPrinter::new())
+        } else {
+            Box::new(SilentObserver)
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tree::tests::{random_point_cloud, test_builder};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
     use std::{thread, time};
 
     pub fn create_test_parameters(
@@ -332,8 +606,11 @@ mod tests {
             resolution: -9,
             use_singletons: true,
             cluster_min: 0,
-            point_cloud,
+            point_cloud: Arc::new(point_cloud),
             verbosity: 0,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
         })
     }
 
@@ -347,7 +624,7 @@ mod tests {
 
         let test_parameters =
             create_test_parameters(Box::from(data.clone()), 1, Box::from(data.clone()), 1);
-        let build_node = BuilderNode::new(&test_parameters).unwrap();
+        let build_node = BuilderNode::new(&test_parameters, test_parameters.point_cloud.reference_indexes()).unwrap();
         let (scale_index, center_index) = build_node.address();
 
         println!("{:?}", build_node);
@@ -382,7 +659,7 @@ mod tests {
 
         let test_parameters =
             create_test_parameters(Box::from(data.clone()), 1, Box::from(labels), 1);
-        let build_node = BuilderNode::new(&test_parameters).unwrap();
+        let build_node = BuilderNode::new(&test_parameters, test_parameters.point_cloud.reference_indexes()).unwrap();
 
         let (node_sender, node_receiver): (
             Sender<MalwareBrotResult<(i32, PointIndex, CoverNode)>>,
@@ -390,7 +667,7 @@ mod tests {
         ) = unbounded();
         let node_sender = Arc::new(node_sender);
 
-        build_node.split_parallel(&test_parameters, &node_sender);
+        build_node.split_parallel(&test_parameters, &node_sender, &CancellationToken::new());
         thread::sleep(time::Duration::from_millis(100));
         let split_count = test_parameters.total_nodes.load(atomic::Ordering::SeqCst) - 1;
         println!(
@@ -428,6 +705,9 @@ mod tests {
             use_singletons: true,
             cluster_min: 5,
             verbosity: 0,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
@@ -472,6 +752,9 @@ mod tests {
             use_singletons: false,
             cluster_min: 5,
             verbosity: 0,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
@@ -480,4 +763,172 @@ mod tests {
         assert!(reader.get_node_and((-2, 2), |n| n.is_leaf()).is_some());
         assert!(reader.no_dangling_refs());
     }
+
+    #[test]
+    fn center_selections_build_valid_trees() {
+        let count = 300;
+        let strategies = vec![
+            CenterSelection::Random,
+            CenterSelection::FarthestFirst,
+            CenterSelection::ApproximateMedoid,
+            CenterSelection::DensityWeighted,
+        ];
+        for center_selection in strategies {
+            let point_cloud = random_point_cloud(count, 0);
+            let mut builder = CoverTreeBuilder::new();
+            builder
+                .set_scale_base(1.5)
+                .set_cutoff(3)
+                .set_resolution(-9)
+                .set_verbosity(0)
+                .set_center_selection(center_selection);
+            let tree = builder.build(point_cloud).unwrap();
+            let reader = tree.reader();
+            assert!(reader.no_dangling_refs());
+            let root_count = reader.get_node_and(reader.root_address(), |n| n.cover_count());
+            assert!(root_count == Some(count));
+
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            let found = reader.knn(&query, 5).unwrap();
+            let mut expected = reader
+                .point_cloud()
+                .distances_to_point(&query, &reader.point_cloud().reference_indexes())
+                .unwrap();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            for ((d, _pi), true_d) in found.iter().zip(&expected) {
+                assert_approx_eq!(*d, *true_d);
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct CountingObserver {
+        nodes_created: std::cell::Cell<usize>,
+        covered_points: std::cell::Cell<usize>,
+        layers: std::cell::RefCell<Vec<i32>>,
+        cancel_after: Option<(usize, CancellationToken)>,
+    }
+
+    impl BuildObserver for CountingObserver {
+        fn node_created(&self, _address: NodeAddress, nodes_created: usize, _total_nodes: usize) {
+            self.nodes_created.set(nodes_created);
+            if let Some((count, token)) = &self.cancel_after {
+                if nodes_created >= *count {
+                    token.cancel();
+                }
+            }
+        }
+        fn points_covered(&self, covered_points: usize, _total_points: usize) {
+            self.covered_points.set(covered_points);
+        }
+        fn layer_reached(&self, scale_index: i32) {
+            self.layers.borrow_mut().push(scale_index);
+        }
+    }
+
+    #[test]
+    fn build_observer_and_cancellation() {
+        let count = 500;
+        let point_cloud = || random_point_cloud(count, 0);
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_scale_base(1.5).set_cutoff(3).set_resolution(-9);
+
+        let observer = CountingObserver::default();
+        let tree = builder
+            .build_with(point_cloud(), &observer, &CancellationToken::new())
+            .unwrap();
+        let reader = tree.reader();
+        assert!(observer.nodes_created.get() == reader.node_count());
+        assert!(observer.covered_points.get() == count);
+        let layers = observer.layers.borrow();
+        assert!(layers[0] == reader.root_address().0);
+        assert!(layers.windows(2).all(|w| w[0] > w[1]));
+
+        let token = CancellationToken::new();
+        token.cancel();
+        match builder.build_with(point_cloud(), &SilentObserver, &token) {
+            Err(MalwareBrotError::BuildCancelled) => {}
+            _ => panic!("The build should have been cancelled"),
+        }
+
+        let token = CancellationToken::new();
+        let observer = CountingObserver {
+            cancel_after: Some((3, token.clone())),
+            ..CountingObserver::default()
+        };
+        match builder.build_with(point_cloud(), &observer, &token) {
+            Err(MalwareBrotError::BuildCancelled) => {}
+            _ => panic!("The build should have been cancelled"),
+        }
+        assert!(observer.nodes_created.get() == 3);
+    }
+
+    #[test]
+    fn seeded_builds_match() {
+        use protobuf::Message;
+
+        let count = 500;
+        let build = |seed: u64| {
+            let mut builder = test_builder();
+            builder.set_seed(seed);
+            builder.build(random_point_cloud(count, 0)).unwrap()
+        };
+
+        let first = build(7).save().write_to_bytes().unwrap();
+        let second = build(7).save().write_to_bytes().unwrap();
+        assert!(first == second);
+        let other_seed = build(8).save().write_to_bytes().unwrap();
+        assert!(first != other_seed);
+    }
+
+    #[test]
+    fn subset_trees_share_point_cloud() {
+        use pointcloud::labels::values::{Metadata, Value, Vector};
+
+        let count = 500;
+        let mut rng = StdRng::seed_from_u64(0);
+        let data: Vec<f32> = (0..2 * count).map(|_i| rng.gen::<f32>()).collect();
+        let labels: Vec<f32> = (0..count).map(|i| (i % 2) as f32).collect();
+        let point_cloud = Arc::new(
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(labels), 1).unwrap(),
+        );
+        let mut builder = CoverTreeBuilder::new();
+        builder.set_scale_base(1.5).set_cutoff(3).set_resolution(-9);
+
+        let subset: Vec<PointIndex> = (0..count as PointIndex).filter(|pi| pi % 3 == 0).collect();
+        let subset_tree = builder.build_subset(Arc::clone(&point_cloud), &subset).unwrap();
+        let odd = |m: &Metadata| match m.get("y") {
+            Some(Value::Vector(Vector::Real(y))) => y[0] > 0.5,
+            _ => false,
+        };
+        let odd_tree = builder.build_filtered(Arc::clone(&point_cloud), &odd).unwrap();
+        let odd_indexes: Vec<PointIndex> = (0..count as PointIndex).filter(|pi| pi % 2 == 1).collect();
+
+        for (tree, indexes) in vec![(subset_tree, subset), (odd_tree, odd_indexes)] {
+            let reader = tree.reader();
+            assert!(Arc::ptr_eq(&reader.parameters().point_cloud, &point_cloud));
+            assert!(reader.point_indexes() == indexes);
+            assert!(reader.validate().unwrap().is_valid());
+            let root_coverage = reader.get_node_and(reader.root_address(), |n| n.coverage());
+            assert!(root_coverage == Some(indexes.len()));
+
+            let k = 5;
+            for _i in 0..20 {
+                let query = [rand::random::<f32>(), rand::random::<f32>()];
+                let found = reader.knn(&query, k).unwrap();
+                let mut expected = point_cloud.distances_to_point(&query, &indexes).unwrap();
+                expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                assert!(found.len() == k);
+                for ((d, pi), true_d) in found.iter().zip(&expected) {
+                    assert!(indexes.contains(pi));
+                    assert_approx_eq!(*d, *true_d);
+                }
+            }
+        }
+
+        match builder.build_subset(point_cloud, &[]) {
+            Err(MalwareBrotError::EmptyTree) => {}
+            _ => panic!("A tree over no points should not build"),
+        }
+    }
 }
@@ -0,0 +1,425 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # KNN Classification and Regression
+//! Predicts a label column of the point cloud from the labels of a point's KNN.
+//!
+//! The classifier votes, each neighbor gives its class a weight and the probabilities are the normalized weights. Classes
+//! can come from one-hot vectors (like the `y` vector of `PointCloud::simple_from_ram`, the class is the index of the
+//! largest entry), integers, bools or strings. The regressor takes the weighted mean of a numeric column.
+//!
+//! Neighbors that don't have the label are skipped. The leave-one-out helpers use a `KnnGraph`, so every point is predicted
+//! from the other points in the tree.
+
+use crate::errors::MalwareBrotError;
+use crate::knn_graph::KnnGraph;
+use crate::*;
+use pointcloud::labels::values::{Number, Value, Vector};
+use std::collections::HashMap;
+
+/// How much each neighbor counts for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Weighting {
+    /// Every neighbor has the same weight.
+    Uniform,
+    /// Neighbors are weighted by `1/d`. If some neighbors are at distance 0, only they count.
+    InverseDistance,
+}
+
+/// A class read from a label.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ClassLabel {
+    /// Integer, bool and one-hot classes.
+    Index(i64),
+    /// String classes.
+    Name(String),
+}
+
+/// The result of a classification.
+#[derive(Debug, Clone)]
+pub struct ClassPrediction {
+    /// The probability of each class that a neighbor had, most likely first
+    pub probabilities: Vec<(ClassLabel, f32)>,
+    /// The most likely class, `None` if none of the neighbors had the label
+    pub class: Option<ClassLabel>,
+    /// The probability of the predicted class
+    pub confidence: f32,
+}
+
+/// The result of a regression.
+#[derive(Debug, Clone)]
+pub struct RegressionPrediction {
+    /// The weighted mean of the neighbors' values, `None` if none of the neighbors had the label
+    pub mean: Option<f32>,
+    /// The weighted standard deviation of the neighbors' values
+    pub deviation: f32,
+}
+
+/// A KNN classifier over one of the label columns of the tree's point cloud.
+pub struct KnnClassifier<'a, M: Metric> {
+    reader: &'a CoverTreeReader<M>,
+    label: String,
+    k: usize,
+    weighting: Weighting,
+}
+
+impl<'a, M: Metric> KnnClassifier<'a, M> {
+    /// Creates a classifier for the label `label` that uses the `k` nearest neighbors, with inverse distance weighting.
+    pub fn new(reader: &'a CoverTreeReader<M>, label: &str, k: usize) -> KnnClassifier<'a, M> {
+        KnnClassifier {
+            reader,
+            label: label.to_string(),
+            k,
+            weighting: Weighting::InverseDistance,
+        }
+    }
+
+    /// Sets the weighting of the vote
+    pub fn set_weighting(&mut self, weighting: Weighting) {
+        self.weighting = weighting;
+    }
+
+    /// Predicts the class of a point.
+    pub fn predict(&self, point: &[f32]) -> MalwareBrotResult<ClassPrediction> {
+        let knn = self.reader.knn(point, self.k)?;
+        self.vote(&knn)
+    }
+
+    /// Predicts the class of a point that's in the tree's point cloud. If `exclude_self` is set the point doesn't vote.
+    pub fn predict_index(
+        &self,
+        pi: PointIndex,
+        exclude_self: bool,
+    ) -> MalwareBrotResult<ClassPrediction> {
+        let knn = self.reader.knn_index(pi, self.k, exclude_self)?;
+        self.vote(&knn)
+    }
+
+    /// The fraction of the labeled points in the tree whose class is predicted correctly by the other points.
+    pub fn leave_one_out_accuracy(&self) -> MalwareBrotResult<f32> {
+        let graph = KnnGraph::build(self.reader, self.k)?;
+        let mut correct = 0;
+        let mut total = 0;
+        for row in 0..graph.len() {
+            let pi = graph.point_indexes[row];
+            let truth = match label_value(self.reader, pi, &self.label)? {
+                Some(value) => class_label(&self.label, &value)?,
+                None => None,
+            };
+            if let Some(truth) = truth {
+                let prediction = self.vote(&graph_knn(&graph, row))?;
+                if prediction.class == Some(truth) {
+                    correct += 1;
+                }
+                total += 1;
+            }
+        }
+        if total == 0 {
+            return Ok(0.0);
+        }
+        Ok(correct as f32 / total as f32)
+    }
+
+    fn vote(&self, knn: &[(f32, PointIndex)]) -> MalwareBrotResult<ClassPrediction> {
+        let mut votes: HashMap<ClassLabel, f32> = HashMap::new();
+        let weights = weights(knn, self.weighting);
+        for ((_d, pi), w) in knn.iter().zip(weights) {
+            if let Some(value) = label_value(self.reader, *pi, &self.label)? {
+                if let Some(class) = class_label(&self.label, &value)? {
+                    *votes.entry(class).or_insert(0.0) += w;
+                }
+            }
+        }
+
+        let total: f32 = votes.values().sum();
+        let mut probabilities: Vec<(ClassLabel, f32)> = votes
+            .into_iter()
+            .map(|(class, w)| (class, if total > 0.0 { w / total } else { 0.0 }))
+            .collect();
+        probabilities.sort_by(|(ca, pa), (cb, pb)| {
+            pb.partial_cmp(pa)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| ca.cmp(cb))
+        });
+        let (class, confidence) = match probabilities.first() {
+            Some((class, p)) => (Some(class.clone()), *p),
+            None => (None, 0.0),
+        };
+        Ok(ClassPrediction {
+            probabilities,
+            class,
+            confidence,
+        })
+    }
+}
+
+/// A KNN regressor over one of the numeric label columns of the tree's point cloud.
+pub struct KnnRegressor<'a, M: Metric> {
+    reader: &'a CoverTreeReader<M>,
+    label: String,
+    k: usize,
+    weighting: Weighting,
+}
+
+impl<'a, M: Metric> KnnRegressor<'a, M> {
+    /// Creates a regressor for the label `label` that uses the `k` nearest neighbors, with inverse distance weighting.
+    pub fn new(reader: &'a CoverTreeReader<M>, label: &str, k: usize) -> KnnRegressor<'a, M> {
+        KnnRegressor {
+            reader,
+            label: label.to_string(),
+            k,
+            weighting: Weighting::InverseDistance,
+        }
+    }
+
+    /// Sets the weighting of the mean
+    pub fn set_weighting(&mut self, weighting: Weighting) {
+        self.weighting = weighting;
+    }
+
+    /// Predicts the value of a point.
+    pub fn predict(&self, point: &[f32]) -> MalwareBrotResult<RegressionPrediction> {
+        let knn = self.reader.knn(point, self.k)?;
+        self.mean(&knn)
+    }
+
+    /// Predicts the value of a point that's in the tree's point cloud. If `exclude_self` is set the point isn't used.
+    pub fn predict_index(
+        &self,
+        pi: PointIndex,
+        exclude_self: bool,
+    ) -> MalwareBrotResult<RegressionPrediction> {
+        let knn = self.reader.knn_index(pi, self.k, exclude_self)?;
+        self.mean(&knn)
+    }
+
+    /// The mean squared error of predicting each labeled point in the tree from the other points.
+    pub fn leave_one_out_error(&self) -> MalwareBrotResult<f32> {
+        let graph = KnnGraph::build(self.reader, self.k)?;
+        let mut error = 0.0;
+        let mut total = 0;
+        for row in 0..graph.len() {
+            let pi = graph.point_indexes[row];
+            let truth = match label_value(self.reader, pi, &self.label)? {
+                Some(value) => numeric_label(&self.label, &value)?,
+                None => None,
+            };
+            if let (Some(truth), Some(mean)) = (truth, self.mean(&graph_knn(&graph, row))?.mean) {
+                error += (truth - mean) * (truth - mean);
+                total += 1;
+            }
+        }
+        if total == 0 {
+            return Ok(0.0);
+        }
+        Ok(error / total as f32)
+    }
+
+    fn mean(&self, knn: &[(f32, PointIndex)]) -> MalwareBrotResult<RegressionPrediction> {
+        let mut values = Vec::with_capacity(knn.len());
+        let weights = weights(knn, self.weighting);
+        for ((_d, pi), w) in knn.iter().zip(weights) {
+            if let Some(value) = label_value(self.reader, *pi, &self.label)? {
+                if let Some(x) = numeric_label(&self.label, &value)? {
+                    values.push((x, w));
+                }
+            }
+        }
+
+        let total: f32 = values.iter().map(|(_x, w)| w).sum();
+        if values.is_empty() || total <= 0.0 {
+            return Ok(RegressionPrediction {
+                mean: None,
+                deviation: 0.0,
+            });
+        }
+        let mean = values.iter().map(|(x, w)| x * w).sum::<f32>() / total;
+        let variance = values
+            .iter()
+            .map(|(x, w)| w * (x - mean) * (x - mean))
+            .sum::<f32>()
+            / total;
+        Ok(RegressionPrediction {
+            mean: Some(mean),
+            deviation: variance.sqrt(),
+        })
+    }
+}
+
+fn graph_knn(graph: &KnnGraph, row: usize) -> Vec<(f32, PointIndex)> {
+    let (nbrs, dists) = graph.row(row);
+    dists
+        .iter()
+        .zip(nbrs)
+        .map(|(d, j)| (*d, graph.point_indexes[*j]))
+        .collect()
+}
+
+fn weights(knn: &[(f32, PointIndex)], weighting: Weighting) -> Vec<f32> {
+    match weighting {
+        Weighting::Uniform => vec![1.0; knn.len()],
+        Weighting::InverseDistance => {
+            if knn.iter().any(|(d, _pi)| *d <= 0.0) {
+                knn.iter()
+                    .map(|(d, _pi)| if *d <= 0.0 { 1.0 } else { 0.0 })
+                    .collect()
+            } else {
+                knn.iter().map(|(d, _pi)| 1.0 / d).collect()
+            }
+        }
+    }
+}
+
+fn label_value<M: Metric>(
+    reader: &CoverTreeReader<M>,
+    pi: PointIndex,
+    label: &str,
+) -> MalwareBrotResult<Option<Value>> {
+    let mut metadata = reader.point_cloud().get_metadata(pi)?;
+    Ok(metadata.swap_remove(label))
+}
+
+fn argmax<T: PartialOrd + Copy>(x: &[T]) -> Option<i64> {
+    let mut max: Option<(usize, T)> = None;
+    for (i, v) in x.iter().enumerate() {
+        match max {
+            Some((_, m)) if m >= *v => {}
+            _ => max = Some((i, *v)),
+        }
+    }
+    max.map(|(i, _)| i as i64)
+}
+
+fn class_label(label: &str, value: &Value) -> MalwareBrotResult<Option<ClassLabel>> {
+    let class = match value {
+        Value::Null => None,
+        Value::Bool(b) => Some(ClassLabel::Index(*b as i64)),
+        Value::Number(Number::Natural(x)) => Some(ClassLabel::Index(*x as i64)),
+        Value::Number(Number::Integer(x)) => Some(ClassLabel::Index(*x as i64)),
+        Value::Vector(Vector::Real(x)) => argmax(x).map(ClassLabel::Index),
+        Value::Vector(Vector::Natural(x)) => argmax(x).map(ClassLabel::Index),
+        Value::Vector(Vector::Integer(x)) => argmax(x).map(ClassLabel::Index),
+        Value::String(s) => Some(ClassLabel::Name(s.clone())),
+        Value::Number(Number::Real(_)) => {
+            return Err(MalwareBrotError::UnsupportedLabel {
+                key: label.to_string(),
+                value_type: value.value_type().to_string(),
+            })
+        }
+    };
+    Ok(class)
+}
+
+fn numeric_label(label: &str, value: &Value) -> MalwareBrotResult<Option<f32>> {
+    let x = match value {
+        Value::Null => None,
+        Value::Bool(b) => Some(*b as u8 as f32),
+        Value::Number(Number::Real(x)) => Some(*x),
+        Value::Number(Number::Natural(x)) => Some(*x as f32),
+        Value::Number(Number::Integer(x)) => Some(*x as f32),
+        _ => {
+            return Err(MalwareBrotError::UnsupportedLabel {
+                key: label.to_string(),
+                value_type: value.value_type().to_string(),
+            })
+        }
+    };
+    Ok(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pointcloud::labels::values::Metadata;
+    use crate::tree::tests::test_builder;
+    use pointcloud::labels::LabelScheme;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn two_blob_tree() -> CoverTreeWriter<L2> {
+        let count = 400;
+        let mut data = Vec::with_capacity(2 * count);
+        let mut one_hot = Vec::with_capacity(2 * count);
+        let mut rng = StdRng::seed_from_u64(0);
+        for i in 0..count {
+            let class = i % 2;
+            data.push(rng.gen::<f32>() + 3.0 * class as f32);
+            data.push(rng.gen::<f32>());
+            one_hot.push((1 - class) as f32);
+            one_hot.push(class as f32);
+        }
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(one_hot), 2).unwrap();
+        let builder = test_builder();
+        builder.build(point_cloud).unwrap()
+    }
+
+    #[test]
+    fn classifies_one_hot_blobs() {
+        let tree = two_blob_tree();
+        let reader = tree.reader();
+        let classifier = KnnClassifier::new(&reader, "y", 5);
+
+        let prediction = classifier.predict(&[3.5, 0.5]).unwrap();
+        assert!(prediction.class == Some(ClassLabel::Index(1)));
+        assert_approx_eq!(prediction.confidence, 1.0);
+        let total: f32 = prediction.probabilities.iter().map(|(_c, p)| p).sum();
+        assert_approx_eq!(total, 1.0);
+
+        assert!(classifier.leave_one_out_accuracy().unwrap() > 0.99);
+    }
+
+    #[test]
+    fn regresses_and_classifies_columns() {
+        let mut scheme = LabelScheme::new();
+        scheme.add_f32("score".to_string());
+        scheme.add_string("side".to_string());
+        let mut labels = scheme.empty();
+        let count = 200;
+        let mut data = Vec::with_capacity(count);
+        for i in 0..count {
+            let x = i as f32 / count as f32;
+            data.push(x);
+            let mut metadata = Metadata::new();
+            metadata.insert("score".to_string(), Value::Number(Number::Real(2.0 * x)));
+            let side = if x < 0.5 { "left" } else { "right" };
+            metadata.insert("side".to_string(), Value::String(side.to_string()));
+            labels.push(None, metadata).unwrap();
+        }
+        let point_cloud = PointCloud::<L2>::from_ram(Box::from(data), 1, labels).unwrap();
+        let builder = test_builder();
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let mut regressor = KnnRegressor::new(&reader, "score", 4);
+        regressor.set_weighting(Weighting::Uniform);
+        let prediction = regressor.predict(&[0.2525]).unwrap();
+        assert_approx_eq!(prediction.mean.unwrap(), 0.505, 0.01);
+        assert!(regressor.leave_one_out_error().unwrap() < 0.001);
+
+        let classifier = KnnClassifier::new(&reader, "side", 3);
+        let prediction = classifier.predict(&[0.9]).unwrap();
+        assert!(prediction.class == Some(ClassLabel::Name("right".to_string())));
+        assert!(classifier.leave_one_out_accuracy().unwrap() > 0.95);
+
+        assert!(KnnClassifier::new(&reader, "score", 3)
+            .predict(&[0.5])
+            .is_err());
+    }
+}
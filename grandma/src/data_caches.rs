@@ -17,9 +17,11 @@
 * under the License.
 */
 
-use crate::errors::MalwareBrotResult;
+use crate::builders::CenterSelection;
+use crate::errors::{MalwareBrotError, MalwareBrotResult};
 use pointcloud::*;
-use rand::{thread_rng, Rng};
+use rand::seq::index::sample;
+use rand::Rng;
 use std::fmt;
 
 #[derive(Clone)]
@@ -29,31 +31,47 @@ pub(crate) struct CoveredData {
     pub(crate) center_index: PointIndex,
 }
 
+/// The number of candidate centers the sampling strategies look at.
+const CENTER_CANDIDATES: usize = 16;
+/// The number of uncovered points the sampling strategies score the candidates against.
+const CENTER_REFERENCES: usize = 128;
+
 #[derive(Debug, Clone)]
 pub(crate) struct UncoveredData {
     coverage: Vec<PointIndex>,
+    /// The distance from each point to the closest center picked so far, starting with the parent's center.
+    dists: Vec<f32>,
 }
 
 impl UncoveredData {
-    pub(crate) fn pick_center<M: Metric>(
+    pub(crate) fn pick_center<M: Metric, R: Rng + ?Sized>(
         &mut self,
         radius: f32,
         point_cloud: &PointCloud<M>,
+        rng: &mut R,
+        center_selection: CenterSelection,
     ) -> MalwareBrotResult<CoveredData> {
-        let mut rng = thread_rng();
-        let new_center: usize = rng.gen_range(0, self.coverage.len());
+        let new_center: usize = match center_selection {
+            CenterSelection::Random => rng.gen_range(0, self.coverage.len()),
+            CenterSelection::FarthestFirst => self.farthest(),
+            CenterSelection::ApproximateMedoid => self.approximate_medoid(point_cloud, rng)?,
+            CenterSelection::DensityWeighted => self.densest(radius, point_cloud, rng)?,
+        };
         let center_index = self.coverage.remove(new_center);
+        self.dists.remove(new_center);
         let dists = point_cloud.distances_to_point_index(center_index, &self.coverage)?;
 
         let mut close_index = Vec::with_capacity(self.coverage.len());
         let mut close_dist = Vec::with_capacity(self.coverage.len());
         let mut far = Vec::new();
-        for (i, d) in self.coverage.iter().zip(&dists) {
+        let mut far_dists = Vec::new();
+        for ((i, d), old_d) in self.coverage.iter().zip(&dists).zip(&self.dists) {
             if *d < radius {
                 close_index.push(*i);
                 close_dist.push(*d);
             } else {
                 far.push(*i);
+                far_dists.push(old_d.min(*d));
             }
         }
         let close = CoveredData {
@@ -62,9 +80,76 @@ impl UncoveredData {
             center_index,
         };
         self.coverage = far;
+        self.dists = far_dists;
         Ok(close)
     }
 
+    /// The point furthest from all the centers picked so far.
+    fn farthest(&self) -> usize {
+        self.dists
+            .iter()
+            .enumerate()
+            .fold((0, -1.0), |(best_i, best_d), (i, d)| {
+                if *d > best_d {
+                    (i, *d)
+                } else {
+                    (best_i, best_d)
+                }
+            })
+            .0
+    }
+
+    /// Of a sample of candidates, the one with the smallest total distance to a sample of the uncovered points.
+    fn approximate_medoid<M: Metric, R: Rng + ?Sized>(
+        &self,
+        point_cloud: &PointCloud<M>,
+        rng: &mut R,
+    ) -> MalwareBrotResult<usize> {
+        let references = self.sample_indexes(CENTER_REFERENCES, rng);
+        let mut best = (0, std::f32::INFINITY);
+        let candidates = sample(rng, self.coverage.len(), CENTER_CANDIDATES.min(self.coverage.len()));
+        for candidate in candidates.into_iter() {
+            let total: f32 = point_cloud
+                .distances_to_point_index(self.coverage[candidate], &references)?
+                .iter()
+                .sum();
+            if total < best.1 {
+                best = (candidate, total);
+            }
+        }
+        Ok(best.0)
+    }
+
+    /// Of a sample of candidates, the one that would cover the most of a sample of the uncovered points.
+    fn densest<M: Metric, R: Rng + ?Sized>(
+        &self,
+        radius: f32,
+        point_cloud: &PointCloud<M>,
+        rng: &mut R,
+    ) -> MalwareBrotResult<usize> {
+        let references = self.sample_indexes(CENTER_REFERENCES, rng);
+        let mut best = (0, 0);
+        let candidates = sample(rng, self.coverage.len(), CENTER_CANDIDATES.min(self.coverage.len()));
+        for candidate in candidates.into_iter() {
+            let count = point_cloud
+                .distances_to_point_index(self.coverage[candidate], &references)?
+                .iter()
+                .filter(|d| **d < radius)
+                .count();
+            if count > best.1 {
+                best = (candidate, count);
+            }
+        }
+        Ok(best.0)
+    }
+
+    fn sample_indexes<R: Rng + ?Sized>(&self, amount: usize, rng: &mut R) -> Vec<PointIndex> {
+        sample(rng, self.coverage.len(), amount.min(self.coverage.len()))
+            .into_iter()
+            .map(|i| self.coverage[i])
+            .collect()
+    }
+
     pub(crate) fn len(&self) -> usize {
         self.coverage.len()
     }
@@ -101,9 +186,26 @@ fn find_split(dist_indexes: &[(f32, usize)], thresh: f32) -> usize {
 }
 
 impl CoveredData {
-    pub(crate) fn new<M: Metric>(point_cloud: &PointCloud<M>) -> MalwareBrotResult<CoveredData> {
-        let mut coverage = point_cloud.reference_indexes();
-        let center_index = coverage.pop().unwrap();
+    /// Covered data for the root of a tree over `coverage`, the last point is the center.
+    pub(crate) fn new<M: Metric>(
+        point_cloud: &PointCloud<M>,
+        mut coverage: Vec<PointIndex>,
+    ) -> MalwareBrotResult<CoveredData> {
+        let center_index = coverage.pop().ok_or(MalwareBrotError::EmptyTree)?;
+        let dists = point_cloud.distances_to_point_index(center_index, &coverage)?;
+        Ok(CoveredData {
+            dists,
+            coverage,
+            center_index,
+        })
+    }
+
+    /// Covered data for a center and a list of the points it covers, used when we grow an existing tree.
+    pub(crate) fn from_indexes<M: Metric>(
+        center_index: PointIndex,
+        coverage: Vec<PointIndex>,
+        point_cloud: &PointCloud<M>,
+    ) -> MalwareBrotResult<CoveredData> {
         let dists = point_cloud.distances_to_point_index(center_index, &coverage)?;
         Ok(CoveredData {
             dists,
@@ -116,12 +218,14 @@ impl CoveredData {
         let mut close_index = Vec::with_capacity(self.coverage.len());
         let mut close_dist = Vec::with_capacity(self.coverage.len());
         let mut far = Vec::new();
+        let mut far_dists = Vec::new();
         for (i, d) in self.coverage.iter().zip(&self.dists) {
             if *d < thresh {
                 close_index.push(*i);
                 close_dist.push(*d);
             } else {
                 far.push(*i);
+                far_dists.push(*d);
             }
         }
         let close = CoveredData {
@@ -129,7 +233,10 @@ impl CoveredData {
             dists: close_dist,
             center_index: self.center_index,
         };
-        let new_far = UncoveredData { coverage: far };
+        let new_far = UncoveredData {
+            coverage: far,
+            dists: far_dists,
+        };
         Ok((close, new_far))
     }
 
@@ -152,7 +259,6 @@ impl CoveredData {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::sync::Arc;
 
     #[test]
     fn splits_correctly_1() {
@@ -171,7 +277,7 @@ mod tests {
 
         let point_cloud =
             PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
-        let cache = CoveredData::new(&Arc::new(point_cloud)).unwrap();
+        let cache = CoveredData::new(&point_cloud, point_cloud.reference_indexes()).unwrap();
         let (close, far) = cache.split(1.0).unwrap();
 
         assert_eq!(1, close.len());
@@ -195,18 +301,32 @@ mod tests {
 
         let point_cloud =
             PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
-        let mut cache = UncoveredData {
-            coverage: (0..19 as PointIndex).collect(),
-        };
-        let close = cache.pick_center(1.0, &point_cloud).unwrap();
+        let strategies = vec![
+            CenterSelection::Random,
+            CenterSelection::FarthestFirst,
+            CenterSelection::ApproximateMedoid,
+            CenterSelection::DensityWeighted,
+        ];
+        for center_selection in strategies {
+            let mut cache = UncoveredData {
+                coverage: (0..19 as PointIndex).collect(),
+                dists: point_cloud
+                    .distances_to_point_index(19, &(0..19 as PointIndex).collect::<Vec<_>>())
+                    .unwrap(),
+            };
+            let close = cache
+                .pick_center(1.0, &point_cloud, &mut rand::thread_rng(), center_selection)
+                .unwrap();
 
-        assert!(!close.coverage.contains(&close.center_index));
-        assert!(!cache.coverage.contains(&close.center_index));
-        for i in &close.coverage {
-            assert!(!cache.coverage.contains(i));
-        }
-        for i in &cache.coverage {
-            assert!(!close.coverage.contains(i));
+            assert!(!close.coverage.contains(&close.center_index));
+            assert!(!cache.coverage.contains(&close.center_index));
+            assert!(cache.coverage.len() == cache.dists.len());
+            for i in &close.coverage {
+                assert!(!cache.coverage.contains(i));
+            }
+            for i in &cache.coverage {
+                assert!(!close.coverage.contains(i));
+            }
         }
     }
 
@@ -228,7 +348,7 @@ mod tests {
         let point_cloud =
             PointCloud::<L2>::simple_from_ram(Box::from(data.clone()), 1, Box::from(labels), 1)
                 .unwrap();
-        let cache = CoveredData::new(&point_cloud).unwrap();
+        let cache = CoveredData::new(&point_cloud, point_cloud.reference_indexes()).unwrap();
 
         let thresh = 0.5;
         let mut true_close: Vec<u64> = Vec::new();
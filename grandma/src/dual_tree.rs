@@ -0,0 +1,284 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Dual Tree KNN
+//! For every point in a query tree, finds its KNN in a reference tree. Instead of running a KNN query from the root of the
+//! reference tree for each query point, we walk both trees together.
+//!
+//! Each query node carries a frontier of reference nodes and reference points that could hold the KNN of some point it covers.
+//! When we visit a query node with center `q` and radius `ρ_q`, every point `p` it covers has a kth nearest neighbor within
+//! `D_k + ρ_q`, where `D_k` is the kth smallest distance from `q` to a reference point we know about. A reference node with
+//! center `r` and radius `ρ_r` only has points further than `d(q,r) - ρ_q - ρ_r` from `p`, so we can drop it from the frontier if
+//! `d(q,r) - ρ_r > D_k + 2ρ_q`. Reference nodes that are larger than the query node are split into their children and singletons,
+//! which tightens `D_k`, and the refined frontier is passed on to the query node's children.
+//!
+//! The points a query node owns directly, its singletons (and its center if it's a leaf), are then finished with a normal KNN
+//! query that's seeded with the frontier instead of the reference root.
+
+use crate::errors::MalwareBrotError;
+use crate::query_tools::KnnQueryHeap;
+use crate::*;
+use std::f32;
+
+/// A reference node on the frontier, with the distance from the current query center to its center.
+#[derive(Debug, Clone, Copy)]
+struct FrontierNode {
+    address: NodeAddress,
+    radius: f32,
+    is_leaf: bool,
+    dist: f32,
+}
+
+/// The reference nodes and points that could hold the KNN of a point covered by the query node.
+#[derive(Debug, Clone, Default)]
+struct Frontier {
+    nodes: Vec<FrontierNode>,
+    points: Vec<(PointIndex, f32)>,
+}
+
+/// For each point in the `query` tree, finds the `k` nearest neighbors in the `reference` tree. Both trees have to use the same metric
+/// and dimension. The result is sorted by the query point index, and each KNN is closest first like `CoverTreeReader::knn`.
+pub fn dual_tree_knn<M: Metric>(
+    query: &CoverTreeReader<M>,
+    reference: &CoverTreeReader<M>,
+    k: usize,
+) -> MalwareBrotResult<Vec<(PointIndex, Vec<(f32, PointIndex)>)>> {
+    let query_dim = query.point_cloud().dim();
+    let reference_dim = reference.point_cloud().dim();
+    if query_dim != reference_dim {
+        return Err(MalwareBrotError::DimensionMismatch {
+            expected: reference_dim,
+            found: query_dim,
+        });
+    }
+
+    // Both trees are read as of one refresh each, if either refreshes part way through the whole join starts over.
+    query.consistent(|query_root| {
+        reference.consistent(|reference_root| {
+            let mut results = Vec::with_capacity(query.point_cloud().len());
+            let mut query_heap = KnnQueryHeap::new(k, reference.parameters().scale_base);
+            let root_frontier = Frontier {
+                nodes: reference_node(reference, reference_root, 0.0)
+                    .into_iter()
+                    .collect(),
+                points: Vec::new(),
+            };
+            let mut stack = vec![(query_root, root_frontier)];
+
+            while let Some((query_address, frontier)) = stack.pop() {
+                let query_node = query.get_node_and(query_address, |n| {
+                    (
+                        n.radius().max(0.0),
+                        n.children().map(|(nested_scale, addresses)| {
+                            let mut children = vec![(nested_scale, query_address.1)];
+                            children.extend_from_slice(addresses);
+                            children
+                        }),
+                        Vec::from(n.singletons()),
+                    )
+                });
+                let (query_radius, children, singletons) = match query_node {
+                    Some(query_node) => query_node,
+                    None => continue,
+                };
+
+                let center = query.point_cloud().get_point(query_address.1)?;
+                let frontier = refine_frontier(reference, frontier, center, query_radius, k)?;
+
+                let mut owned_points = singletons;
+                match children {
+                    Some(children) => {
+                        for child_address in children {
+                            stack.push((child_address, frontier.clone()));
+                        }
+                    }
+                    None => owned_points.push(query_address.1),
+                }
+
+                for pi in owned_points {
+                    let point = query.point_cloud().get_point(pi)?;
+                    query_heap.reset(k);
+                    seed_heap(reference, &frontier, point, &mut query_heap)?;
+                    reference.resume_knn_with_heap(point, &mut query_heap)?;
+                    let mut dists = vec![0.0; query_heap.len()];
+                    let mut indexes = vec![0; query_heap.len()];
+                    query_heap.unpack_into(&mut dists, &mut indexes);
+                    results.push((pi, dists.into_iter().zip(indexes).collect()));
+                }
+            }
+
+            results.sort_by_key(|(pi, _)| *pi);
+            Ok(results)
+        })
+    })
+}
+
+fn reference_node<M: Metric>(
+    reference: &CoverTreeReader<M>,
+    address: NodeAddress,
+    dist: f32,
+) -> Option<FrontierNode> {
+    // Nodes that only cover their center have a radius of -inf
+    reference.get_node_and(address, |n| FrontierNode {
+        address,
+        radius: n.radius().max(0.0),
+        is_leaf: n.is_leaf(),
+        dist,
+    })
+}
+
+/// The kth smallest distance to a known reference point, or infinity if we don't know `k` points yet.
+fn kth_known_distance(frontier: &Frontier, k: usize) -> f32 {
+    let mut known: Vec<(PointIndex, f32)> = frontier
+        .nodes
+        .iter()
+        .map(|n| (n.address.1, n.dist))
+        .chain(frontier.points.iter().cloned())
+        .collect();
+    known.sort_by_key(|(pi, _d)| *pi);
+    known.dedup_by_key(|(pi, _d)| *pi);
+    if k == 0 || known.len() < k {
+        return f32::INFINITY;
+    }
+    let mut dists: Vec<f32> = known.iter().map(|(_pi, d)| *d).collect();
+    dists.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    dists[k - 1]
+}
+
+/// Recomputes the distances of the frontier to the new query center, then prunes and splits it until every reference node left is
+/// a leaf or no larger than the query node.
+fn refine_frontier<M: Metric>(
+    reference: &CoverTreeReader<M>,
+    frontier: Frontier,
+    center: &[f32],
+    query_radius: f32,
+    k: usize,
+) -> MalwareBrotResult<Frontier> {
+    let point_cloud = reference.point_cloud();
+    let mut indexes: Vec<PointIndex> = frontier.nodes.iter().map(|n| n.address.1).collect();
+    indexes.extend(frontier.points.iter().map(|(pi, _d)| *pi));
+    let dists = point_cloud.distances_to_point(center, &indexes)?;
+    let (node_dists, point_dists) = dists.split_at(frontier.nodes.len());
+    let mut frontier = Frontier {
+        nodes: frontier
+            .nodes
+            .iter()
+            .zip(node_dists)
+            .map(|(n, d)| FrontierNode { dist: *d, ..*n })
+            .collect(),
+        points: frontier
+            .points
+            .iter()
+            .zip(point_dists)
+            .map(|((pi, _), d)| (*pi, *d))
+            .collect(),
+    };
+
+    loop {
+        let bound = kth_known_distance(&frontier, k) + 2.0 * query_radius;
+        frontier.nodes.retain(|n| n.dist - n.radius <= bound);
+        frontier.points.retain(|(_pi, d)| *d <= bound);
+
+        let (to_split, to_keep): (Vec<FrontierNode>, Vec<FrontierNode>) = frontier
+            .nodes
+            .iter()
+            .partition(|n| !n.is_leaf && n.radius > query_radius);
+        if to_split.is_empty() {
+            return Ok(frontier);
+        }
+        frontier.nodes = to_keep;
+
+        for parent in to_split {
+            let split = reference.get_node_and(parent.address, |n| {
+                let mut children = Vec::new();
+                if let Some((nested_scale, addresses)) = n.children() {
+                    children.extend_from_slice(addresses);
+                    children.push((nested_scale, parent.address.1));
+                }
+                (children, Vec::from(n.singletons()))
+            });
+            let (children, singletons) = match split {
+                Some(split) => split,
+                None => continue,
+            };
+            // The nested child is last, it has the same center as the parent.
+            let child_indexes: Vec<PointIndex> = children[..children.len().saturating_sub(1)]
+                .iter()
+                .map(|(_si, pi)| *pi)
+                .collect();
+            let mut child_dists = point_cloud.distances_to_point(center, &child_indexes)?;
+            child_dists.push(parent.dist);
+            frontier.nodes.extend(
+                children
+                    .iter()
+                    .zip(child_dists)
+                    .filter_map(|(address, dist)| reference_node(reference, *address, dist)),
+            );
+            let singleton_dists = point_cloud.distances_to_point(center, &singletons)?;
+            frontier
+                .points
+                .extend(singletons.iter().cloned().zip(singleton_dists));
+        }
+    }
+}
+
+/// Pushes the frontier onto a KNN heap for a single query point.
+fn seed_heap<M: Metric>(
+    reference: &CoverTreeReader<M>,
+    frontier: &Frontier,
+    point: &[f32],
+    query_heap: &mut KnnQueryHeap,
+) -> MalwareBrotResult<()> {
+    let point_cloud = reference.point_cloud();
+    let addresses: Vec<NodeAddress> = frontier.nodes.iter().map(|n| n.address).collect();
+    let centers: Vec<PointIndex> = addresses.iter().map(|(_si, pi)| *pi).collect();
+    let node_dists = point_cloud.distances_to_point(point, &centers)?;
+    query_heap.push_nodes(&addresses, &node_dists, None);
+
+    let points: Vec<PointIndex> = frontier.points.iter().map(|(pi, _d)| *pi).collect();
+    let point_dists = point_cloud.distances_to_point(point, &points)?;
+    query_heap.push_outliers(&points, &point_dists);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::tests::random_tree;
+
+    #[test]
+    fn dual_tree_matches_knn() {
+        let query_tree = random_tree(200, 1);
+        let reference_tree = random_tree(500, 2);
+        let query = query_tree.reader();
+        let reference = reference_tree.reader();
+
+        let k = 5;
+        let results = dual_tree_knn(&query, &reference, k).unwrap();
+        assert!(results.len() == 200);
+        for (pi, nbrs) in results {
+            let point = query.point_cloud().get_point(pi).unwrap();
+            let expected = reference.knn(point, k).unwrap();
+            assert!(nbrs.len() == k);
+            for ((d, _), (true_d, _)) in nbrs.iter().zip(&expected) {
+                assert_approx_eq!(*d, *true_d);
+            }
+        }
+    }
+}
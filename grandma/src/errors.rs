@@ -20,6 +20,8 @@
 //! The errors that can occor when a cover tree is loading, working or saving. 
 //! Most errors are floated up from `PointCloud` as that's the i/o layer.
 
+use crate::{ClusterAddress, NodeAddress};
+use pointcloud::PointIndex;
 use pointcloud::errors::PointCloudError;
 use protobuf::ProtobufError;
 use std::error::Error;
@@ -45,6 +47,46 @@ pub enum MalwareBrotError {
     DoubleNest,
     /// Inserted a node before you changed it from a leaf node into a normal node. Insert the nested child first.
     InsertBeforeNest,
+    /// The query points don't have the same dimension as the points in the tree
+    DimensionMismatch {
+        /// The dimension of the tree's point cloud
+        expected: usize,
+        /// The dimension that was passed in
+        found: usize,
+    },
+    /// A node that should be in the tree, like a child of a node, isn't in its layer
+    NodeNotInTree(NodeAddress),
+    /// The point is in the point cloud, but not in the tree, like a point that was already removed
+    PointNotInTree(PointIndex),
+    /// A cluster that should be in the tree isn't in its layer, usually because `CoverTreeWriter::cluster` wasn't run
+    ClusterNotInTree(ClusterAddress),
+    /// The tree would have no points in it, from removing them all or building over none
+    EmptyTree,
+    /// The label can't be used for this prediction, like a real number for classification
+    UnsupportedLabel {
+        /// The label's key
+        key: String,
+        /// The type of the label's value
+        value_type: String,
+    },
+    /// The build's `CancellationToken` was cancelled before it finished
+    BuildCancelled,
+    /// The trees can't be merged as they use different scale bases
+    ScaleBaseMismatch {
+        /// The scale base of the tree being merged into
+        expected: f32,
+        /// The scale base of the other tree
+        found: f32,
+    },
+    /// The tree was refreshed while a lazy query was still reading it, run the query again
+    TreeRefreshed,
+    /// Points were pushed to the point cloud but couldn't be put in the tree. They stay in the point cloud
+    PointsNotInserted {
+        /// The indexes the points were given in the point cloud
+        point_indexes: Vec<PointIndex>,
+        /// Why they couldn't be inserted
+        error: Box<MalwareBrotError>,
+    },
 }
 
 impl fmt::Display for MalwareBrotError {
@@ -63,6 +105,36 @@ impl fmt::Display for MalwareBrotError {
             &MalwareBrotError::InsertBeforeNest => {
                 write!(f,"Inserted a node into a node that does not have a nested child")
             }
+            &MalwareBrotError::DimensionMismatch { .. } => {
+                write!(f,"the query points do not have the same dimension as the tree")
+            }
+            &MalwareBrotError::NodeNotInTree(..) => {
+                write!(f,"a node referenced by the tree is not in its layer")
+            }
+            &MalwareBrotError::PointNotInTree(..) => {
+                write!(f,"the point is not in the tree")
+            }
+            &MalwareBrotError::ClusterNotInTree(..) => {
+                write!(f,"a cluster referenced by the tree is not in its layer, has the tree been clustered?")
+            }
+            &MalwareBrotError::EmptyTree => {
+                write!(f,"the tree would have no points in it")
+            }
+            &MalwareBrotError::UnsupportedLabel { .. } => {
+                write!(f,"the label's type can not be used for this prediction")
+            }
+            &MalwareBrotError::BuildCancelled => {
+                write!(f,"the build was cancelled")
+            }
+            &MalwareBrotError::ScaleBaseMismatch { .. } => {
+                write!(f,"the trees do not have the same scale base")
+            }
+            &MalwareBrotError::TreeRefreshed => {
+                write!(f,"the tree was refreshed while the query was reading it")
+            }
+            &MalwareBrotError::PointsNotInserted { ref error, .. } => {
+                write!(f,"the points were pushed to the point cloud, but not inserted into the tree: {}",error)
+            }
         }
     }
 }
@@ -84,6 +156,36 @@ impl Error for MalwareBrotError {
             &MalwareBrotError::InsertBeforeNest => {
                 "Inserted a node into a node that does not have a nested child"
             }
+            &MalwareBrotError::DimensionMismatch { .. } => {
+                "the query points do not have the same dimension as the tree"
+            }
+            &MalwareBrotError::NodeNotInTree(..) => {
+                "a node referenced by the tree is not in its layer"
+            }
+            &MalwareBrotError::PointNotInTree(..) => {
+                "the point is not in the tree"
+            }
+            &MalwareBrotError::ClusterNotInTree(..) => {
+                "a cluster referenced by the tree is not in its layer, has the tree been clustered?"
+            }
+            &MalwareBrotError::EmptyTree => {
+                "the tree would have no points in it"
+            }
+            &MalwareBrotError::UnsupportedLabel { .. } => {
+                "the label's type can not be used for this prediction"
+            }
+            &MalwareBrotError::BuildCancelled => {
+                "the build was cancelled"
+            }
+            &MalwareBrotError::ScaleBaseMismatch { .. } => {
+                "the trees do not have the same scale base"
+            }
+            &MalwareBrotError::TreeRefreshed => {
+                "the tree was refreshed while the query was reading it"
+            }
+            &MalwareBrotError::PointsNotInserted { .. } => {
+                "the points were pushed to the point cloud, but not inserted into the tree"
+            }
         }
     }
 
@@ -95,6 +197,16 @@ impl Error for MalwareBrotError {
             &MalwareBrotError::NameNotInTree { .. } => None,
             &MalwareBrotError::DoubleNest => None,
             &MalwareBrotError::InsertBeforeNest => None,
+            &MalwareBrotError::DimensionMismatch { .. } => None,
+            &MalwareBrotError::NodeNotInTree(..) => None,
+            &MalwareBrotError::PointNotInTree(..) => None,
+            &MalwareBrotError::ClusterNotInTree(..) => None,
+            &MalwareBrotError::EmptyTree => None,
+            &MalwareBrotError::UnsupportedLabel { .. } => None,
+            &MalwareBrotError::BuildCancelled => None,
+            &MalwareBrotError::ScaleBaseMismatch { .. } => None,
+            &MalwareBrotError::TreeRefreshed => None,
+            &MalwareBrotError::PointsNotInserted { ref error, .. } => Some(&**error),
         }
     }
 }
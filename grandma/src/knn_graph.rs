@@ -0,0 +1,257 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # All Points KNN Graph
+//! Computes the KNN of every point in the tree's point cloud, for UMAP, spectral embeddings and the like.
+//!
+//! Every point in the tree lives somewhere: it's either a singleton of a node, or the center of a chain of nested nodes.
+//! The lowest of these is the point's home node. Instead of starting each query at the root we start it at the home node
+//! too. The home node covers the point's closest neighbors, so the greedy search there fills the KNN heap quickly and the
+//! search from the root is mostly pruned away.
+//!
+//! The output is a CSR style adjacency, rows and columns are positions in `point_indexes`.
+
+use crate::errors::MalwareBrotError;
+use crate::query_tools::KnnQueryHeap;
+use crate::*;
+use std::collections::HashMap;
+
+/// A KNN graph in CSR form. The neighbors of the point `point_indexes[i]` are
+/// `neighbors[offsets[i]..offsets[i+1]]`, with distances `distances[offsets[i]..offsets[i+1]]`, closest first.
+/// The neighbors are positions in `point_indexes`, not point indexes, so this can go straight into a sparse matrix.
+#[derive(Debug, Clone)]
+pub struct KnnGraph {
+    /// The point index of each row
+    pub point_indexes: Vec<PointIndex>,
+    /// The start of each row in `neighbors` and `distances`, with the total number of edges at the end
+    pub offsets: Vec<usize>,
+    /// The rows of the neighbors
+    pub neighbors: Vec<usize>,
+    /// The distances to the neighbors
+    pub distances: Vec<f32>,
+}
+
+impl KnnGraph {
+    /// Builds the KNN graph of all the points in the tree. A point is not its own neighbor.
+    /// This runs in parallel, one chunk of points per rayon thread.
+    pub fn build<M: Metric>(reader: &CoverTreeReader<M>, k: usize) -> MalwareBrotResult<KnnGraph> {
+        let point_indexes = reader.point_indexes();
+        let home_addresses = home_addresses(reader);
+        let rows: HashMap<PointIndex, usize> = point_indexes
+            .iter()
+            .enumerate()
+            .map(|(row, pi)| (*pi, row))
+            .collect();
+
+        let count = point_indexes.len();
+        let k = k.min(count.saturating_sub(1));
+        let mut distances = vec![0.0; count * k];
+        let mut neighbors = vec![0; count * k];
+        let mut lens = vec![0; count];
+        if count == 0 || k == 0 {
+            return Ok(KnnGraph {
+                point_indexes,
+                offsets: vec![0; count + 1],
+                neighbors,
+                distances,
+            });
+        }
+
+        let chunk_len = (count + rayon::current_num_threads() - 1) / rayon::current_num_threads();
+        let readers: Vec<CoverTreeReader<M>> = (0..((count + chunk_len - 1) / chunk_len))
+            .map(|_| reader.reader())
+            .collect();
+        readers
+            .into_par_iter()
+            .zip(point_indexes.par_chunks(chunk_len))
+            .zip(distances.par_chunks_mut(chunk_len * k))
+            .zip(neighbors.par_chunks_mut(chunk_len * k))
+            .zip(lens.par_chunks_mut(chunk_len))
+            .try_for_each(
+                |((((reader, chunk_indexes), chunk_dists), chunk_nbrs), chunk_lens)| {
+                    let mut query_heap = KnnQueryHeap::new(k + 1, reader.parameters().scale_base);
+                    let mut nbrs = vec![0; k + 1];
+                    let mut dists = vec![0.0; k + 1];
+                    let row_iter = chunk_indexes
+                        .iter()
+                        .zip(chunk_dists.chunks_mut(k))
+                        .zip(chunk_nbrs.chunks_mut(k))
+                        .zip(chunk_lens.iter_mut());
+                    for (((pi, row_dists), row_nbrs), row_len) in row_iter {
+                        let point = reader.point_cloud().get_point(*pi)?;
+                        reader.consistent(|root_address| {
+                            query_heap.reset(k + 1);
+                            reader.knn_from_with_heap(
+                                root_address,
+                                point,
+                                home_addresses.get(pi).cloned(),
+                                &mut query_heap,
+                            )
+                        })?;
+                        query_heap.unpack_into(&mut dists, &mut nbrs);
+                        let found = nbrs
+                            .iter()
+                            .zip(&dists)
+                            .filter(|(npi, _d)| *npi != pi && rows.contains_key(npi))
+                            .take(k);
+                        for ((npi, d), (row_d, row_n)) in
+                            found.zip(row_dists.iter_mut().zip(row_nbrs.iter_mut()))
+                        {
+                            *row_d = *d;
+                            *row_n = rows[npi];
+                            *row_len += 1;
+                        }
+                    }
+                    Ok::<(), MalwareBrotError>(())
+                },
+            )?;
+
+        // Squeeze out the padding of rows that didn't fill up.
+        let mut offsets = Vec::with_capacity(count + 1);
+        offsets.push(0);
+        let mut edge_count = 0;
+        for (row, row_len) in lens.iter().enumerate() {
+            for j in 0..*row_len {
+                distances[edge_count] = distances[row * k + j];
+                neighbors[edge_count] = neighbors[row * k + j];
+                edge_count += 1;
+            }
+            offsets.push(edge_count);
+        }
+        distances.truncate(edge_count);
+        neighbors.truncate(edge_count);
+
+        Ok(KnnGraph {
+            point_indexes,
+            offsets,
+            neighbors,
+            distances,
+        })
+    }
+
+    /// The number of points (rows) in the graph
+    pub fn len(&self) -> usize {
+        self.point_indexes.len()
+    }
+
+    /// The number of directed edges in the graph
+    pub fn edge_count(&self) -> usize {
+        self.neighbors.len()
+    }
+
+    /// The neighbors and distances of a row, closest first.
+    pub fn row(&self, row: usize) -> (&[usize], &[f32]) {
+        let range = self.offsets[row]..self.offsets[row + 1];
+        (&self.neighbors[range.clone()], &self.distances[range])
+    }
+
+    /// The symmetrised graph, `j` is a neighbor of `i` if `i` is in the KNN of `j` or `j` is in the KNN of `i`.
+    /// Rows can be longer than `k` after this, they're still sorted closest first.
+    pub fn symmetrize(&self) -> KnnGraph {
+        let mut edges: Vec<Vec<(f32, usize)>> = vec![Vec::new(); self.len()];
+        for i in 0..self.len() {
+            let (nbrs, dists) = self.row(i);
+            for (j, d) in nbrs.iter().zip(dists) {
+                edges[i].push((*d, *j));
+                edges[*j].push((*d, i));
+            }
+        }
+
+        let mut offsets = Vec::with_capacity(self.len() + 1);
+        let mut neighbors = Vec::with_capacity(2 * self.edge_count());
+        let mut distances = Vec::with_capacity(2 * self.edge_count());
+        offsets.push(0);
+        for mut row in edges {
+            row.sort_by_key(|(_d, j)| *j);
+            row.dedup_by_key(|(_d, j)| *j);
+            row.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            for (d, j) in row {
+                distances.push(d);
+                neighbors.push(j);
+            }
+            offsets.push(neighbors.len());
+        }
+
+        KnnGraph {
+            point_indexes: self.point_indexes.clone(),
+            offsets,
+            neighbors,
+            distances,
+        }
+    }
+}
+
+/// Finds the lowest node that each point is a center or a singleton of.
+fn home_addresses<M: Metric>(reader: &CoverTreeReader<M>) -> HashMap<PointIndex, NodeAddress> {
+    reader.consistent(|_root_address| {
+        let mut homes: HashMap<PointIndex, NodeAddress> = HashMap::new();
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|pi, n| {
+                let address = (*n.scale_index(), *pi);
+                let home = homes.entry(*pi).or_insert(address);
+                if address.0 < home.0 {
+                    *home = address;
+                }
+                for singleton in n.singletons() {
+                    homes.insert(*singleton, address);
+                }
+            });
+        }
+        homes
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::tests::random_tree;
+
+    #[test]
+    fn knn_graph_matches_knn() {
+        let count = 300;
+        let tree = random_tree(count, 0);
+        let reader = tree.reader();
+
+        let k = 4;
+        let graph = KnnGraph::build(&reader, k).unwrap();
+        assert!(graph.len() == count);
+        assert!(graph.edge_count() == count * k);
+        for i in 0..graph.len() {
+            let pi = graph.point_indexes[i];
+            let expected = reader.knn_index(pi, k, true).unwrap();
+            let (nbrs, dists) = graph.row(i);
+            for ((j, d), (true_d, _)) in nbrs.iter().zip(dists).zip(&expected) {
+                assert!(*j != i);
+                assert_approx_eq!(*d, *true_d);
+            }
+        }
+
+        let sym = graph.symmetrize();
+        assert!(sym.edge_count() >= graph.edge_count());
+        for i in 0..sym.len() {
+            let (nbrs, dists) = sym.row(i);
+            for w in dists.windows(2) {
+                assert!(w[0] <= w[1]);
+            }
+            for j in nbrs {
+                assert!(sym.row(*j).0.contains(&i));
+            }
+        }
+    }
+}
@@ -28,8 +28,8 @@
 //! Writes to the tree are written to each layer and then each layer is refreshed. You should refrain from refreshing 
 //! single layers and try to handle all write operations as a tree level function. 
 //! 
-//! There is also an experimental pair of cluster hashmaps, which need to be replaced by a data structure that
-//! respects and represents the nerve more. 
+//! There is also a pair of cluster hashmaps, filled in by `CoverTreeWriter::cluster`. Each cluster is a connected
+//! component of overlapping node balls on the layer, see `CoverCluster`.
 
 use crate::evmap::monomap::{MonoReadHandle,MonoWriteHandle};
 use pointcloud::*;
@@ -38,7 +38,6 @@ use pointcloud::*;
 use super::*;
 use tree_file_format::*;
 use node::*;
-use pointcloud::utils::AdjMatrix;
 use std::iter::FromIterator;
 use std::sync::{atomic, Arc};
 
@@ -49,7 +48,6 @@ pub struct CoverLayerReader {
     scale_index: i32,
     node_reader: MonoReadHandle<PointIndex, CoverNode>,
     cluster_reader: MonoReadHandle<usize, CoverCluster>,
-    cluster_index: Arc<atomic::AtomicUsize>,
 }
 
 impl CoverLayerReader {
@@ -89,15 +87,23 @@ impl CoverLayerReader {
             .flatten()
     }
 
-    #[doc(hidden)]
-    pub fn get_cluster_and<F, T>(&self, pi: &usize, f: F) -> Option<T>
+    /// Read only access to a single cluster, the clusters are only there after `CoverTreeWriter::cluster` is run.
+    pub fn get_cluster_and<F, T>(&self, id: &usize, f: F) -> Option<T>
     where
         F: FnOnce(&CoverCluster) -> T,
     {
-        self.cluster_reader.get_and(pi, |n| f(n))
+        self.cluster_reader.get_and(id, |c| f(c))
+    }
+
+    /// Read only access to all clusters on the layer.
+    pub fn for_each_cluster<F>(&self, f: F)
+    where
+        F: FnMut(&usize, &CoverCluster),
+    {
+        self.cluster_reader.for_each(f)
     }
 
-    #[doc(hidden)]
+    /// Total number of clusters on this layer
     pub fn cluster_count(&self) -> usize {
         self.cluster_reader.len()
     }
@@ -118,63 +124,9 @@ impl CoverLayerReader {
             scale_index: self.scale_index,
             node_reader: self.node_reader.factory().handle(),
             cluster_reader: self.cluster_reader.factory().handle(),
-            cluster_index: Arc::clone(&self.cluster_index),
-        }
-    }
-
-    #[doc(hidden)]
-    pub fn get_components<M: Metric>(
-        &self,
-        mut unclustered: Vec<PointIndex>,
-        point_cloud: &PointCloud<M>,
-    ) -> MalwareBrotResult<Vec<(usize, Vec<PointIndex>)>> {
-        if unclustered.len() == 1 {
-            //println!("\t\t Singleton with {:?}", unclustered);
-            let id = self.cluster_index.fetch_add(1, atomic::Ordering::SeqCst);
-            Ok(vec![(id, unclustered)])
-        } else {
-            let mut new_clusters = Vec::new();
-            let adj = point_cloud.adj(&unclustered)?;
-            while let Some(i) = unclustered.pop() {
-                //println!("\t\t\t Building cluster around {:?}", i);
-                let id = self.cluster_index.fetch_add(1, atomic::Ordering::SeqCst);
-                let indexes = self.get_components_rec(&i, &mut unclustered, &adj);
-                new_clusters.push((id, indexes));
-            }
-            Ok(new_clusters)
         }
     }
 
-    fn get_components_rec(
-        &self,
-        center: &PointIndex,
-        unclustered: &mut Vec<PointIndex>,
-        adj: &AdjMatrix,
-    ) -> Vec<PointIndex> {
-        let mut unvisited = vec![*center];
-        let mut component = vec![*center];
-
-        while let Some(i) = unvisited.pop() {
-            //println!("\t\t\t\t getting radius of {}",i);
-            let i_radius = self.node_reader.get_and(&i, |n| n.radius()).unwrap();
-            unclustered.retain(|j| {
-                //println!("\t\t\t\t\t getting radius of {}",j);
-                let j_radius = self.node_reader.get_and(&j, |n| n.radius()).unwrap();
-                if *adj.get(i, *j).unwrap() < i_radius + j_radius {
-                    //println!("\t\t\t\t\t {} and {} have radii {} and {} and are dist {}",i,j,i_radius,j_radius, *adj.get(i, *j).unwrap());
-                    component.push(*j);
-                    unvisited.push(*j);
-                    //println!("\t\t\t\t\t component: {:?}", component);
-                    //println!("\t\t\t\t\t unvisited: {:?}", unvisited);
-                    false
-                } else {
-                    true
-                }
-            });
-        }
-        component
-    }
-
     /// Checks if this is a true cover tree layer.
     pub fn brute_check_seperability(
         &self,
@@ -202,13 +154,51 @@ impl CoverLayerReader {
 }
 
 
-#[doc(hidden)]
-#[derive(Clone)]
+/// A connected component of the nodes at one scale index, where two nodes are connected if their balls overlap, that
+/// is the distance between their centers is less than the sum of their radii. The children of the nodes in a cluster
+/// are split into the clusters in `children_ids`, so the clusters form a tree that follows the cover tree down.
+#[derive(Debug, Clone, PartialEq)]
 pub struct CoverCluster {
+    /// The center indexes of the nodes in the cluster, sorted
     pub indexes: Vec<PointIndex>,
+    /// The clusters the children of these nodes were split into. These can be on any lower layer.
     pub children_ids: Vec<ClusterAddress>,
 }
 
+impl CoverCluster {
+    pub(crate) fn load(cluster_proto: &ClusterProto) -> CoverCluster {
+        let indexes = cluster_proto
+            .get_indexes()
+            .iter()
+            .map(|pi| *pi as PointIndex)
+            .collect();
+        let children_ids = cluster_proto
+            .get_children_scale_indexes()
+            .iter()
+            .zip(cluster_proto.get_children_cluster_ids())
+            .map(|(si, id)| (*si, *id as usize))
+            .collect();
+        CoverCluster {
+            indexes,
+            children_ids,
+        }
+    }
+
+    pub(crate) fn save(&self, id: usize) -> ClusterProto {
+        let mut proto = ClusterProto::new();
+        proto.set_id(id as u64);
+        proto.set_indexes(self.indexes.iter().map(|pi| *pi as u64).collect());
+        proto.set_children_scale_indexes(self.children_ids.iter().map(|(si, _id)| *si).collect());
+        proto.set_children_cluster_ids(
+            self.children_ids
+                .iter()
+                .map(|(_si, id)| *id as u64)
+                .collect(),
+        );
+        proto
+    }
+}
+
 /// Primarily contains the node writer head, but also has the cluster writer head and the index head.
 pub(crate) struct CoverLayerWriter {
     scale_index: i32,
@@ -224,7 +214,6 @@ impl CoverLayerWriter {
             scale_index: self.scale_index,
             node_reader: self.node_writer.factory().handle(),
             cluster_reader: self.cluster_writer.factory().handle(),
-            cluster_index: Arc::clone(&self.cluster_index),
         }
     }
 
@@ -240,27 +229,42 @@ impl CoverLayerWriter {
         }
     }
 
-    #[doc(hidden)]
     pub(crate) fn insert_cluster(&mut self, index: usize, cluster: CoverCluster) {
         self.cluster_writer.insert(index, cluster);
     }
 
+    /// Hands out the next unused cluster id on this layer.
+    pub(crate) fn next_cluster_id(&self) -> usize {
+        self.cluster_index.fetch_add(1, atomic::Ordering::SeqCst)
+    }
+
+    /// Removes all clusters and restarts the ids, the layer appears unclustered after the next refresh.
+    pub(crate) fn clear_clusters(&mut self) {
+        self.cluster_writer.purge();
+        self.cluster_index.store(0, atomic::Ordering::SeqCst);
+    }
+
 
     pub(crate) fn load(layer_proto: &LayerProto) -> CoverLayerWriter {
         let scale_index = layer_proto.get_scale_index();
         let (_node_reader, mut node_writer) = evmap::monomap::new();
-        let (_cluster_reader, cluster_writer) = evmap::monomap::new::<usize, CoverCluster>();
+        let (_cluster_reader, mut cluster_writer) = evmap::monomap::new::<usize, CoverCluster>();
         for node_proto in layer_proto.get_nodes() {
             let index = node_proto.get_center_index() as PointIndex;
             let node = CoverNode::load(scale_index, node_proto);
             node_writer.insert(index, node);
         }
+        for cluster_proto in layer_proto.get_clusters() {
+            cluster_writer.insert(cluster_proto.get_id() as usize, CoverCluster::load(cluster_proto));
+        }
         node_writer.refresh();
+        cluster_writer.refresh();
+        let cluster_index = layer_proto.get_cluster_index() as usize;
         CoverLayerWriter {
             scale_index,
             cluster_writer,
             node_writer,
-            cluster_index: Arc::new(atomic::AtomicUsize::new(0)),
+            cluster_index: Arc::new(atomic::AtomicUsize::new(cluster_index)),
         }
     }
 
@@ -270,15 +274,36 @@ impl CoverLayerWriter {
         self.node_writer.for_each(|_pi, node| {
             node_protos.push(node.save());
         });
+        // The map's iteration order changes from run to run, so sort the nodes to keep the file reproducible.
+        node_protos.sort_by_key(|n| n.get_center_index());
         layer_proto.set_nodes(node_protos);
+        let mut cluster_protos = layer_proto.take_clusters();
+        self.cluster_writer.for_each(|id, cluster| {
+            cluster_protos.push(cluster.save(*id));
+        });
+        cluster_protos.sort_by_key(|c| c.get_id());
+        layer_proto.set_clusters(cluster_protos);
+        layer_proto.set_cluster_index(self.cluster_index.load(atomic::Ordering::SeqCst) as u64);
         layer_proto.set_scale_index(self.scale_index);
         layer_proto
     }
 
+    /// Read only access to a single node, as of the last refresh.
+    pub(crate) fn get_node_and<F, T>(&self, pi: &PointIndex, f: F) -> Option<T>
+    where
+        F: FnOnce(&CoverNode) -> T,
+    {
+        self.node_writer.get_and(pi, |n| f(n))
+    }
+
     pub(crate) fn insert_raw(&mut self, index: PointIndex, node: CoverNode) {
         self.node_writer.insert(index, node);
     }
 
+    pub(crate) fn remove_raw(&mut self, index: PointIndex) {
+        self.node_writer.remove(index);
+    }
+
     pub(crate) fn refresh(&mut self) {
         self.node_writer.refresh();
         self.cluster_writer.refresh();
@@ -43,6 +43,9 @@
 //! This is mainly to stop before floating point errors become an issue. Try to choose it to result in a cutoff of about
 //! 2^-9. 
 //!
+//! If you'd rather not guess, the `tuning::ParameterTuner` samples your point cloud and proposes all 3 for a memory
+//! budget or a query latency target.
+//!
 //! See the git readme for a description of the algo.
 //!
 
@@ -63,14 +66,23 @@ pub(crate) mod evmap;
 
 mod tree_file_format;
 mod builders;
+pub mod classifier;
 mod data_caches;
+pub mod dual_tree;
+pub mod knn_graph;
 pub mod layer;
+pub mod linkage;
 pub mod node;
 pub mod query_tools;
 mod tree;
+pub mod tuning;
 pub mod utils;
+pub mod validation;
+pub mod window;
 
-pub use builders::CoverTreeBuilder;
+pub use builders::{
+    BuildObserver, CancellationToken, CenterSelection, CoverTreeBuilder, ProgressPrinter, SilentObserver,
+};
 pub use tree::*;
 
 /// The data structure explicitly seperates the covertree by layer, and the addressing schema for nodes 
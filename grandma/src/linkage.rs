@@ -0,0 +1,354 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Linkage Matrices
+//! Exports the tree as a dendrogram in the linkage matrix format scipy uses, so it can be cut into flat clusters at any
+//! scale, plotted, or handed to `scipy.cluster.hierarchy`.
+//!
+//! The observations are the points in the tree, in the order of `LinkageMatrix::point_indexes`. A node at scale index
+//! `i` merges its center, its singletons and the subtrees of its children at height `b^i`. The children are at lower
+//! scale indexes, so the heights are monotone. There are two hierarchies that can be exported:
+//! * `LinkageMatrix::from_nodes` follows the nodes, each node is a merge.
+//! * `LinkageMatrix::from_clusters` follows the layer clusters made by `CoverTreeWriter::cluster`, each cluster is a
+//!   merge. Nodes with overlapping balls are merged at their own scale, instead of at their parent's.
+//!
+//! A merge of more than 2 things is written as a chain of pairwise merges at the same height.
+
+use crate::errors::MalwareBrotError;
+use crate::*;
+use std::collections::HashMap;
+
+/// A row of the linkage matrix, the merge of two earlier clusters. Like scipy, the ids below the number of observations
+/// are the observations themselves and the id `n + j` is the cluster made by row `j`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkageRow {
+    /// The id of the first cluster merged
+    pub first: usize,
+    /// The id of the second cluster merged
+    pub second: usize,
+    /// The height of the merge, `b^i` for the scale index `i` where they merge
+    pub height: f32,
+    /// The number of observations in the merged cluster
+    pub count: usize,
+}
+
+/// A dendrogram of the points in a tree, see the module docs.
+#[derive(Debug, Clone)]
+pub struct LinkageMatrix {
+    scale_base: f32,
+    point_indexes: Vec<PointIndex>,
+    rows: Vec<LinkageRow>,
+}
+
+impl LinkageMatrix {
+    /// The dendrogram of the node hierarchy.
+    pub fn from_nodes<M: Metric>(reader: &CoverTreeReader<M>) -> MalwareBrotResult<LinkageMatrix> {
+        reader.consistent(|root_address| {
+            let mut linker = Linker::new(reader);
+            linker.link_node(root_address)?;
+            Ok(linker.finish())
+        })
+    }
+
+    /// The dendrogram of the layer clusters. The tree has to have been clustered with `CoverTreeWriter::cluster`,
+    /// otherwise this is a `ClusterNotInTree` error.
+    pub fn from_clusters<M: Metric>(reader: &CoverTreeReader<M>) -> MalwareBrotResult<LinkageMatrix> {
+        reader.consistent(|root_address| {
+            let root_cluster = (root_address.0, 0);
+            let mut linker = Linker::new(reader);
+            linker.link_cluster(root_cluster)?;
+            Ok(linker.finish())
+        })
+    }
+
+    /// The point behind each observation, sorted.
+    pub fn point_indexes(&self) -> &[PointIndex] {
+        &self.point_indexes
+    }
+
+    /// The merges, one less than the number of observations.
+    pub fn rows(&self) -> &[LinkageRow] {
+        &self.rows
+    }
+
+    /// The matrix in scipy's layout, each row is `[first, second, height, count]`.
+    pub fn to_array(&self) -> Vec<[f64; 4]> {
+        self.rows
+            .iter()
+            .map(|r| [r.first as f64, r.second as f64, r.height as f64, r.count as f64])
+            .collect()
+    }
+
+    /// Cuts the dendrogram at the height `b^scale_index`, like scipy's `fcluster` with the `distance` criterion. Every
+    /// point gets the id of its flat cluster, and the points in a flat cluster are all below a node, or cluster, at that
+    /// scale index or lower. The ids count up from 0 in the order of the smallest point index in each flat cluster.
+    pub fn flat_clusters(&self, scale_index: i32) -> HashMap<PointIndex, usize> {
+        let height = self.scale_base.powi(scale_index);
+        let count = self.point_indexes.len();
+        let mut parents: Vec<usize> = (0..count).collect();
+        // A representative observation for every cluster id, so that rows can be merged by their observations.
+        let mut representatives: Vec<usize> = (0..count).collect();
+        for row in &self.rows {
+            let first = representatives[row.first];
+            if row.height <= height {
+                let second = find(&mut parents, representatives[row.second]);
+                let first_root = find(&mut parents, first);
+                parents[second.max(first_root)] = second.min(first_root);
+            }
+            representatives.push(first);
+        }
+
+        let mut ids: HashMap<usize, usize> = HashMap::new();
+        let mut flat_clusters = HashMap::with_capacity(count);
+        for (observation, pi) in self.point_indexes.iter().enumerate() {
+            let root = find(&mut parents, observation);
+            let next_id = ids.len();
+            let id = *ids.entry(root).or_insert(next_id);
+            flat_clusters.insert(*pi, id);
+        }
+        flat_clusters
+    }
+}
+
+/// The root of an observation in the union find, flattening the path on the way.
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/// Builds the rows with a walk down the tree. Each walk returns the id and size of the subtree it merged.
+struct Linker<'a, M: Metric> {
+    reader: &'a CoverTreeReader<M>,
+    observations: HashMap<PointIndex, usize>,
+    point_indexes: Vec<PointIndex>,
+    rows: Vec<LinkageRow>,
+}
+
+impl<'a, M: Metric> Linker<'a, M> {
+    fn new(reader: &'a CoverTreeReader<M>) -> Linker<'a, M> {
+        let point_indexes = reader.point_indexes();
+        let observations = point_indexes
+            .iter()
+            .enumerate()
+            .map(|(i, pi)| (*pi, i))
+            .collect();
+        let rows = Vec::with_capacity(point_indexes.len().saturating_sub(1));
+        Linker {
+            reader,
+            observations,
+            point_indexes,
+            rows,
+        }
+    }
+
+    fn finish(self) -> LinkageMatrix {
+        LinkageMatrix {
+            scale_base: self.reader.parameters().scale_base,
+            point_indexes: self.point_indexes,
+            rows: self.rows,
+        }
+    }
+
+    fn observation(&self, pi: PointIndex) -> (usize, usize) {
+        (self.observations[&pi], 1)
+    }
+
+    fn link_node(&mut self, address: NodeAddress) -> MalwareBrotResult<(usize, usize)> {
+        let (children, singletons) = self
+            .reader
+            .get_node_and(address, |n| {
+                let children = n.children().map(|(nested_si, addresses)| {
+                    let mut children = vec![(nested_si, address.1)];
+                    children.extend_from_slice(addresses);
+                    children
+                });
+                (children, n.singletons().to_vec())
+            })
+            .ok_or(MalwareBrotError::NodeNotInTree(address))?;
+
+        let mut merged = Vec::with_capacity(singletons.len() + 1);
+        match children {
+            Some(children) => {
+                for child in children {
+                    merged.push(self.link_node(child)?);
+                }
+            }
+            None => merged.push(self.observation(address.1)),
+        }
+        merged.extend(singletons.into_iter().map(|pi| self.observation(pi)));
+        Ok(self.merge(merged, address.0))
+    }
+
+    fn link_cluster(&mut self, address: ClusterAddress) -> MalwareBrotResult<(usize, usize)> {
+        let (indexes, children_ids) = self
+            .reader
+            .get_cluster_and(address, |c| (c.indexes.clone(), c.children_ids.clone()))
+            .ok_or(MalwareBrotError::ClusterNotInTree(address))?;
+
+        let mut merged = Vec::with_capacity(children_ids.len());
+        for child in children_ids {
+            merged.push(self.link_cluster(child)?);
+        }
+        // The centers of routing nodes are below their nested child, so only the leaves' centers are left.
+        for pi in indexes {
+            let (is_leaf, singletons) = self
+                .reader
+                .get_node_and((address.0, pi), |n| (n.is_leaf(), n.singletons().to_vec()))
+                .ok_or(MalwareBrotError::NodeNotInTree((address.0, pi)))?;
+            if is_leaf {
+                merged.push(self.observation(pi));
+            }
+            merged.extend(singletons.into_iter().map(|pi| self.observation(pi)));
+        }
+        Ok(self.merge(merged, address.0))
+    }
+
+    /// Chains the merges of a node or cluster at its scale.
+    fn merge(&mut self, merged: Vec<(usize, usize)>, scale_index: i32) -> (usize, usize) {
+        let height = self.reader.scale(scale_index);
+        let mut merged = merged.into_iter();
+        let mut current = merged.next().expect("a node covers at least its center");
+        for (id, count) in merged {
+            let row = LinkageRow {
+                first: current.0,
+                second: id,
+                height,
+                count: current.1 + count,
+            };
+            self.rows.push(row);
+            current = (self.point_indexes.len() + self.rows.len() - 1, row.count);
+        }
+        current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::tests::random_tree;
+
+    /// Checks the rows are a valid scipy linkage: each id is used once and only after it's made, the counts add up,
+    /// and the heights don't decrease going up.
+    fn assert_valid_linkage(linkage: &LinkageMatrix) {
+        let n = linkage.point_indexes().len();
+        assert!(linkage.rows().len() == n - 1);
+        let mut counts: Vec<usize> = vec![1; n];
+        let mut heights: Vec<f32> = vec![0.0; n];
+        let mut used = vec![false; 2 * n - 1];
+        for (j, row) in linkage.rows().iter().enumerate() {
+            for id in &[row.first, row.second] {
+                assert!(*id < n + j);
+                assert!(!used[*id]);
+                used[*id] = true;
+                assert!(heights[*id] <= row.height);
+            }
+            assert!(row.count == counts[row.first] + counts[row.second]);
+            counts.push(row.count);
+            heights.push(row.height);
+        }
+        assert!(counts[2 * n - 2] == n);
+    }
+
+    #[test]
+    fn linkage_cuts_follow_the_tree() {
+        let mut tree = random_tree(400, 0);
+        let reader = tree.reader();
+        assert!(LinkageMatrix::from_clusters(&reader).is_err());
+
+        let linkage = LinkageMatrix::from_nodes(&reader).unwrap();
+        assert_valid_linkage(&linkage);
+        let root_scale = reader.root_address().0;
+        let top = linkage.flat_clusters(root_scale);
+        assert!(top.len() == 400);
+        assert!(top.values().all(|id| *id == 0));
+        let bottom = linkage.flat_clusters(reader.parameters().resolution - 100);
+        let mut bottom_ids: Vec<usize> = bottom.values().cloned().collect();
+        bottom_ids.sort();
+        assert!(bottom_ids == (0..400).collect::<Vec<usize>>());
+
+        // A cut is the subtrees of the nodes at or below the scale, with everything above it on its own.
+        for si in reader.parameters().resolution..root_scale {
+            let mut expected = Vec::new();
+            let mut to_visit = vec![reader.root_address()];
+            while let Some(address) = to_visit.pop() {
+                if address.0 <= si {
+                    let mut below = Vec::new();
+                    let mut subtree = vec![address];
+                    while let Some(a) = subtree.pop() {
+                        reader.get_node_and(a, |n| {
+                            below.extend_from_slice(n.singletons());
+                            match n.children() {
+                                Some((nested_si, children)) => {
+                                    subtree.push((nested_si, a.1));
+                                    subtree.extend_from_slice(children);
+                                }
+                                None => below.push(a.1),
+                            }
+                        });
+                    }
+                    expected.push(below);
+                    continue;
+                }
+                reader.get_node_and(address, |n| {
+                    expected.extend(n.singletons().iter().map(|pi| vec![*pi]));
+                    match n.children() {
+                        Some((nested_si, children)) => {
+                            to_visit.push((nested_si, address.1));
+                            to_visit.extend_from_slice(children);
+                        }
+                        None => expected.push(vec![address.1]),
+                    }
+                });
+            }
+            let cut = linkage.flat_clusters(si);
+            let mut found: Vec<Vec<PointIndex>> = vec![Vec::new(); cut.values().max().unwrap() + 1];
+            for (pi, id) in &cut {
+                found[*id].push(*pi);
+            }
+            for group in expected.iter_mut().chain(found.iter_mut()) {
+                group.sort();
+            }
+            expected.sort();
+            found.sort();
+            assert!(expected == found);
+        }
+
+        tree.cluster().unwrap();
+        let reader = tree.reader();
+        let clustered = LinkageMatrix::from_clusters(&reader).unwrap();
+        assert_valid_linkage(&clustered);
+        assert!(clustered.point_indexes() == linkage.point_indexes());
+        // Clusters merge nodes at their own scale, so the cuts are coarser than the node cuts.
+        for si in reader.parameters().resolution..=root_scale {
+            let node_cut = linkage.flat_clusters(si);
+            let cluster_cut = clustered.flat_clusters(si);
+            let node_count = node_cut.values().max().unwrap() + 1;
+            let cluster_count = cluster_cut.values().max().unwrap() + 1;
+            assert!(cluster_count <= node_count);
+            let mut same_cluster: HashMap<usize, usize> = HashMap::new();
+            for (pi, id) in &node_cut {
+                let cluster_id = cluster_cut[pi];
+                assert!(*same_cluster.entry(*id).or_insert(cluster_id) == cluster_id);
+            }
+        }
+    }
+}
@@ -22,11 +22,12 @@
 //! 
 use crate::errors::{MalwareBrotError, MalwareBrotResult};
 use crate::tree_file_format::*;
-use crate::query_tools::KnnQueryHeap;
+use crate::query_tools::{KnnQueryHeap, LazyKnnHeap, MetadataFilter, RangeQueryHeap};
 use crate::NodeAddress;
 use pointcloud::labels::MetaSummary;
 use pointcloud::*;
 use smallvec::SmallVec;
+use std::collections::HashMap;
 
 /// The node children. This is a separate struct from the `CoverNode` to use the rust compile time type checking and 
 /// `Option` data structure to ensure that all nodes with children are valid and cover their nested child.
@@ -37,7 +38,7 @@ pub(crate) struct NodeChildren {
 }
 
 /// The actual cover node. The fields can be separated into three piles. The first two consist of node `address` for testing and reference
-/// when working and the `radius`, `cover_count`, and `singles_summary` for a query various properties of the node.
+/// when working and the `radius`, `cover_count`, `singles_summary`, and `subtree_summary` for a query various properties of the node.
 /// Finally we have the children and singleton pile. The singletons are saved in a `SmallVec` directly attached to the node. This saves a
 /// memory redirect for the first 20 singleton children. The children are saved in a separate struct also consisting of a `SmallVec`
 /// (though, this is only 10 wide before we allocate on the heap), and the scale index of the nested child.
@@ -49,6 +50,7 @@ pub struct CoverNode {
     radius: f32,
     cover_count: usize,
     singles_summary: Option<MetaSummary>,
+    subtree_summary: Option<MetaSummary>,
     /// Children
     children: Option<NodeChildren>,
     singles_indexes: SmallVec<[PointIndex; 20]>,
@@ -64,6 +66,7 @@ impl CoverNode {
             children: None,
             singles_indexes: SmallVec::new(),
             singles_summary: None,
+            subtree_summary: None,
         }
     }
 
@@ -77,6 +80,22 @@ impl CoverNode {
         self.radius
     }
 
+    /// The number of points this node covers
+    pub fn cover_count(&self) -> usize {
+        self.cover_count
+    }
+
+    /// The summary of the metadata of the singletons of this node
+    pub fn singles_summary(&self) -> Option<&MetaSummary> {
+        self.singles_summary.as_ref()
+    }
+
+    /// The summary of the metadata of every point this node covers, the center, the singletons and all the descendants.
+    /// This is `None` if it hasn't been computed, see `CoverTreeWriter::summarize_subtrees`.
+    pub fn subtree_summary(&self) -> Option<&MetaSummary> {
+        self.subtree_summary.as_ref()
+    }
+
     /// Add a nested child and converts the node from a leaf to a routing node.
     /// Throws an error if the node is already a routing node with a nested node.
     pub fn insert_nested_child(
@@ -195,6 +214,165 @@ impl CoverNode {
         Ok(())
     }
 
+    /// Performs a brute force knn against the singletons that pass the filter. If the singletons' summary shows that none of
+    /// them can pass we don't touch them at all.
+    pub fn filtered_singleton_knn<M: Metric, F: MetadataFilter>(
+        &self,
+        point: &[f32],
+        point_cloud: &PointCloud<M>,
+        filter: &F,
+        query_heap: &mut KnnQueryHeap,
+    ) -> MalwareBrotResult<()> {
+        if let Some(summary) = &self.singles_summary {
+            if !filter.could_match(summary) {
+                return Ok(());
+            }
+        }
+        let mut passing = Vec::with_capacity(self.singles_indexes.len());
+        for pi in self.singles_indexes.iter() {
+            if filter.matches(&point_cloud.get_metadata(*pi)?) {
+                passing.push(*pi);
+            }
+        }
+        let distances = point_cloud.distances_to_point(point, &passing[..])?;
+        query_heap.push_outliers(&passing[..], &distances[..]);
+        Ok(())
+    }
+
+    /// Performs a brute force knn against the children of the node, only the children's centers that pass the filter are
+    /// candidates for the KNN. Children whose subtree can't contain a passing point are skipped entirely, pass one flag per
+    /// child in `subtrees_could_match`, in the order of `children` with the nested child first. Does nothing if this is a
+    /// leaf node. If you have the distance from the query point to this you can pass it to save a distance calculation.
+    pub fn filtered_child_knn<M: Metric, F: MetadataFilter>(
+        &self,
+        dist_to_center: Option<f32>,
+        point: &[f32],
+        point_cloud: &PointCloud<M>,
+        filter: &F,
+        subtrees_could_match: &[bool],
+        query_heap: &mut KnnQueryHeap,
+    ) -> MalwareBrotResult<()> {
+        let dist_to_center = match dist_to_center {
+            Some(d) => d,
+            None => point_cloud.distances_to_point(point, &[self.address.1])?[0],
+        };
+
+        if let Some(children) = &self.children {
+            if subtrees_could_match.get(0).cloned().unwrap_or(true) {
+                query_heap.push_node_addresses(
+                    &[(children.nested_scale, self.address.1)],
+                    &[dist_to_center],
+                    None,
+                );
+            }
+            let children_addresses: Vec<NodeAddress> = children
+                .addresses
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| subtrees_could_match.get(i + 1).cloned().unwrap_or(true))
+                .map(|(_, address)| *address)
+                .collect();
+            let children_indexes: Vec<PointIndex> =
+                children_addresses.iter().map(|(_si, pi)| *pi).collect();
+            let distances = point_cloud.distances_to_point(point, &children_indexes[..])?;
+            query_heap.push_node_addresses(&children_addresses[..], &distances, Some(self.address));
+            for (pi, d) in children_indexes.iter().zip(&distances) {
+                if filter.matches(&point_cloud.get_metadata(*pi)?) {
+                    query_heap.push_outliers(&[*pi], &[*d]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands this node for a lazy nearest neighbor query, all the children go onto the node heap and the singletons go onto
+    /// the point heap. If you have the distance from the query point to this you can pass it to save a distance calculation.
+    pub fn lazy_knn<M: Metric>(
+        &self,
+        dist_to_center: Option<f32>,
+        point: &[f32],
+        point_cloud: &PointCloud<M>,
+        query_heap: &mut LazyKnnHeap,
+    ) -> MalwareBrotResult<()> {
+        let distances = point_cloud.distances_to_point(point, &self.singles_indexes[..])?;
+        query_heap.push_outliers(&self.singles_indexes[..], &distances[..]);
+
+        if let Some(children) = &self.children {
+            let dist_to_center = match dist_to_center {
+                Some(d) => d,
+                None => point_cloud.distances_to_point(point, &[self.address.1])?[0],
+            };
+            query_heap.push_nodes(&[(children.nested_scale, self.address.1)], &[dist_to_center]);
+            let children_indexes: Vec<PointIndex> =
+                children.addresses.iter().map(|(_si, pi)| *pi).collect();
+            let distances = point_cloud.distances_to_point(point, &children_indexes[..])?;
+            query_heap.push_nodes(&children.addresses[..], &distances);
+        }
+        Ok(())
+    }
+
+    /// Performs the `singleton_range` and `child_range` with a provided query heap, if this node could cover a point within the
+    /// query's radius. If you have the distance from the query point to this you can pass it to save a distance calculation.
+    pub fn range<M: Metric>(
+        &self,
+        dist_to_center: Option<f32>,
+        point: &[f32],
+        point_cloud: &PointCloud<M>,
+        query_heap: &mut RangeQueryHeap,
+    ) -> MalwareBrotResult<()> {
+        let dist_to_center = match dist_to_center {
+            Some(d) => d,
+            None => point_cloud.distances_to_point(point, &[self.address.1])?[0],
+        };
+        // Nodes that only cover their center have a radius of -inf
+        if dist_to_center - self.radius.max(0.0) > query_heap.radius() {
+            return Ok(());
+        }
+        self.singleton_range(point, point_cloud, query_heap)?;
+        self.child_range(Some(dist_to_center), point, point_cloud, query_heap)?;
+
+        if self.children.is_none() {
+            query_heap.push_outliers(&[self.address.1], &[dist_to_center]);
+        }
+        Ok(())
+    }
+
+    /// Performs a brute force range query against just the singleton children with a provided query heap.
+    pub fn singleton_range<M: Metric>(
+        &self,
+        point: &[f32],
+        point_cloud: &PointCloud<M>,
+        query_heap: &mut RangeQueryHeap,
+    ) -> MalwareBrotResult<()> {
+        let distances = point_cloud.distances_to_point(point, &self.singles_indexes[..])?;
+        query_heap.push_outliers(&self.singles_indexes[..], &distances[..]);
+        Ok(())
+    }
+
+    /// Pushes the children of the node that could cover a point within the query's radius onto the range heap. Does nothing if this is a leaf node.
+    /// If you have the distance from the query point to this you can pass it to save a distance calculation.
+    pub fn child_range<M: Metric>(
+        &self,
+        dist_to_center: Option<f32>,
+        point: &[f32],
+        point_cloud: &PointCloud<M>,
+        query_heap: &mut RangeQueryHeap,
+    ) -> MalwareBrotResult<()> {
+        let dist_to_center = match dist_to_center {
+            Some(d) => d,
+            None => point_cloud.distances_to_point(point, &[self.address.1])?[0],
+        };
+
+        if let Some(children) = &self.children {
+            query_heap.push_nodes(&[(children.nested_scale, self.address.1)], &[dist_to_center]);
+            let children_indexes: Vec<PointIndex> =
+                children.addresses.iter().map(|(_si, pi)| *pi).collect();
+            let distances = point_cloud.distances_to_point(point, &children_indexes[..])?;
+            query_heap.push_nodes(&children.addresses[..], &distances);
+        }
+        Ok(())
+    }
+
     /// Inserts a routing child into the node. Make sure the child node is also in the tree or you get a dangling reference
     pub(crate) fn insert_child(&mut self, address: NodeAddress, coverage: usize) -> MalwareBrotResult<()> {
         self.cover_count += coverage;
@@ -206,6 +384,67 @@ impl CoverNode {
         }
     }
 
+    /// Removes a routing child from the node, with the number of points it covered. Returns false if it wasn't a child.
+    pub(crate) fn remove_child(&mut self, address: NodeAddress, coverage: usize) -> bool {
+        if let Some(children) = &mut self.children {
+            if let Some(i) = children.addresses.iter().position(|a| *a == address) {
+                children.addresses.remove(i);
+                self.cover_count = self.cover_count.saturating_sub(coverage);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The number of points a parent counts this node as covering, including its center.
+    pub(crate) fn coverage(&self) -> usize {
+        if self.is_leaf() {
+            self.singles_indexes.len() + 1
+        } else {
+            self.cover_count
+        }
+    }
+
+    /// A copy of the node at a new scale index, with its point indexes swapped by the map. For moving it into another tree.
+    pub(crate) fn remapped(
+        &self,
+        scale_index: i32,
+        point_map: &HashMap<PointIndex, PointIndex>,
+    ) -> CoverNode {
+        CoverNode {
+            address: (scale_index, point_map[&self.address.1]),
+            radius: self.radius,
+            cover_count: self.cover_count,
+            singles_summary: self.singles_summary.clone(),
+            subtree_summary: self.subtree_summary.clone(),
+            children: self.children.as_ref().map(|children| NodeChildren {
+                nested_scale: children.nested_scale,
+                addresses: children
+                    .addresses
+                    .iter()
+                    .map(|(si, pi)| (*si, point_map[pi]))
+                    .collect(),
+            }),
+            singles_indexes: self.singles_indexes.iter().map(|pi| point_map[pi]).collect(),
+        }
+    }
+
+    /// A copy of the node at a new scale index, for moving it within the tree.
+    pub(crate) fn rescaled(&self, scale_index: i32) -> CoverNode {
+        let mut node = self.clone();
+        node.address.0 = scale_index;
+        node
+    }
+
+    /// Takes over the children and singletons of the nested child, for when it's the only child left.
+    pub(crate) fn absorb_nested(&mut self, nested: CoverNode) {
+        self.cover_count = nested.cover_count;
+        self.children = nested.children;
+        self.singles_indexes = nested.singles_indexes;
+        self.singles_summary = nested.singles_summary;
+        self.subtree_summary = nested.subtree_summary;
+    }
+
     /// Inserts a `vec` of singleton children into the node.
     pub(crate) fn insert_singletons(&mut self, addresses: Vec<PointIndex>) {
         self.cover_count += addresses.len();
@@ -216,10 +455,25 @@ impl CoverNode {
         self.cover_count += 1;
         self.singles_indexes.push(address);
     }
+    /// Removes a singleton child from the node, returns false if it wasn't there.
+    pub(crate) fn remove_singleton(&mut self, address: PointIndex) -> bool {
+        match self.singles_indexes.iter().position(|pi| *pi == address) {
+            Some(i) => {
+                self.cover_count -= 1;
+                self.singles_indexes.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
     /// Updates the radius
     pub(crate) fn set_radius(&mut self, radius: f32) {
         self.radius = radius;
     }
+    /// Updates the cover count, for when a point is inserted into or removed from a descendant
+    pub(crate) fn set_cover_count(&mut self, cover_count: usize) {
+        self.cover_count = cover_count;
+    }
 
     /// Updates the metasummary of the singletons this covers. Call this after inserting or removing a singleton.
     pub(crate) fn update_metasummary<M: Metric>(
@@ -230,16 +484,45 @@ impl CoverNode {
         Ok(())
     }
 
+    /// Recomputes the summary of everything this covers from the subtree summaries of the children. The summary is set to
+    /// `None` if any child's is missing. Pass the children's summaries in the order of `children`, nested child first.
+    pub(crate) fn update_subtree_summary<M: Metric>(
+        &mut self,
+        children_summaries: &[Option<MetaSummary>],
+        point_cloud: &PointCloud<M>,
+    ) -> MalwareBrotResult<()> {
+        let mut summaries = Vec::with_capacity(children_summaries.len() + 1);
+        for summary in children_summaries {
+            match summary {
+                Some(summary) => summaries.push(summary.clone()),
+                None => {
+                    self.subtree_summary = None;
+                    return Ok(());
+                }
+            }
+        }
+        if self.is_leaf() {
+            let mut indexes: Vec<PointIndex> = self.singles_indexes.to_vec();
+            indexes.push(self.address.1);
+            summaries.push(point_cloud.get_metasummary(&indexes[..])?);
+        } else {
+            summaries.push(point_cloud.get_metasummary(&self.singles_indexes[..])?);
+        }
+        self.subtree_summary = Some(MetaSummary::combine(&summaries[..])?);
+        Ok(())
+    }
+
     pub(crate) fn load(scale_index: i32, node_proto: &NodeProto) -> CoverNode {
         let singles_indexes = node_proto
             .outlier_point_indexes
             .iter()
             .map(|i| *i as PointIndex)
             .collect();
-        let singles_summary = Some(MetaSummary::new());
+        let singles_summary = serde_json::from_str(node_proto.get_outlier_summary_json()).ok();
         let radius = node_proto.get_radius();
         let address = (scale_index, node_proto.get_center_index());
         let cover_count = node_proto.get_cover_count() as usize;
+        let subtree_summary = serde_json::from_str(node_proto.get_subtree_summary_json()).ok();
         let children;
         if node_proto.get_is_leaf() {
             children = None;
@@ -263,6 +546,7 @@ impl CoverNode {
             children,
             singles_indexes,
             singles_summary,
+            subtree_summary,
         }
     }
 
@@ -272,6 +556,16 @@ impl CoverNode {
         proto.set_center_index(self.address.1 as u64);
         proto.set_radius(self.radius);
         proto.set_outlier_point_indexes(self.singles_indexes.iter().map(|pi| *pi as u64).collect());
+        if let Some(summary) = &self.singles_summary {
+            proto.set_outlier_summary_json(
+                serde_json::to_string(summary).expect("a metasummary always serializes"),
+            );
+        }
+        if let Some(summary) = &self.subtree_summary {
+            proto.set_subtree_summary_json(
+                serde_json::to_string(summary).expect("a metasummary always serializes"),
+            );
+        }
 
         match &self.children {
             Some(children) => {
@@ -331,6 +625,7 @@ mod tests {
             children,
             singles_indexes: smallvec![4, 5, 6],
             singles_summary: None,
+            subtree_summary: None,
         }
     }
 
@@ -342,6 +637,7 @@ mod tests {
             children: None,
             singles_indexes: smallvec![1, 2, 3, 4, 5, 6],
             singles_summary: None,
+            subtree_summary: None,
         }
     }
 
@@ -0,0 +1,124 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Filters over point metadata for the filtered KNN. A filter has to answer two questions, does a single point's metadata
+//! pass, and could any of the points behind a `MetaSummary` pass. The second lets the query skip the singletons of a node
+//! without loading their metadata.
+
+use pointcloud::labels::values::{Metadata, Number, Value};
+use pointcloud::labels::{MetaSummary, ValueSummary};
+
+/// A predicate over the metadata of a point.
+pub trait MetadataFilter {
+    /// Does the point with this metadata pass the filter.
+    fn matches(&self, metadata: &Metadata) -> bool;
+    /// Could any of the points that this summary was made from pass the filter. Only return false if you're sure none can,
+    /// the default assumes that some could.
+    fn could_match(&self, _summary: &MetaSummary) -> bool {
+        true
+    }
+}
+
+/// Any closure over the metadata is a filter, but it can't use the summaries to skip nodes.
+impl<F: Fn(&Metadata) -> bool> MetadataFilter for F {
+    fn matches(&self, metadata: &Metadata) -> bool {
+        self(metadata)
+    }
+}
+
+/// Simple filters on a single label key that know how to read the node summaries.
+#[derive(Debug, Clone)]
+pub enum LabelFilter {
+    /// The label is equal to the value. Works for bools, numbers and strings.
+    Equals(String, Value),
+    /// The label is a number greater than the value.
+    GreaterThan(String, f32),
+    /// The label is a number less than the value.
+    LessThan(String, f32),
+    /// All of the filters pass.
+    And(Vec<LabelFilter>),
+}
+
+fn number_value(number: &Number) -> f32 {
+    match number {
+        Number::Real(x) => *x,
+        Number::Natural(x) => *x as f32,
+        Number::Integer(x) => *x as f32,
+    }
+}
+
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => number_value(a) == number_value(b),
+        (Value::String(a), Value::String(b)) => a == b,
+        _ => false,
+    }
+}
+
+impl MetadataFilter for LabelFilter {
+    fn matches(&self, metadata: &Metadata) -> bool {
+        match self {
+            LabelFilter::Equals(key, value) => metadata
+                .get(key)
+                .map(|v| values_equal(v, value))
+                .unwrap_or(false),
+            LabelFilter::GreaterThan(key, x) => match metadata.get(key) {
+                Some(Value::Number(n)) => number_value(n) > *x,
+                _ => false,
+            },
+            LabelFilter::LessThan(key, x) => match metadata.get(key) {
+                Some(Value::Number(n)) => number_value(n) < *x,
+                _ => false,
+            },
+            LabelFilter::And(filters) => filters.iter().all(|f| f.matches(metadata)),
+        }
+    }
+
+    fn could_match(&self, summary: &MetaSummary) -> bool {
+        match self {
+            LabelFilter::Equals(key, value) => match (summary.get(key), value) {
+                (Some(ValueSummary::BoolSummary(bs)), Value::Bool(b)) => {
+                    if *b {
+                        bs.true_count > 0
+                    } else {
+                        bs.false_count > 0
+                    }
+                }
+                (Some(ValueSummary::NumberSummary(ns)), Value::Number(n)) => {
+                    let x = number_value(n);
+                    ns.count() > 0 && ns.min() <= x && x <= ns.max()
+                }
+                (Some(ValueSummary::StringSummary(ss)), Value::String(s)) => {
+                    ss.unique_strings.contains_key(s)
+                }
+                _ => true,
+            },
+            LabelFilter::GreaterThan(key, x) => match summary.get(key) {
+                Some(ValueSummary::NumberSummary(ns)) => ns.count() > 0 && ns.max() > *x,
+                _ => true,
+            },
+            LabelFilter::LessThan(key, x) => match summary.get(key) {
+                Some(ValueSummary::NumberSummary(ns)) => ns.count() > 0 && ns.min() < *x,
+                _ => true,
+            },
+            LabelFilter::And(filters) => filters.iter().all(|f| f.could_match(summary)),
+        }
+    }
+}
@@ -21,10 +21,13 @@
 
 use crate::NodeAddress;
 use pointcloud::*;
+use std::cmp::Reverse;
 use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::f32;
 
-mod query_items;
+pub(crate) mod query_items;
+mod metadata_filter;
+pub use metadata_filter::{LabelFilter, MetadataFilter};
 
 use query_items::{QueryAddress, QuerySingleton};
 
@@ -50,6 +53,7 @@ pub struct KnnQueryHeap {
     dist_heap: BinaryHeap<QuerySingleton>,
     k: usize,
     scale_base: f32,
+    epsilon: f32,
 }
 
 impl KnnQueryHeap {
@@ -64,9 +68,32 @@ impl KnnQueryHeap {
             known_indexes: HashSet::new(),
             k,
             scale_base: scale_base,
+            epsilon: 0.0,
         }
     }
 
+    /// Creates a KNN heap for an approximate query. A node is only kept if it could cover a point closer than `max_dist/(1+epsilon)`,
+    /// this is checked when it's pushed and again when it's popped. So, the `i`th neighbor found is at most `(1+epsilon)` times
+    /// further away than the true `i`th neighbor. With an `epsilon` of 0 this is the same as `new`.
+    pub fn new_approximate(k: usize, scale_base: f32, epsilon: f32) -> KnnQueryHeap {
+        let mut heap = KnnQueryHeap::new(k, scale_base);
+        heap.epsilon = epsilon.max(0.0);
+        heap
+    }
+
+    /// The relaxation of the pruning, 0 for an exact query.
+    pub fn epsilon(&self) -> f32 {
+        self.epsilon
+    }
+
+    /// Approximate queries drop nodes that can't improve the KNN by a factor of `1+epsilon` when they're popped.
+    /// Exact queries only prune when pushing. The `min_dist` of a popped node may have been raised by `increase_estimated_distance`,
+    /// which is only good for ordering, so we use the scale bound again.
+    fn relaxed_out_of_range(&self, node: &QueryAddress) -> bool {
+        let emd = (node.dist_to_center - self.scale_base.powi(node.address.0)).max(0.0);
+        self.epsilon > 0.0 && emd * (1.0 + self.epsilon) >= self.max_dist()
+    }
+
     /// Finds the closest node who could have a child node at least the current kth furthest distance away from the query point. 
     /// This pops that node and pushes it onto the singleton heap.
     pub fn closest_unvisited_child_covering_address(&mut self) -> Option<(f32, NodeAddress)> {
@@ -75,11 +102,11 @@ impl KnnQueryHeap {
                 if min_dist_update > node_to_visit.min_dist {
                     node_to_visit.min_dist = min_dist_update;
                     self.child_heap.push(node_to_visit);
-                } else {
+                } else if !self.relaxed_out_of_range(&node_to_visit) {
                     self.singleton_heap.push(node_to_visit);
                     return Some((node_to_visit.dist_to_center,node_to_visit.address));
                 }
-            } else {
+            } else if !self.relaxed_out_of_range(&node_to_visit) {
                 self.singleton_heap.push(node_to_visit);
                 return Some((node_to_visit.dist_to_center,node_to_visit.address));
             }
@@ -95,10 +122,10 @@ impl KnnQueryHeap {
                 if min_dist_update > node_to_visit.min_dist {
                     node_to_visit.min_dist = min_dist_update;
                     self.singleton_heap.push(node_to_visit);
-                } else {
+                } else if !self.relaxed_out_of_range(&node_to_visit) {
                     return Some((node_to_visit.dist_to_center,node_to_visit.address));
                 }
-            } else {
+            } else if !self.relaxed_out_of_range(&node_to_visit) {
                 return Some((node_to_visit.dist_to_center,node_to_visit.address));
             }
         }
@@ -133,6 +160,32 @@ impl KnnQueryHeap {
         result.iter().rev().cloned().collect()
     }
 
+    /// Clears the heaps for another query, keeping the allocations around. This is for running many queries through one heap.
+    pub fn reset(&mut self, k: usize) {
+        self.child_heap.clear();
+        self.singleton_heap.clear();
+        self.known_indexes.clear();
+        self.est_min_dist.clear();
+        self.dist_heap.clear();
+        self.k = k;
+    }
+
+    /// Unpacks the distance heap into a pair of output rows, closest first. This empties the distance heap but doesn't consume it.
+    /// If there are fewer than `dists.len()` points on the heap the rest of the row is padded with `f32::MAX` and `PointIndex::MAX`.
+    pub fn unpack_into(&mut self, dists: &mut [f32], indexes: &mut [PointIndex]) {
+        for (d, i) in dists.iter_mut().zip(indexes.iter_mut()).skip(self.dist_heap.len()) {
+            *d = f32::MAX;
+            *i = PointIndex::max_value();
+        }
+        while let Some(el) = self.dist_heap.pop() {
+            let j = self.dist_heap.len();
+            if j < dists.len() {
+                dists[j] = el.dist;
+                indexes[j] = el.index;
+            }
+        }
+    }
+
     /// This allows you to update the minimum distance to the parent of a node, or it's siblings.
     /// If you are well within the radius of coverage of a node, this allows you to remove the parent or sibling from the
     ///  `closest_unvisited_child_covering_address` and `closest_unvisited_singleton_covering_address` queries.
@@ -163,6 +216,34 @@ impl KnnQueryHeap {
         }
     }
 
+    /// Shove a bunch of nodes onto the child heap, without pushing their centers onto the distance heap. This is for queries
+    /// that filter points, where the centers have to pass the filter before they go in with `push_outliers`.
+    /// Optionally, if you pass a parent node it updates the distance to that parent node.
+    pub fn push_node_addresses(
+        &mut self,
+        indexes: &[NodeAddress],
+        dists: &[f32],
+        parent_address: Option<NodeAddress>,
+    ) {
+        let max_dist = self.max_dist();
+        let mut parent_est_dist_update = 0.0;
+        for ((si, pi), d) in indexes.iter().zip(dists) {
+            let emd = (d - self.scale_base.powi(*si)).max(0.0);
+            parent_est_dist_update = emd.max(parent_est_dist_update);
+            if emd * (1.0 + self.epsilon) < max_dist {
+                self.child_heap.push(QueryAddress {
+                    address: (*si, *pi),
+                    dist_to_center: *d,
+                    min_dist: emd,
+                });
+            }
+        }
+
+        if let Some(a) = parent_address {
+            self.increase_estimated_distance(a, parent_est_dist_update);
+        }
+    }
+
     /// Shove a bunch of nodes onto the heap. Optionally, if you pass a parent node it updates the distance to that parent node.
     pub fn push_nodes(
         &mut self,
@@ -175,7 +256,7 @@ impl KnnQueryHeap {
         for ((si,pi), d) in indexes.iter().zip(dists) {
             let emd = (d - self.scale_base.powi(*si)).max(0.0);
             parent_est_dist_update = emd.max(parent_est_dist_update);
-            if emd < max_dist {
+            if emd * (1.0 + self.epsilon) < max_dist {
                 self.child_heap.push(QueryAddress {
                     address: (*si,*pi),
                     dist_to_center: *d,
@@ -205,6 +286,202 @@ impl KnnQueryHeap {
     }
 }
 
+/// The heaps for a lazy nearest neighbor query, where we don't know `k` up front. There's a min-heap of nodes that haven't been
+/// expanded, ordered by the minimum distance a covered point could have to the query point, and a min-heap of points we've
+/// computed the distance to but haven't handed out yet.
+///
+/// A point is only handed out once it's closer than the minimum possible distance of every unexpanded node. So the points come
+/// out in increasing distance order, and we only expand as much of the tree as we need for the points that are asked for.
+/// Like the `KnnQueryHeap` we have a HashSet of known points to stop a center from going in twice.
+#[derive(Debug)]
+pub struct LazyKnnHeap {
+    node_heap: BinaryHeap<QueryAddress>,
+    point_heap: BinaryHeap<Reverse<QuerySingleton>>,
+    known_indexes: HashSet<PointIndex>,
+    scale_base: f32,
+}
+
+impl LazyKnnHeap {
+    /// Creates a new lazy heap. The `scale_base` is for the minimum distance from our query point to potential covered points of a node.
+    pub fn new(scale_base: f32) -> LazyKnnHeap {
+        LazyKnnHeap {
+            node_heap: BinaryHeap::new(),
+            point_heap: BinaryHeap::new(),
+            known_indexes: HashSet::new(),
+            scale_base,
+        }
+    }
+
+    /// If the closest point we know about is closer than any point an unexpanded node could cover, this pops it.
+    pub fn closest_settled_point(&mut self) -> Option<(f32, PointIndex)> {
+        let point_dist = self.point_heap.peek().map(|p| (p.0).dist)?;
+        match self.node_heap.peek() {
+            Some(node) if node.min_dist < point_dist => None,
+            _ => self.point_heap.pop().map(|p| ((p.0).dist, (p.0).index)),
+        }
+    }
+
+    /// Pops the unexpanded node that could cover the closest point.
+    pub fn closest_unexpanded_node_address(&mut self) -> Option<(f32, NodeAddress)> {
+        self.node_heap
+            .pop()
+            .map(|node| (node.dist_to_center, node.address))
+    }
+
+    /// The number of points waiting to be handed out
+    pub fn len(&self) -> usize {
+        self.point_heap.len()
+    }
+
+    /// The number of nodes that haven't been expanded
+    pub fn node_len(&self) -> usize {
+        self.node_heap.len()
+    }
+
+    /// Shove a bunch of single points onto the heap
+    pub fn push_outliers(&mut self, indexes: &[PointIndex], dists: &[f32]) {
+        for (i, d) in indexes.iter().zip(dists) {
+            if !self.known_indexes.contains(i) {
+                self.known_indexes.insert(*i);
+                self.point_heap.push(Reverse(QuerySingleton::new(*i, *d)));
+            }
+        }
+    }
+
+    /// Shove a bunch of nodes onto the heap, their centers go on as points.
+    pub fn push_nodes(&mut self, indexes: &[NodeAddress], dists: &[f32]) {
+        for ((si, pi), d) in indexes.iter().zip(dists) {
+            let emd = (d - self.scale_base.powi(*si)).max(0.0);
+            self.node_heap.push(QueryAddress {
+                address: (*si, *pi),
+                dist_to_center: *d,
+                min_dist: emd,
+            });
+        }
+        let centers: Vec<PointIndex> = indexes.iter().map(|(_si, pi)| *pi).collect();
+        self.push_outliers(&centers, dists);
+    }
+}
+
+/// Settings for an approximate KNN query, see `CoverTreeReader::approximate_knn`. The default is an exact query with no limits.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApproximateKnnParameters {
+    /// The relaxation of the pruning. Every neighbor returned is at most `(1+epsilon)` times further away than the true neighbor,
+    /// unless the query was cut off.
+    pub epsilon: f32,
+    /// Hard cap on the number of node visits. Visiting a node's children and visiting its singletons count separately.
+    pub max_nodes: Option<usize>,
+    /// Hard cap on the number of distance evaluations.
+    pub max_distances: Option<usize>,
+}
+
+/// How good the answer to an approximate KNN query is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnnStatus {
+    /// The query ran to completion with no relaxation, this is the true KNN.
+    Exact,
+    /// The query ran to completion, the neighbors are within the `(1+epsilon)` guarantee.
+    WithinEpsilon,
+    /// The query ran out of budget, the neighbors are the best found so far and have no guarantee.
+    CutOff,
+}
+
+/// The result of an approximate KNN query.
+#[derive(Debug)]
+pub struct ApproximateKnn {
+    /// The neighbors found, closest first.
+    pub neighbors: Vec<(f32, PointIndex)>,
+    /// Whether the neighbors are exact, within epsilon or the query was cut off early.
+    pub status: KnnStatus,
+    /// The number of node visits the query made.
+    pub nodes_visited: usize,
+    /// The number of distances the query calculated.
+    pub distance_evaluations: usize,
+}
+
+/// The heap for a range query. Unlike the KNN there's no shrinking maximum distance, so we only need a min-heap of nodes that
+/// could cover a point within the radius and a list of the points we've found inside the radius.
+///
+/// Nodes are pushed with the same minimum distance estimate as the `KnnQueryHeap`, `d - b^i`, and rejected if that is larger than the radius.
+/// The node's actual radius is tighter than `b^i` so it should be checked again when the node is visited, see `CoverNode::range`.
+/// We also keep the HashSet of known points to stop double inserts of a center point that's repeated down the tree.
+#[derive(Debug)]
+pub struct RangeQueryHeap {
+    node_heap: BinaryHeap<QueryAddress>,
+    known_indexes: HashSet<PointIndex>,
+    results: Vec<QuerySingleton>,
+    radius: f32,
+    scale_base: f32,
+}
+
+impl RangeQueryHeap {
+    /// Creates a new range heap. The `scale_base` is for the minimum distance from our query point to potential covered points of a node.
+    pub fn new(radius: f32, scale_base: f32) -> RangeQueryHeap {
+        RangeQueryHeap {
+            node_heap: BinaryHeap::new(),
+            known_indexes: HashSet::new(),
+            results: Vec::new(),
+            radius,
+            scale_base,
+        }
+    }
+
+    /// The radius of the query
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Pops the closest node that could cover a point within the radius.
+    pub fn closest_unvisited_node_address(&mut self) -> Option<(f32, NodeAddress)> {
+        self.node_heap
+            .pop()
+            .map(|node_to_visit| (node_to_visit.dist_to_center, node_to_visit.address))
+    }
+
+    /// The current number of points found within the radius
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    /// The current number of nodes still to visit
+    pub fn node_len(&self) -> usize {
+        self.node_heap.len()
+    }
+
+    /// Unpacks the points found within the radius, sorted by distance. This consumes the query heap.
+    pub fn unpack(mut self) -> Vec<(f32, PointIndex)> {
+        self.results.sort();
+        self.results.iter().map(|el| (el.dist, el.index)).collect()
+    }
+
+    /// Shove a bunch of single points onto the heap, only those within the radius are kept.
+    pub fn push_outliers(&mut self, indexes: &[PointIndex], dists: &[f32]) {
+        for (i, d) in indexes.iter().zip(dists) {
+            if *d <= self.radius && !self.known_indexes.contains(i) {
+                self.known_indexes.insert(*i);
+                self.results.push(QuerySingleton::new(*i, *d));
+            }
+        }
+    }
+
+    /// Shove a bunch of nodes onto the heap. Their centers are treated like outliers, and the nodes are only kept if they could cover
+    /// a point within the radius.
+    pub fn push_nodes(&mut self, indexes: &[NodeAddress], dists: &[f32]) {
+        for ((si, pi), d) in indexes.iter().zip(dists) {
+            let emd = (d - self.scale_base.powi(*si)).max(0.0);
+            if emd <= self.radius {
+                self.node_heap.push(QueryAddress {
+                    address: (*si, *pi),
+                    dist_to_center: *d,
+                    min_dist: emd,
+                });
+            }
+        }
+        let centers: Vec<PointIndex> = indexes.iter().map(|(_si, pi)| *pi).collect();
+        self.push_outliers(&centers, dists);
+    }
+}
+
 //Tested in the node file too
 #[cfg(test)]
 pub(crate) mod tests {
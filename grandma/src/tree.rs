@@ -39,13 +39,23 @@ use node::*;
 //use pointcloud::*;
 
 use tree_file_format::*;
-use std::sync::{atomic, Arc};
+use crate::validation::{self, ValidationReport};
+use std::cell::OnceCell;
+use std::sync::{atomic, Arc, Mutex};
+use std::thread;
 
-use crate::query_tools::KnnQueryHeap;
-use errors::MalwareBrotResult;
+use crate::query_tools::{
+    ApproximateKnn, ApproximateKnnParameters, KnnQueryHeap, KnnStatus, LazyKnnHeap,
+    MetadataFilter, RangeQueryHeap,
+};
+use errors::{MalwareBrotError, MalwareBrotResult};
+use pointcloud::labels::values::Metadata;
+use pointcloud::labels::MetaSummary;
+use crate::builders::build_subtree;
+use std::collections::{BTreeMap, HashMap};
+use serde::Serialize;
 use std::iter::Iterator;
 use std::ops::Range;
-use std::slice::Iter;
 
 /// Container for the parameters governing the construction of the covertree
 #[derive(Debug)]
@@ -61,12 +71,18 @@ pub struct CoverTreeParameters<M: Metric> {
     pub resolution: i32,
     /// If you don't want singletons messing with your tree and want everything to be a node or a element of leaf node, make this true. 
     pub use_singletons: bool,
-    /// Clustering is currently slow, avoid
+    /// Unused for now
     pub cluster_min: usize,
-    /// The point cloud this tree references
-    pub point_cloud: PointCloud<M>,
+    /// The point cloud this tree references, this can be shared with other trees
+    pub point_cloud: Arc<PointCloud<M>>,
     /// This should be replaced by a logging solution
     pub verbosity: u32,
+    /// Seed for picking the centers of new nodes, see `CoverTreeBuilder::seed`
+    pub seed: Option<u64>,
+    /// How the centers of new nodes are picked, see `CoverTreeBuilder::center_selection`
+    pub center_selection: CenterSelection,
+    /// If the nodes have subtree summaries, see `CoverTreeBuilder::summarize_subtrees`
+    pub summarize_subtrees: bool,
 }
 
 impl<M: Metric> CoverTreeParameters<M> {
@@ -83,7 +99,88 @@ impl<M: Metric> CoverTreeParameters<M> {
 /// Helper struct for iterating thru the reader's of the the layers. 
 pub struct LayerIter<'a> {
     scales: Range<i32>,
-    layers: Iter<'a, CoverLayerReader>,
+    layers: std::vec::IntoIter<&'a CoverLayerReader>,
+}
+
+/// What the writer publishes with each `refresh`, shared by the writer and all of its readers. The epoch is odd while
+/// the layers are being swapped, so a read that starts and ends on the same even epoch saw every layer and the root from
+/// a single refresh. See `CoverTreeReader::consistent`.
+pub(crate) struct TreeHead {
+    epoch: atomic::AtomicUsize,
+    root_scale: atomic::AtomicI32,
+    root_index: atomic::AtomicU64,
+    layer_count: atomic::AtomicUsize,
+    /// A reader of each published layer. New readers, and old readers of a tree that grew, get theirs from these.
+    layers: Mutex<Vec<CoverLayerReader>>,
+}
+
+impl TreeHead {
+    fn new(root_address: NodeAddress, layers: &[CoverLayerWriter]) -> TreeHead {
+        TreeHead {
+            epoch: atomic::AtomicUsize::new(0),
+            root_scale: atomic::AtomicI32::new(root_address.0),
+            root_index: atomic::AtomicU64::new(root_address.1),
+            layer_count: atomic::AtomicUsize::new(layers.len()),
+            layers: Mutex::new(layers.iter().map(|l| l.reader()).collect()),
+        }
+    }
+
+    /// Refreshes the layers and stores the root as one version bump.
+    fn publish(&self, root_address: NodeAddress, layers: &mut [CoverLayerWriter]) {
+        self.epoch.fetch_add(1, atomic::Ordering::SeqCst);
+        layers.par_iter_mut().for_each(|l| l.refresh());
+        {
+            let mut readers = self.layers.lock().unwrap();
+            while readers.len() < layers.len() {
+                let reader = layers[readers.len()].reader();
+                readers.push(reader);
+            }
+            self.layer_count.store(readers.len(), atomic::Ordering::Release);
+        }
+        self.root_scale.store(root_address.0, atomic::Ordering::Release);
+        self.root_index.store(root_address.1, atomic::Ordering::Release);
+        self.epoch.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+
+    /// Waits out a refresh that's under way, then returns the epoch and the root to start a read from.
+    fn begin_read(&self) -> (usize, NodeAddress) {
+        loop {
+            let epoch = self.epoch.load(atomic::Ordering::Acquire);
+            if epoch & 1 == 0 {
+                let root_address = (
+                    self.root_scale.load(atomic::Ordering::Acquire),
+                    self.root_index.load(atomic::Ordering::Acquire),
+                );
+                return (epoch, root_address);
+            }
+            thread::yield_now();
+        }
+    }
+
+    /// True if there was no refresh since `begin_read` returned this epoch, so everything read in between is from one version.
+    fn end_read(&self, epoch: usize) -> bool {
+        atomic::fence(atomic::Ordering::SeqCst);
+        self.epoch.load(atomic::Ordering::Relaxed) == epoch
+    }
+
+    fn layer_count(&self) -> usize {
+        self.layer_count.load(atomic::Ordering::Acquire)
+    }
+
+    fn layer_readers(&self) -> Vec<CoverLayerReader> {
+        self.layers.lock().unwrap().iter().map(|l| l.reader()).collect()
+    }
+
+    fn layer_reader(&self, index: usize) -> CoverLayerReader {
+        self.layers.lock().unwrap()[index].reader()
+    }
+}
+
+/// A layer that was added to the tree after a reader was made. They're chained so that the reader can pick up more
+/// without moving the ones it has handed out.
+struct GrownLayer {
+    layer: CoverLayerReader,
+    above: OnceCell<Box<GrownLayer>>,
 }
 
 impl<'a> Iterator for LayerIter<'a> {
@@ -103,15 +200,66 @@ impl<'a> Iterator for LayerIter<'a> {
 /// All queries of the covertree should go through a reader head. This includes queries you are doing to modify the tree.
 /// There are no thread locks anywhere in the code below the reader head, so it's fast. 
 ///
-/// The data structure is just a list of `CoverLayerReader`s, the parameter's object and the root address. Copies are relatively
-/// expensive as each `CoverLayerReader` contains several Arcs that need to be cloned.
+/// The data structure is just a list of `CoverLayerReader`s, the parameter's object and the head the writer publishes the
+/// root address on. Copies are relatively expensive as each `CoverLayerReader` contains several Arcs that need to be cloned.
+///
+/// Queries see the tree as of a single `CoverTreeWriter::refresh`. If the writer refreshes while a query is running the
+/// query starts over on the new tree, see `consistent`.
 pub struct CoverTreeReader<M: Metric> {
     parameters: Arc<CoverTreeParameters<M>>,
     layers: Vec<CoverLayerReader>,
-    root_address: NodeAddress,
+    grown: OnceCell<Box<GrownLayer>>,
+    head: Arc<TreeHead>,
 }
 
 impl<M: Metric> CoverTreeReader<M> {
+    fn new(parameters: Arc<CoverTreeParameters<M>>, head: Arc<TreeHead>) -> CoverTreeReader<M> {
+        CoverTreeReader {
+            parameters,
+            layers: head.layer_readers(),
+            grown: OnceCell::new(),
+            head,
+        }
+    }
+
+    /// Runs a read of the tree against the root and layers of one refresh. If the writer refreshes while it's running,
+    /// the read is thrown away and run again on the new tree, so it can be called more than once. All the queries below
+    /// go through this, use it when you walk the tree yourself with `get_node_and` and want a consistent view.
+    pub fn consistent<F, T>(&self, mut read: F) -> T
+    where
+        F: FnMut(NodeAddress) -> T,
+    {
+        loop {
+            let (epoch, root_address) = self.head.begin_read();
+            let result = read(root_address);
+            if self.head.end_read(epoch) {
+                return result;
+            }
+        }
+    }
+
+    /// The reader of the layer at this internal index, picking up layers the tree grew after this reader was made.
+    fn layer_at(&self, index: usize) -> &CoverLayerReader {
+        if index < self.layers.len() {
+            return &self.layers[index];
+        }
+        let mut grown = &self.grown;
+        let mut grown_index = self.layers.len();
+        loop {
+            let layer = grown.get_or_init(|| {
+                Box::new(GrownLayer {
+                    layer: self.head.layer_reader(grown_index),
+                    above: OnceCell::new(),
+                })
+            });
+            if grown_index == index {
+                return &layer.layer;
+            }
+            grown = &layer.above;
+            grown_index += 1;
+        }
+    }
+
     /// A reference to the point cloud the tree was built on.
     pub fn point_cloud(&self) -> &PointCloud<M> {
         &self.parameters.point_cloud
@@ -120,7 +268,7 @@ impl<M: Metric> CoverTreeReader<M> {
     /// Returns a borrowed reader for a cover layer. 
     /// 
     pub fn layer(&self, scale_index: i32) -> &CoverLayerReader {
-        &self.layers[self.parameters.internal_index(scale_index)]
+        self.layer_at(self.parameters.internal_index(scale_index))
     }
 
     /// simple helper to get the scale from the scale index and the scale base, this is just `b^i`
@@ -133,21 +281,25 @@ impl<M: Metric> CoverTreeReader<M> {
     where
         F: FnOnce(&CoverNode) -> T,
     {
-        self.layers[self.parameters.internal_index(node_address.0)]
+        self.layer(node_address.0)
             .get_node_and(&node_address.1, |n| f(n))
     }
 
-    /// The root of the tree. Pass this to `get_node_and` to get the root node's content and start a traversal of the tree.
+    /// The root of the tree as of the last refresh. Pass this to `get_node_and` to get the root node's content and start a
+    /// traversal of the tree, inside `consistent` if the tree is being written to.
     pub fn root_address(&self) -> NodeAddress {
-        self.root_address
+        self.consistent(|root_address| root_address)
     }
 
-    /// 
+    /// Iterates thru all the layers with their scale index, from the bottom up. The bottom layer holds the nodes below the
+    /// resolution, so it's given the scale index `resolution - 1`.
     pub fn layers<'a>(&'a self) -> LayerIter<'a> {
+        let layer_count = self.head.layer_count();
+        let layers: Vec<&'a CoverLayerReader> = (0..layer_count).map(|i| self.layer_at(i)).collect();
         LayerIter {
-            scales: (self.layers.len() as i32 - self.parameters.resolution)
-                ..(self.parameters.resolution),
-            layers: self.layers.iter(),
+            scales: (self.parameters.resolution - 1)
+                ..(self.parameters.resolution - 1 + layer_count as i32),
+            layers: layers.into_iter(),
         }
     }
 
@@ -158,12 +310,31 @@ impl<M: Metric> CoverTreeReader<M> {
 
     /// This is the total number of nodes in the tree. This queries each layer, so it's not a simple return int.
     pub fn node_count(&self) -> usize {
-        self.layers().fold(0,|a,(_si,l)| a+l.node_count())
+        self.consistent(|_root_address| self.layers().fold(0,|a,(_si,l)| a+l.node_count()))
+    }
+
+    /// The indexes of the points in the tree, sorted. This can be fewer than the point cloud holds, if the tree was built
+    /// over a subset of it or points were removed.
+    pub fn point_indexes(&self) -> Vec<PointIndex> {
+        let mut point_indexes = self.consistent(|_root_address| {
+            let mut point_indexes = Vec::new();
+            for (_si, layer) in self.layers() {
+                layer.for_each_node(|pi, n| {
+                    if n.is_leaf() {
+                        point_indexes.push(*pi);
+                    }
+                    point_indexes.extend_from_slice(n.singletons());
+                });
+            }
+            point_indexes
+        });
+        point_indexes.sort();
+        point_indexes
     }
 
     /// Returns the scale index range. It starts at the minimum resolution and ends at the top. You can reverse this for the correct order.
     pub fn scale_range(&self) -> Range<i32> {
-        (self.parameters.resolution)..(self.parameters.resolution - 1 + self.layers.len() as i32)
+        (self.parameters.resolution)..(self.parameters.resolution - 1 + self.head.layer_count() as i32)
     }
     
     /// # The KNN query.
@@ -184,21 +355,365 @@ impl<M: Metric> CoverTreeReader<M> {
     /// See `query_tools::KnnQueryHeap` for the pair of heaps and mechanisms for tracking the minimum distance and the current knn set.
     /// See the `nodes::CoverNode::singleton_knn` and `nodes::CoverNode::child_knn` for the brute force node based knn.
     pub fn knn(&self,point:&[f32],k:usize) -> MalwareBrotResult<Vec<(f32,PointIndex)>> {
-        let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+        self.consistent(|root_address| {
+            let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+            self.knn_from_with_heap(root_address, point, None, &mut query_heap)?;
+            Ok(query_heap.unpack())
+        })
+    }
+
+    /// The KNN of a point that's already in the tree's point cloud. If `exclude_self` is set the point itself
+    /// is filtered out of the result, and you still get `k` other points (if the tree has that many).
+    pub fn knn_index(
+        &self,
+        point_index: PointIndex,
+        k: usize,
+        exclude_self: bool,
+    ) -> MalwareBrotResult<Vec<(f32, PointIndex)>> {
+        let point = self.parameters.point_cloud.get_point(point_index)?;
+        if exclude_self {
+            let mut nbrs = self.knn(point, k + 1)?;
+            nbrs.retain(|(_d, pi)| *pi != point_index);
+            nbrs.truncate(k);
+            Ok(nbrs)
+        } else {
+            self.knn(point, k)
+        }
+    }
+
+    /// The KNN of a point in the tree's point cloud by name. Errors with `NameNotInTree` if the name isn't known.
+    /// See `knn_index`.
+    pub fn knn_name(
+        &self,
+        point_name: &PointName,
+        k: usize,
+        exclude_self: bool,
+    ) -> MalwareBrotResult<Vec<(f32, PointIndex)>> {
+        let point_cloud = &self.parameters.point_cloud;
+        let point_index = match point_cloud.get_index(point_name) {
+            Some(point_index) => Some(*point_index),
+            None => point_cloud.get_appended_index(point_name),
+        };
+        match point_index {
+            Some(point_index) => self.knn_index(point_index, k, exclude_self),
+            None => Err(MalwareBrotError::NameNotInTree(point_name.clone())),
+        }
+    }
+
+    /// # The Filtered KNN query.
+    /// The KNN query, but only points whose metadata passes the filter are returned. See `query_tools::LabelFilter` for
+    /// filters on a single label, or pass any closure over the `Metadata`.
+    ///
+    /// Nodes are traversed just like the KNN query, but the `max_dist` pruning only tightens once we've found `k` points that
+    /// pass. So that a rare filter doesn't walk the whole tree, a child is only visited if the filter could pass its
+    /// `subtree_summary`, and a node's singletons are only brute forced if the filter could pass its `singles_summary`.
+    /// Nodes without a subtree summary, like those of a loaded tree that hasn't been summarized, are always visited.
+    pub fn knn_filtered<F: MetadataFilter>(
+        &self,
+        point: &[f32],
+        k: usize,
+        filter: &F,
+    ) -> MalwareBrotResult<Vec<(f32, PointIndex)>> {
+        self.consistent(|root_address| {
+            let point_cloud = &self.parameters.point_cloud;
+            let mut query_heap = KnnQueryHeap::new(k, self.parameters.scale_base);
+
+            if !self.subtree_could_match(root_address, filter) {
+                return Ok(Vec::new());
+            }
+            let root_center = point_cloud.get_point(root_address.1)?;
+            let dist_to_root = M::dense(root_center, point);
+            query_heap.push_node_addresses(&[root_address], &[dist_to_root], None);
+            if filter.matches(&point_cloud.get_metadata(root_address.1)?) {
+                query_heap.push_outliers(&[root_address.1], &[dist_to_root]);
+            }
+            self.filtered_greedy_knn_nodes(point, filter, &mut query_heap)?;
+
+            while let Some((_dist, address)) = query_heap.closest_unvisited_singleton_covering_address() {
+                if let Some(res) = self.get_node_and(address, |n| {
+                    n.filtered_singleton_knn(point, point_cloud, filter, &mut query_heap)
+                }) {
+                    res?;
+                }
+                self.filtered_greedy_knn_nodes(point, filter, &mut query_heap)?;
+            }
+
+            Ok(query_heap.unpack())
+        })
+    }
+
+    /// # The Approximate KNN query.
+    /// This is the KNN query with a relaxed pruning and a budget. A node is only visited if it could cover a point closer than
+    /// `max_dist/(1+epsilon)`, and the query stops before any node visit that would take it past `max_nodes` node visits or
+    /// `max_distances` distance evaluations.
+    ///
+    /// The result reports if the neighbors are exact, within the `(1+epsilon)` guarantee, or if the query was cut off. A cut off
+    /// query returns the best neighbors it found, which can be fewer than `k`.
+    ///
+    /// See `query_tools::ApproximateKnnParameters` and `query_tools::KnnQueryHeap::new_approximate`.
+    pub fn approximate_knn(
+        &self,
+        point: &[f32],
+        k: usize,
+        parameters: &ApproximateKnnParameters,
+    ) -> MalwareBrotResult<ApproximateKnn> {
+        self.consistent(|root_address| {
+            let mut query_heap =
+                KnnQueryHeap::new_approximate(k, self.parameters.scale_base, parameters.epsilon);
+            let mut budget = KnnBudget {
+                parameters,
+                nodes_visited: 0,
+                distance_evaluations: 1,
+            };
+
+            let root_center = self.parameters.point_cloud.get_point(root_address.1)?;
+            let dist_to_root = M::dense(root_center, point);
+            query_heap.push_nodes(&[root_address], &[dist_to_root], None);
+            let mut complete = self.budgeted_greedy_knn_nodes(point, &mut query_heap, &mut budget)?;
+
+            while complete {
+                match query_heap.closest_unvisited_singleton_covering_address() {
+                    Some((_dist, address)) => {
+                        let singleton_len = self.get_node_and(address, |n| n.singleton_len()).unwrap_or(0);
+                        if !budget.spend(singleton_len) {
+                            complete = false;
+                        } else {
+                            if let Some(res) = self.get_node_and(address, |n| {
+                                n.singleton_knn(point, &self.parameters.point_cloud, &mut query_heap)
+                            }) {
+                                res?;
+                            }
+                            complete = self.budgeted_greedy_knn_nodes(point, &mut query_heap, &mut budget)?;
+                        }
+                    }
+                    None => break,
+                }
+            }
+
+            let status = if !complete {
+                KnnStatus::CutOff
+            } else if query_heap.epsilon() > 0.0 {
+                KnnStatus::WithinEpsilon
+            } else {
+                KnnStatus::Exact
+            };
+            Ok(ApproximateKnn {
+                neighbors: query_heap.unpack(),
+                status,
+                nodes_visited: budget.nodes_visited,
+                distance_evaluations: budget.distance_evaluations,
+            })
+        })
+    }
+
+    /// # The Batch KNN query.
+    /// Runs a KNN query for every row of `points`, a row-major matrix with `dim` columns, in parallel.
+    ///
+    /// The queries are split into one chunk per rayon thread. Each chunk gets its own reader (the reader isn't `Sync`) and
+    /// a single `KnnQueryHeap` that is reset between queries, so the heap allocations are reused across the chunk.
+    ///
+    /// The result is a pair of flat row-major `n x k` matrices, the distances and the point indexes, where row `i` is the
+    /// KNN of the `i`th query point closest first. If the tree has fewer than `k` points, `k` is lowered to the number of points.
+    pub fn knn_batch(
+        &self,
+        points: &[f32],
+        dim: usize,
+        k: usize,
+    ) -> MalwareBrotResult<(Vec<f32>, Vec<PointIndex>)> {
+        let tree_dim = self.parameters.point_cloud.dim();
+        if dim != tree_dim || points.len() % dim != 0 {
+            return Err(MalwareBrotError::DimensionMismatch {
+                expected: tree_dim,
+                found: dim,
+            });
+        }
+        let k = k.min(self.parameters.point_cloud.len());
+        let count = points.len() / dim;
+        let mut dists = vec![0.0; count * k];
+        let mut indexes = vec![0; count * k];
+        if count == 0 || k == 0 {
+            return Ok((dists, indexes));
+        }
+
+        let chunk_len = (count + rayon::current_num_threads() - 1) / rayon::current_num_threads();
+        let readers: Vec<CoverTreeReader<M>> = (0..((count + chunk_len - 1) / chunk_len))
+            .map(|_| self.reader())
+            .collect();
+        readers
+            .into_par_iter()
+            .zip(points.par_chunks(chunk_len * dim))
+            .zip(dists.par_chunks_mut(chunk_len * k))
+            .zip(indexes.par_chunks_mut(chunk_len * k))
+            .try_for_each(|(((reader, chunk_points), chunk_dists), chunk_indexes)| {
+                let mut query_heap = KnnQueryHeap::new(k, reader.parameters.scale_base);
+                let rows = chunk_points
+                    .chunks(dim)
+                    .zip(chunk_dists.chunks_mut(k))
+                    .zip(chunk_indexes.chunks_mut(k));
+                for ((point, row_dists), row_indexes) in rows {
+                    reader.consistent(|root_address| {
+                        query_heap.reset(k);
+                        reader.knn_from_with_heap(root_address, point, None, &mut query_heap)
+                    })?;
+                    query_heap.unpack_into(row_dists, row_indexes);
+                }
+                Ok::<(), MalwareBrotError>(())
+            })?;
+        Ok((dists, indexes))
+    }
+
+    /// Clones the reader, expensive!
+    pub fn reader(&self) -> CoverTreeReader<M> {
+        CoverTreeReader::new(Arc::clone(&self.parameters), Arc::clone(&self.head))
+    }
 
-        let root_center = self.parameters.point_cloud.get_point(self.root_address.1)?;
+    /// The KNN query from the root, but if a `start_address` is passed that node is pushed onto the heap next to the root.
+    /// If the start node is close to the query point the greedy search starts there and fills the KNN quickly, which
+    /// prunes most of the search from the root.
+    pub(crate) fn knn_from_with_heap(
+        &self,
+        root_address: NodeAddress,
+        point: &[f32],
+        start_address: Option<NodeAddress>,
+        query_heap: &mut KnnQueryHeap,
+    ) -> MalwareBrotResult<()> {
+        let root_center = self.parameters.point_cloud.get_point(root_address.1)?;
         let dist_to_root = M::dense(root_center,point);
-        query_heap.push_nodes(&[self.root_address],&[dist_to_root],None);
-        self.greedy_knn_nodes(&point,&mut query_heap);
+        query_heap.push_nodes(&[root_address],&[dist_to_root],None);
+        if let Some(start_address) = start_address {
+            let start_center = self.parameters.point_cloud.get_point(start_address.1)?;
+            let dist_to_start = M::dense(start_center, point);
+            query_heap.push_nodes(&[start_address], &[dist_to_start], None);
+        }
+        self.resume_knn_with_heap(point, query_heap)
+    }
+
+    /// Runs the KNN query on a heap that's already been seeded with nodes and points, without pushing the root.
+    /// The seeded nodes and points must cover every point that could be in the KNN.
+    pub(crate) fn resume_knn_with_heap(
+        &self,
+        point: &[f32],
+        query_heap: &mut KnnQueryHeap,
+    ) -> MalwareBrotResult<()> {
+        self.greedy_knn_nodes(&point,query_heap);
 
         while let Some((_dist,address)) = query_heap.closest_unvisited_singleton_covering_address() {
-            self.get_node_and(address, |n| n.singleton_knn(point,&self.parameters.point_cloud,&mut query_heap));
-            self.greedy_knn_nodes(&point,&mut query_heap);
+            if let Some(res) = self.get_node_and(address, |n| n.singleton_knn(point,&self.parameters.point_cloud,query_heap)) {
+                res?;
+            }
+            self.greedy_knn_nodes(&point,query_heap);
         }
-        
-        Ok(query_heap.unpack())
+        Ok(())
     }
-    
+
+    /// # The Lazy Nearest Neighbor Query
+    /// An iterator over the points of the tree in increasing distance from the query point, for when you don't know `k` up front.
+    /// Nodes are only expanded when the next point could be under them, so taking a few neighbors is cheap, and taking them all
+    /// is a sort of the whole point cloud.
+    ///
+    /// The iterator reads the tree as of the refresh it was made after. If the writer refreshes before it's done, it stops
+    /// with a `TreeRefreshed` error, as what's left of the tree might not match what it has already returned.
+    ///
+    /// See `query_tools::LazyKnnHeap` and `NearestNeighbors`.
+    pub fn nearest_neighbors<'a>(&'a self, point: &[f32]) -> MalwareBrotResult<NearestNeighbors<'a, M>> {
+        let (epoch, root_address) = self.head.begin_read();
+        let mut query_heap = LazyKnnHeap::new(self.parameters.scale_base);
+        let root_center = self.parameters.point_cloud.get_point(root_address.1)?;
+        let dist_to_root = M::dense(root_center, point);
+        query_heap.push_nodes(&[root_address], &[dist_to_root]);
+        Ok(NearestNeighbors {
+            reader: self,
+            epoch,
+            point: Vec::from(point),
+            query_heap,
+            error: None,
+        })
+    }
+
+    /// # The Trace
+    /// The path a point would take if it were inserted into the tree. Starting at the root, we step to the closest child
+    /// whose scale `b^i` covers the point, until we hit a leaf or no child covers it. Each step records the node address, the
+    /// distance to the node's center, the node's radius, `cover_count`, and the summary of its singletons' labels.
+    ///
+    /// Use `TraceStep::to_json` to get a report.
+    pub fn trace(&self, point: &[f32]) -> MalwareBrotResult<Vec<TraceStep>> {
+        self.consistent(|root_address| {
+            let point_cloud = &self.parameters.point_cloud;
+            let mut trace = Vec::new();
+            let root_center = point_cloud.get_point(root_address.1)?;
+            let mut current = Some((root_address, M::dense(root_center, point)));
+
+            while let Some((address, dist)) = current.take() {
+                let node_info = self.get_node_and(address, |n| {
+                    let children = n.children().map(|(nested_scale, addresses)| {
+                        let mut children = vec![(nested_scale, address.1)];
+                        children.extend_from_slice(addresses);
+                        children
+                    });
+                    let step = TraceStep {
+                        address,
+                        dist,
+                        // Nodes that only cover their center have a radius of -inf
+                        radius: n.radius().max(0.0),
+                        cover_count: n.cover_count(),
+                        summary: n.singles_summary().cloned(),
+                    };
+                    (step, children)
+                });
+                let (step, children) = match node_info {
+                    Some(node_info) => node_info,
+                    None => break,
+                };
+                trace.push(step);
+
+                if let Some(children) = children {
+                    // The nested child has the same center, so we already have its distance.
+                    let mut dists = vec![dist];
+                    let child_centers: Vec<PointIndex> =
+                        children[1..].iter().map(|(_si, pi)| *pi).collect();
+                    dists.extend(point_cloud.distances_to_point(point, &child_centers)?);
+                    for (child_address, child_dist) in children.iter().zip(dists) {
+                        if child_dist <= self.scale(child_address.0) {
+                            match current {
+                                Some((_, d)) if d <= child_dist => {}
+                                _ => current = Some((*child_address, child_dist)),
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(trace)
+        })
+    }
+
+    /// # The Range Query
+    /// Finds all points within `radius` of the query point, sorted by distance.
+    ///
+    /// This starts at the root and visits every node that could cover a point in range. A node is skipped at push time if the
+    /// scale bound `b^i` puts all of its covered points out of range, and again when visited if the node's actual radius does.
+    /// Singletons are brute forced when their node is visited, just like the KNN.
+    ///
+    /// See `query_tools::RangeQueryHeap` and `nodes::CoverNode::range`.
+    pub fn range_query(&self, point: &[f32], radius: f32) -> MalwareBrotResult<Vec<(f32, PointIndex)>> {
+        self.consistent(|root_address| {
+            let mut query_heap = RangeQueryHeap::new(radius, self.parameters.scale_base);
+
+            let root_center = self.parameters.point_cloud.get_point(root_address.1)?;
+            let dist_to_root = M::dense(root_center, point);
+            query_heap.push_nodes(&[root_address], &[dist_to_root]);
+
+            while let Some((dist, address)) = query_heap.closest_unvisited_node_address() {
+                if let Some(res) = self.get_node_and(address, |n| {
+                    n.range(Some(dist), point, &self.parameters.point_cloud, &mut query_heap)
+                }) {
+                    res?;
+                }
+            }
+
+            Ok(query_heap.unpack())
+        })
+    }
+
     fn greedy_knn_nodes(&self, point: &[f32], query_heap: &mut KnnQueryHeap) {
         loop {
             if let Some((dist, nearest_address)) = query_heap.closest_unvisited_child_covering_address() {
@@ -218,162 +733,507 @@ impl<M: Metric> CoverTreeReader<M> {
         }
     }
 
+    fn filtered_greedy_knn_nodes<F: MetadataFilter>(
+        &self,
+        point: &[f32],
+        filter: &F,
+        query_heap: &mut KnnQueryHeap,
+    ) -> MalwareBrotResult<()> {
+        while let Some((dist, nearest_address)) = query_heap.closest_unvisited_child_covering_address() {
+            let children = self.get_node_and(nearest_address, |n| {
+                n.children().map(|(nested_scale, addresses)| {
+                    let mut children = vec![(nested_scale, nearest_address.1)];
+                    children.extend_from_slice(addresses);
+                    children
+                })
+            });
+            let subtrees_could_match: Vec<bool> = match children {
+                Some(Some(children)) => children
+                    .iter()
+                    .map(|address| self.subtree_could_match(*address, filter))
+                    .collect(),
+                _ => break,
+            };
+            if let Some(res) = self.get_node_and(nearest_address, |n| {
+                n.filtered_child_knn(
+                    Some(dist),
+                    point,
+                    &self.parameters.point_cloud,
+                    filter,
+                    &subtrees_could_match[..],
+                    query_heap,
+                )
+            }) {
+                res?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the filter against the node's subtree summary, true if there's no summary.
+    fn subtree_could_match<F: MetadataFilter>(&self, address: NodeAddress, filter: &F) -> bool {
+        self.get_node_and(address, |n| match n.subtree_summary() {
+            Some(summary) => filter.could_match(summary),
+            None => true,
+        })
+        .unwrap_or(true)
+    }
+
+    /// The `greedy_knn_nodes` with a budget. Returns false if the budget ran out before we hit a leaf.
+    fn budgeted_greedy_knn_nodes(
+        &self,
+        point: &[f32],
+        query_heap: &mut KnnQueryHeap,
+        budget: &mut KnnBudget,
+    ) -> MalwareBrotResult<bool> {
+        while let Some((dist, nearest_address)) = query_heap.closest_unvisited_child_covering_address() {
+            let children_len = self
+                .get_node_and(nearest_address, |n| n.children_len())
+                .unwrap_or(0);
+            if children_len == 0 {
+                break;
+            }
+            // The distance to the nested child is the distance to this node's center, which we already have.
+            if !budget.spend(children_len - 1) {
+                return Ok(false);
+            }
+            if let Some(res) = self.get_node_and(nearest_address, |n| {
+                n.child_knn(Some(dist), point, &self.parameters.point_cloud, query_heap)
+            }) {
+                res?;
+            }
+        }
+        Ok(true)
+    }
+
     /// Checks that there are no node addresses in the child list of any node that don't reference a node in the tree. 
     /// Please calmly panic if there are, the tree is very invalid.
     pub(crate) fn no_dangling_refs(&self) -> bool {
-        let mut refs_to_check = vec![self.root_address];
-        while let Some(node_addr) = refs_to_check.pop() {
-            if self
-                .get_node_and(node_addr, |n| {
-                    match n.children() {
-                        None => {}
-                        Some((nested_scale, other_children)) => {
-                            refs_to_check.push((nested_scale, node_addr.1));
-                            refs_to_check.extend(&other_children[..]);
-                        }
-                    };
-                })
-                .is_none()
-            {
-                return false;
+        self.consistent(|root_address| {
+            let mut refs_to_check = vec![root_address];
+            while let Some(node_addr) = refs_to_check.pop() {
+                if self
+                    .get_node_and(node_addr, |n| {
+                        match n.children() {
+                            None => {}
+                            Some((nested_scale, other_children)) => {
+                                refs_to_check.push((nested_scale, node_addr.1));
+                                refs_to_check.extend(&other_children[..]);
+                            }
+                        };
+                    })
+                    .is_none()
+                {
+                    return false;
+                }
             }
-        }
-        true
+            true
+        })
+    }
+
+    /// Brute force checks every cover tree invariant and reports each broken one, see the `validation` module. This is
+    /// slow, it's for tests and for checking a tree after loading it, before it's put to use.
+    pub fn validate(&self) -> MalwareBrotResult<ValidationReport> {
+        validation::validate(self, None)
+    }
+
+    /// `validate`, and also checks that each of these points is in the tree. A point that's in the point cloud but not
+    /// reached from the root is otherwise fine, as the tree might be built on a subset or have had points removed. Pass
+    /// the point cloud's `reference_indexes` for a tree that should hold all of them.
+    pub fn validate_points(&self, point_indexes: &[PointIndex]) -> MalwareBrotResult<ValidationReport> {
+        validation::validate(self, Some(point_indexes))
     }
 
+    /// Splits the children of the nodes in a cluster into clusters, grouped by the children's scale index. Returns the
+    /// clusters in order of their scale index, then their smallest center index.
     fn cluster_children(
         &self,
         si: i32,
         pis: &[PointIndex],
-    ) -> MalwareBrotResult<Vec<(usize, i32, Vec<PointIndex>)>> {
-        //println!("\tClustering children of {:?}", (si,pis));
-        let mut children_addresses = Vec::new();
+        max_radii: &HashMap<i32, f32>,
+    ) -> MalwareBrotResult<Vec<(i32, Vec<PointIndex>)>> {
+        let mut children_by_scale: BTreeMap<i32, Vec<PointIndex>> = BTreeMap::new();
         for pi in pis {
-            self.layer(si).get_node_children_and(&pi, |na, nas| {
-                children_addresses.push(na);
-                children_addresses.extend(nas);
+            self.layer(si).get_node_children_and(pi, |nested_address, children_addresses| {
+                for (csi, cpi) in Some(&nested_address).into_iter().chain(children_addresses) {
+                    children_by_scale.entry(*csi).or_insert_with(Vec::new).push(*cpi);
+                }
             });
         }
-        println!(
-            "\t There are {:?} total children, with an average spawn of {}",
-            children_addresses.len(),
-            (children_addresses.len() as f32) / (pis.len() as f32)
-        );
 
-        let mut scale_indexes: Vec<i32> = children_addresses.iter().map(|(si, _pi)| *si).collect();
-        ////println!("\t Child addresses are {:?}", children_addresses);
-        scale_indexes.dedup();
         let mut clusters = Vec::new();
-        for child_si in scale_indexes {
-            let unclustered: Vec<PointIndex> = children_addresses
-                .iter()
-                .filter(|(osi, _pi)| osi == &child_si)
-                .map(|(_si, pi)| *pi)
-                .collect();
-            //println!("\t\t[{}] Clustering on {:?} points", child_si, unclustered.len());
-            let mut components = self
-                .layer(child_si)
-                .get_components(unclustered, &self.parameters.point_cloud)?;
-            //println!("\t\t[{}] Obtained {:?} clusters", child_si, components);
-
-            while let Some((id, component)) = components.pop() {
-                clusters.push((id, child_si, component));
+        for (child_si, children) in children_by_scale.into_iter().rev() {
+            let max_radius = max_radii.get(&child_si).cloned().unwrap_or(0.0);
+            for component in self.overlap_components(child_si, children, max_radius)? {
+                clusters.push((child_si, component));
             }
         }
         Ok(clusters)
     }
-}
 
-/// 
-pub struct CoverTreeWriter<M: Metric> {
-    pub(crate) parameters: Arc<CoverTreeParameters<M>>,
-    pub(crate) layers: Vec<CoverLayerWriter>,
-    pub(crate) root_address: NodeAddress,
-}
+    /// The connected components of the overlap graph of the given nodes, all at scale index `si`. The overlapping
+    /// nodes are found with a search down the tree instead of computing all pairs of distances.
+    fn overlap_components(
+        &self,
+        si: i32,
+        mut unclustered: Vec<PointIndex>,
+        max_radius: f32,
+    ) -> MalwareBrotResult<Vec<Vec<PointIndex>>> {
+        unclustered.sort();
+        if unclustered.len() == 1 {
+            return Ok(vec![unclustered]);
+        }
+        let mut components = Vec::new();
+        let mut component_of: HashMap<PointIndex, Option<usize>> =
+            unclustered.iter().map(|pi| (*pi, None)).collect();
+        for start in &unclustered {
+            if component_of[start].is_some() {
+                continue;
+            }
+            let id = components.len();
+            component_of.insert(*start, Some(id));
+            let mut component = vec![*start];
+            let mut unvisited = vec![*start];
+            while let Some(pi) = unvisited.pop() {
+                let radius = self
+                    .get_node_and((si, pi), |n| n.radius().max(0.0))
+                    .ok_or(MalwareBrotError::NodeNotInTree((si, pi)))?;
+                let point = self.parameters.point_cloud.get_point(pi)?;
+                for other in self.overlapping_nodes(point, radius, si, max_radius)? {
+                    if let Some(slot @ None) = component_of.get_mut(&other) {
+                        *slot = Some(id);
+                        component.push(other);
+                        unvisited.push(other);
+                    }
+                }
+            }
+            component.sort();
+            components.push(component);
+        }
+        Ok(components)
+    }
 
-impl<M: Metric> CoverTreeWriter<M> {
-    #[doc(hidden)]
-    pub fn cluster(&mut self) -> MalwareBrotResult<()> {
-        let reader = self.reader();
-        let mut pending_clusters = vec![(0, self.root_address.0, vec![self.root_address.1])];
-        while let Some((i, si, pis)) = pending_clusters.pop() {
-            println!(
-                "Clustering children of cluster_id:{:?}, len: {}",
-                (i, si),
-                pis.len()
-            );
-            let mut children_clusters = reader.cluster_children(si, &pis[..])?;
-            let mut children_ids = Vec::new();
-            while let Some((id, nsi, npis)) = children_clusters.pop() {
-                pending_clusters.push((id, nsi, npis));
-                children_ids.push((nsi, id));
-            }
-            unsafe {self.layer(si).insert_cluster(
-                i,
-                CoverCluster {
-                    indexes: pis,
-                    children_ids,
-                },
-            );}
+    /// The centers of the nodes at scale index `si` whose balls overlap the ball of the given radius around the point.
+    /// `max_radius` has to bound the radius of every node at that scale index, it's used to prune the search.
+    fn overlapping_nodes(
+        &self,
+        point: &[f32],
+        radius: f32,
+        si: i32,
+        max_radius: f32,
+    ) -> MalwareBrotResult<Vec<PointIndex>> {
+        let point_cloud = &self.parameters.point_cloud;
+        let root_address = self.root_address();
+        let mut overlapping = Vec::new();
+        if root_address.0 < si {
+            return Ok(overlapping);
         }
-        Ok(())
+        let root_dist = point_cloud.distances_to_point(point, &[root_address.1])?[0];
+        let mut to_visit = vec![(root_dist, root_address)];
+        while let Some((dist, address)) = to_visit.pop() {
+            let (node_radius, children) = self
+                .get_node_and(address, |n| {
+                    (
+                        n.radius().max(0.0),
+                        n.children().map(|(nsi, c)| (nsi, c.to_vec())),
+                    )
+                })
+                .ok_or(MalwareBrotError::NodeNotInTree(address))?;
+            if address.0 == si {
+                if dist < radius + node_radius {
+                    overlapping.push(address.1);
+                }
+                continue;
+            }
+            // Every node below this one has its center within the node's radius of this center.
+            if dist - node_radius >= radius + max_radius {
+                continue;
+            }
+            if let Some((nested_si, children_addresses)) = children {
+                if nested_si >= si {
+                    to_visit.push((dist, (nested_si, address.1)));
+                }
+                let deep_enough: Vec<NodeAddress> = children_addresses
+                    .into_iter()
+                    .filter(|(csi, _)| *csi >= si)
+                    .collect();
+                let indexes: Vec<PointIndex> = deep_enough.iter().map(|(_, pi)| *pi).collect();
+                let distances = point_cloud.distances_to_point(point, &indexes)?;
+                to_visit.extend(distances.into_iter().zip(deep_enough));
+            }
+        }
+        Ok(overlapping)
     }
 
-    /// Provides a reference to a `CoverLayerWriter`. Do not use, unless you're going to leave the tree in a *valid* state.
-    pub(crate) unsafe fn layer(&mut self, scale_index: i32) -> &mut CoverLayerWriter {
-        &mut self.layers[self.parameters.internal_index(scale_index)]
+    /// Read only access to a cluster, see `CoverTreeWriter::cluster`.
+    pub fn get_cluster_and<F, T>(&self, cluster_address: ClusterAddress, f: F) -> Option<T>
+    where
+        F: FnOnce(&CoverCluster) -> T,
+    {
+        self.layer(cluster_address.0)
+            .get_cluster_and(&cluster_address.1, |c| f(c))
     }
 
-    /// Creates a reader for queries.
-    pub fn reader(&self) -> CoverTreeReader<M> {
-        CoverTreeReader {
-            parameters: Arc::clone(&self.parameters),
-            layers: self.layers.iter().map(|l| l.reader()).collect(),
-            root_address: self.root_address,
-        }
+    /// The cluster containing only the root, the top of the cluster tree. This is `None` if the tree hasn't been clustered.
+    pub fn root_cluster_address(&self) -> Option<ClusterAddress> {
+        self.consistent(|root_address| {
+            let address = (root_address.0, 0);
+            self.get_cluster_and(address, |_c| address)
+        })
     }
+}
 
-    pub(crate) unsafe fn insert_raw(
-        &mut self,
-        scale_index: i32,
-        point_index: PointIndex,
-        node: CoverNode,
-    ) {
-        self.layers[self.parameters.internal_index(scale_index)].insert_raw(point_index, node);
+/// A single step of a `CoverTreeReader::trace`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceStep {
+    /// The node this step is at
+    pub address: NodeAddress,
+    /// The distance from the traced point to the node's center
+    pub dist: f32,
+    /// The radius of the node
+    pub radius: f32,
+    /// The number of points the node covers
+    pub cover_count: usize,
+    /// The summary of the labels of the node's singletons
+    pub summary: Option<MetaSummary>,
+}
+
+impl TraceStep {
+    /// Encodes this into a compact json object. Distances and radii that aren't finite, like the `-inf` radius of an
+    /// empty leaf, are written as `null`.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("a trace step always serializes")
     }
+}
 
-    /// Loads a tree from a protobuf. There's a `load_tree` in `utils` that handles loading from a path to a protobuf file.
-    pub fn load(
-        cover_proto: &CoreProto,
-        point_cloud: PointCloud<M>,
-    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
-        let parameters = Arc::new(CoverTreeParameters {
-            total_nodes: atomic::AtomicUsize::new(0),
-            use_singletons: cover_proto.use_singletons,
-            scale_base: cover_proto.scale_base as f32,
-            cutoff: cover_proto.cutoff as usize,
-            resolution: cover_proto.resolution as i32,
-            cluster_min: 5,
-            point_cloud,
-            verbosity: 2,
-        });
-        let root_address = (cover_proto.get_root_scale(), cover_proto.get_root_index());
-        let layers = cover_proto
-            .get_layers()
-            .par_iter()
-            .map(|l| CoverLayerWriter::load(l))
-            .collect();
+/// Iterator over the points of a tree in increasing distance from a query point, see `CoverTreeReader::nearest_neighbors`.
+///
+/// If there's an error reading the point cloud, or the tree is refreshed under it, the iteration stops early and the error
+/// can be taken with `take_error`.
+pub struct NearestNeighbors<'a, M: Metric> {
+    reader: &'a CoverTreeReader<M>,
+    epoch: usize,
+    point: Vec<f32>,
+    query_heap: LazyKnnHeap,
+    error: Option<MalwareBrotError>,
+}
 
-        Ok(CoverTreeWriter {
-            parameters,
-            layers,
-            root_address,
-        })
+impl<'a, M: Metric> NearestNeighbors<'a, M> {
+    /// Takes the error that stopped the iteration, if there was one.
+    pub fn take_error(&mut self) -> Option<MalwareBrotError> {
+        self.error.take()
     }
+}
 
-    /// Encodes the tree into a protobuf. See `utils::save_tree` for saving to a file on disk.
-    pub fn save(&self) -> CoreProto {
-        let mut cover_proto = CoreProto::new();
+impl<'a, M: Metric> Iterator for NearestNeighbors<'a, M> {
+    type Item = (f32, PointIndex);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        loop {
+            if let Some(nbr) = self.query_heap.closest_settled_point() {
+                if !self.reader.head.end_read(self.epoch) {
+                    self.error = Some(MalwareBrotError::TreeRefreshed);
+                    return None;
+                }
+                return Some(nbr);
+            }
+            let (dist, address) = self.query_heap.closest_unexpanded_node_address()?;
+            let point = &self.point;
+            let query_heap = &mut self.query_heap;
+            let point_cloud = &self.reader.parameters.point_cloud;
+            if let Some(Err(e)) = self
+                .reader
+                .get_node_and(address, |n| n.lazy_knn(Some(dist), point, point_cloud, query_heap))
+            {
+                self.error = Some(e);
+                return None;
+            }
+        }
+    }
+}
+
+/// Tracks the spending of an approximate KNN query against its budget.
+struct KnnBudget<'a> {
+    parameters: &'a ApproximateKnnParameters,
+    nodes_visited: usize,
+    distance_evaluations: usize,
+}
+
+impl<'a> KnnBudget<'a> {
+    /// Spends a node visit that costs `distances` distance evaluations. Returns false, and spends nothing, if that's over budget.
+    fn spend(&mut self, distances: usize) -> bool {
+        let over_nodes = self
+            .parameters
+            .max_nodes
+            .map(|m| self.nodes_visited + 1 > m)
+            .unwrap_or(false);
+        let over_distances = self
+            .parameters
+            .max_distances
+            .map(|m| self.distance_evaluations + distances > m)
+            .unwrap_or(false);
+        if over_nodes || over_distances {
+            false
+        } else {
+            self.nodes_visited += 1;
+            self.distance_evaluations += distances;
+            true
+        }
+    }
+}
+
+/// The nodes touched by a batch of inserts or removals, before they're written to the layers. Removed nodes are `None`.
+struct StagedNodes {
+    root_address: NodeAddress,
+    nodes: HashMap<NodeAddress, Option<CoverNode>>,
+}
+
+/// Wraps an error from after points were appended to the point cloud, so the caller knows they're only there.
+fn not_inserted(point_indexes: &[PointIndex], error: MalwareBrotError) -> MalwareBrotError {
+    if point_indexes.is_empty() {
+        error
+    } else {
+        MalwareBrotError::PointsNotInserted {
+            point_indexes: point_indexes.to_vec(),
+            error: Box::new(error),
+        }
+    }
+}
+
+/// Where a point lives in the tree.
+enum PointHome {
+    /// It's a singleton of this node
+    Singleton(NodeAddress),
+    /// It's the center of this node, and its nested children
+    Center(NodeAddress),
+}
+
+/// 
+pub struct CoverTreeWriter<M: Metric> {
+    pub(crate) parameters: Arc<CoverTreeParameters<M>>,
+    pub(crate) layers: Vec<CoverLayerWriter>,
+    pub(crate) root_address: NodeAddress,
+    head: Arc<TreeHead>,
+}
+
+impl<M: Metric> CoverTreeWriter<M> {
+    pub(crate) fn new(
+        parameters: Arc<CoverTreeParameters<M>>,
+        layers: Vec<CoverLayerWriter>,
+        root_address: NodeAddress,
+    ) -> CoverTreeWriter<M> {
+        let head = Arc::new(TreeHead::new(root_address, &layers));
+        CoverTreeWriter {
+            parameters,
+            layers,
+            root_address,
+            head,
+        }
+    }
+
+    /// Clusters every layer of the tree. Starting from the root, the children of the nodes of a cluster are grouped by
+    /// scale index and each group is split into the connected components of overlapping node balls. These are the
+    /// cluster's children, and are linked through `CoverCluster::children_ids`. The root's cluster is
+    /// `CoverTreeReader::root_cluster_address`.
+    ///
+    /// The overlapping nodes are found by searching the tree, so this scales with the size of the tree rather than with
+    /// the square of the size of the layers. Any old clusters are thrown away first. Clusters aren't kept up to date by
+    /// `insert` and `remove`, run this again after those.
+    pub fn cluster(&mut self) -> MalwareBrotResult<()> {
+        let reader = self.reader();
+        let mut max_radii: HashMap<i32, f32> = HashMap::new();
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|_pi, n| {
+                let max_radius = max_radii.entry(*n.scale_index()).or_insert(0.0);
+                *max_radius = max_radius.max(n.radius());
+            });
+        }
+        for layer in self.layers.iter_mut() {
+            layer.clear_clusters();
+        }
+
+        let root_id = unsafe { self.layer(self.root_address.0).next_cluster_id() };
+        let mut pending_clusters = vec![(self.root_address.0, root_id, vec![self.root_address.1])];
+        while let Some((si, id, pis)) = pending_clusters.pop() {
+            let children_clusters = reader.cluster_children(si, &pis[..], &max_radii)?;
+            let mut children_ids = Vec::with_capacity(children_clusters.len());
+            for (child_si, child_pis) in children_clusters {
+                let child_id = unsafe { self.layer(child_si).next_cluster_id() };
+                children_ids.push((child_si, child_id));
+                pending_clusters.push((child_si, child_id, child_pis));
+            }
+            unsafe {
+                self.layer(si).insert_cluster(
+                    id,
+                    CoverCluster {
+                        indexes: pis,
+                        children_ids,
+                    },
+                );
+            }
+        }
+        self.refresh();
+        Ok(())
+    }
+
+    /// Provides a reference to a `CoverLayerWriter`. Do not use, unless you're going to leave the tree in a *valid* state.
+    pub(crate) unsafe fn layer(&mut self, scale_index: i32) -> &mut CoverLayerWriter {
+        &mut self.layers[self.parameters.internal_index(scale_index)]
+    }
+
+    /// Creates a reader for queries. It sees the tree as of the last `refresh`, and follows later refreshes.
+    pub fn reader(&self) -> CoverTreeReader<M> {
+        CoverTreeReader::new(Arc::clone(&self.parameters), Arc::clone(&self.head))
+    }
+
+    pub(crate) unsafe fn insert_raw(
+        &mut self,
+        scale_index: i32,
+        point_index: PointIndex,
+        node: CoverNode,
+    ) {
+        self.layers[self.parameters.internal_index(scale_index)].insert_raw(point_index, node);
+    }
+
+    /// Loads a tree from a protobuf. There's a `load_tree` in `utils` that handles loading from a path to a protobuf file.
+    pub fn load(
+        cover_proto: &CoreProto,
+        point_cloud: PointCloud<M>,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let node_count = cover_proto.get_layers().iter().map(|l| l.get_nodes().len()).sum();
+        let summarize_subtrees = cover_proto
+            .get_layers()
+            .iter()
+            .flat_map(|l| l.get_nodes())
+            .any(|n| !n.get_subtree_summary_json().is_empty());
+        let parameters = Arc::new(CoverTreeParameters {
+            total_nodes: atomic::AtomicUsize::new(node_count),
+            use_singletons: cover_proto.use_singletons,
+            scale_base: cover_proto.scale_base as f32,
+            cutoff: cover_proto.cutoff as usize,
+            resolution: cover_proto.resolution as i32,
+            cluster_min: 5,
+            point_cloud: Arc::new(point_cloud),
+            verbosity: 2,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees,
+        });
+        let root_address = (cover_proto.get_root_scale(), cover_proto.get_root_index());
+        let layers = cover_proto
+            .get_layers()
+            .par_iter()
+            .map(|l| CoverLayerWriter::load(l))
+            .collect();
+
+        Ok(CoverTreeWriter::new(parameters, layers, root_address))
+    }
+
+    /// Encodes the tree into a protobuf. See `utils::save_tree` for saving to a file on disk.
+    pub fn save(&self) -> CoreProto {
+        let mut cover_proto = CoreProto::new();
         cover_proto.set_scale_base(self.parameters.scale_base);
         cover_proto.set_cutoff(self.parameters.cutoff as u64);
         cover_proto.set_resolution(self.parameters.resolution);
@@ -386,17 +1246,1104 @@ impl<M: Metric> CoverTreeWriter<M> {
         cover_proto
     }
 
-    /// Swaps the maps on each layer so that any `CoverTreeReaders` see the updated tree. 
+    /// Swaps the maps on each layer and publishes the root so that any `CoverTreeReaders` see the updated tree. 
     /// Only call once you have a valid tree.
+    ///
+    /// The layers and the root are published together, queries that overlap a refresh are run again on the new tree.
     pub fn refresh(&mut self) {
-        self.layers.par_iter_mut().for_each(|l| l.refresh());
+        self.head.publish(self.root_address, &mut self.layers);
+    }
+
+    /// Computes the `subtree_summary` of every node, from the bottom up, and refreshes. The builder only does this if
+    /// `CoverTreeBuilder::summarize_subtrees` is set. The summaries are saved with the tree.
+    pub fn summarize_subtrees(&mut self) -> MalwareBrotResult<()> {
+        let reader = self.reader();
+        let mut nodes: Vec<CoverNode> = Vec::with_capacity(reader.node_count());
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|_pi, n| nodes.push(n.clone()));
+        }
+        nodes.sort_by_key(|n| (*n.scale_index(), *n.center_index()));
+        let mut summaries: HashMap<NodeAddress, Option<MetaSummary>> = HashMap::with_capacity(nodes.len());
+        for mut node in nodes {
+            let address = (*node.scale_index(), *node.center_index());
+            let children_summaries: Vec<Option<MetaSummary>> = match node.children() {
+                Some((nested_scale, children)) => std::iter::once(&(nested_scale, address.1))
+                    .chain(children)
+                    .map(|child| summaries.get(child).cloned().unwrap_or(None))
+                    .collect(),
+                None => Vec::new(),
+            };
+            node.update_subtree_summary(&children_summaries[..], &self.parameters.point_cloud)?;
+            summaries.insert(address, node.subtree_summary().cloned());
+            unsafe { self.insert_raw(address.0, address.1, node) };
+        }
+        self.refresh();
+        Ok(())
+    }
+
+    /// Inserts a point from the point cloud into the tree, see `insert_batch`.
+    pub fn insert(&mut self, point_index: PointIndex) -> MalwareBrotResult<()> {
+        self.insert_batch(&[point_index])
+    }
+
+    /// Inserts points from the point cloud into the tree. The points shouldn't already be in the tree.
+    ///
+    /// Each point descends from the root to the closest child that covers it, updating the `cover_count` and radius of
+    /// the nodes on the way. Where no child covers it, it becomes a singleton, or a new leaf if we're not using singletons.
+    /// If it's within the child scale of a singleton, that singleton is promoted to a child node that covers both. Leaves that
+    /// grow past the cutoff are split like the builder would. A point that's outside the root's scale gets a new root that
+    /// has the old root as its nested child.
+    ///
+    /// The modified nodes are held back until the whole batch is placed, so if a point can't be inserted nothing is written.
+    /// Then they're written and published with `refresh`.
+    pub fn insert_batch(&mut self, point_indexes: &[PointIndex]) -> MalwareBrotResult<()> {
+        let mut staged = StagedNodes {
+            root_address: self.root_address,
+            nodes: HashMap::new(),
+        };
+        for pi in point_indexes {
+            self.stage_insert(*pi, &mut staged)?;
+        }
+        self.write_staged(staged)?;
+        self.refresh();
+        Ok(())
+    }
+
+    /// Appends a point to the point cloud and inserts it into the tree, returns its new index. See `push_batch`.
+    pub fn push(&mut self, point: &[f32], metadata: Metadata) -> MalwareBrotResult<PointIndex> {
+        let point_index = self.parameters.point_cloud.push(point, metadata)?;
+        self.insert(point_index)
+            .map_err(|e| not_inserted(&[point_index], e))?;
+        Ok(point_index)
+    }
+
+    /// Appends a batch of points to the point cloud and inserts them into the tree, returns their new indexes.
+    /// See `PointCloud::push_batch`.
+    ///
+    /// The point cloud checks the whole batch before it takes any of it, so a bad batch leaves both untouched. The
+    /// points can't be taken back out of the point cloud though, so if they can't be inserted after that the error is a
+    /// `PointsNotInserted` with their indexes.
+    pub fn push_batch(
+        &mut self,
+        points: &[f32],
+        metadata: Vec<Metadata>,
+        names: Option<Vec<PointName>>,
+    ) -> MalwareBrotResult<Vec<PointIndex>> {
+        let point_indexes = self
+            .parameters
+            .point_cloud
+            .push_batch(points, metadata, names)?;
+        self.insert_batch(&point_indexes)
+            .map_err(|e| not_inserted(&point_indexes, e))?;
+        Ok(point_indexes)
+    }
+
+    /// Merges two trees, like ones built in parallel on shards of a dataset, see `merge_from`. The tree that covers fewer
+    /// points is merged into the larger one, which is returned.
+    pub fn merge(
+        first: CoverTreeWriter<M>,
+        second: CoverTreeWriter<M>,
+    ) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let first_count = first
+            .reader()
+            .get_node_and(first.root_address, |n| n.coverage());
+        let second_count = second
+            .reader()
+            .get_node_and(second.root_address, |n| n.coverage());
+        let (mut larger, smaller) = if first_count >= second_count {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        larger.merge_from(&smaller.reader())?;
+        Ok(larger)
+    }
+
+    /// Moves the points and nodes of another tree into this one, instead of rebuilding from scratch. Both trees have to use
+    /// the same `scale_base` and dimension, and shouldn't have any points in common.
+    ///
+    /// The other tree's points are appended to our point cloud with `PointCloud::push_batch`. They keep their names, unless
+    /// we already have a point with that name, in which case they get the default name. If both trees were built on the
+    /// same point cloud, like with `build_subset`, nothing is appended and the points keep their indexes. Then the other tree's nodes are
+    /// grafted in from its root down. A node whose ball fits inside one of ours, and whose center is far enough from that
+    /// node's children, goes in whole, with everything under it, as a child of the deepest such node. It's moved to that
+    /// node's nested scale. So shards that are far apart go in whole, and overlapping ones are broken up until the pieces
+    /// fit between our nodes. Nodes that don't fit anywhere are split
+    /// into their children and singletons, and the points of leaves that don't fit are inserted like `insert_batch`.
+    /// If the other tree is outside our root, a new root is put above it first.
+    ///
+    /// Like `insert_batch`, the nodes are held back until the merge is done and then published with `refresh`. The other
+    /// tree's points are all read and checked against our label scheme before any are appended. If the nodes can't be
+    /// grafted after that, the error is a `PointsNotInserted` with the appended points, like `push_batch`.
+    pub fn merge_from(&mut self, other: &CoverTreeReader<M>) -> MalwareBrotResult<()> {
+        let point_cloud = &self.parameters.point_cloud;
+        let other_point_cloud = other.point_cloud();
+        if point_cloud.dim() != other_point_cloud.dim() {
+            return Err(MalwareBrotError::DimensionMismatch {
+                expected: point_cloud.dim(),
+                found: other_point_cloud.dim(),
+            });
+        }
+        if self.parameters.scale_base != other.parameters().scale_base {
+            return Err(MalwareBrotError::ScaleBaseMismatch {
+                expected: self.parameters.scale_base,
+                found: other.parameters().scale_base,
+            });
+        }
+
+        let mut other_points = Vec::new();
+        let mut stack = vec![other.root_address()];
+        while let Some(address) = stack.pop() {
+            other.get_node_and(address, |n| {
+                other_points.extend_from_slice(n.singletons());
+                match n.children() {
+                    Some((nested_scale, addresses)) => {
+                        stack.push((nested_scale, address.1));
+                        stack.extend_from_slice(addresses);
+                    }
+                    None => other_points.push(address.1),
+                }
+            });
+        }
+
+        let mut point_map = HashMap::with_capacity(other_points.len());
+        let mut appended = Vec::new();
+        if Arc::ptr_eq(point_cloud, &other.parameters().point_cloud) {
+            // Trees built on subsets of one point cloud already share their points.
+            point_map.extend(other_points.iter().map(|pi| (*pi, *pi)));
+        } else {
+            let other_name = |pi: &PointIndex| match other_point_cloud.get_name(pi) {
+                Some(name) => Some(name.clone()),
+                None => other_point_cloud.get_appended_name(pi),
+            };
+            let (named, unnamed): (Vec<PointIndex>, Vec<PointIndex>) =
+                other_points.iter().partition(|pi| {
+                    other_name(pi)
+                        .map(|name| {
+                            point_cloud.get_index(&name).is_none()
+                                && point_cloud.get_appended_index(&name).is_none()
+                        })
+                        .unwrap_or(false)
+                });
+            let mut batches = Vec::with_capacity(2);
+            for (batch, keep_names) in [(named, true), (unnamed, false)] {
+                if batch.is_empty() {
+                    continue;
+                }
+                let mut points = Vec::with_capacity(batch.len() * point_cloud.dim());
+                let mut metadata = Vec::with_capacity(batch.len());
+                for pi in &batch {
+                    points.extend_from_slice(other_point_cloud.get_point(*pi)?);
+                    let label = other_point_cloud.get_metadata(*pi)?;
+                    point_cloud.check_metadata(&label)?;
+                    metadata.push(label);
+                }
+                let names = if keep_names {
+                    Some(batch.iter().filter_map(other_name).collect())
+                } else {
+                    None
+                };
+                batches.push((batch, points, metadata, names));
+            }
+            for (batch, points, metadata, names) in batches {
+                let point_indexes = point_cloud
+                    .push_batch(&points, metadata, names)
+                    .map_err(|e| not_inserted(&appended, e.into()))?;
+                appended.extend_from_slice(&point_indexes);
+                point_map.extend(batch.into_iter().zip(point_indexes));
+            }
+        }
+
+        let mut staged = StagedNodes {
+            root_address: self.root_address,
+            nodes: HashMap::new(),
+        };
+        self.stage_graft(other, other.root_address(), &point_map, &mut staged)
+            .and_then(|()| self.write_staged(staged))
+            .map_err(|e| not_inserted(&appended, e))?;
+        self.refresh();
+        Ok(())
+    }
+
+    /// Removes a point from the tree, see `remove_batch`.
+    pub fn remove(&mut self, point_index: PointIndex) -> MalwareBrotResult<()> {
+        self.remove_batch(&[point_index])
+    }
+
+    /// Removes points from the tree. The points stay in the point cloud, but queries won't find them.
+    ///
+    /// A singleton is just dropped from its node. If the point is the center of a node, a point close to it from the
+    /// bottom of the chain of nested nodes that share the center is promoted to take its place, on every node of the
+    /// chain. The nodes' other children and singletons stay under the new centers as they are. A child whose center is
+    /// too close to another child on its scale is taken apart into its own children and singletons, which stay instead.
+    /// Only the points that are now further than a node's scale from its new center are put back in from the root.
+    /// Removing the root's center promotes a new root the same way, with its scale raised to cover everything. The
+    /// `cover_count` of the nodes above is reduced, but their radius isn't shrunk, so it's still an upper bound.
+    ///
+    /// Like `insert_batch`, nothing is written until the whole batch is done, then `refresh` publishes the changes.
+    pub fn remove_batch(&mut self, point_indexes: &[PointIndex]) -> MalwareBrotResult<()> {
+        let mut staged = StagedNodes {
+            root_address: self.root_address,
+            nodes: HashMap::new(),
+        };
+        for pi in point_indexes {
+            self.stage_remove(*pi, &mut staged)?;
+        }
+        self.write_staged(staged)?;
+        self.refresh();
+        Ok(())
+    }
+
+    /// Read only access to a node, from the staged nodes or as of the last refresh.
+    fn staged_node_and<F, T>(
+        &self,
+        staged: &StagedNodes,
+        address: NodeAddress,
+        f: F,
+    ) -> MalwareBrotResult<T>
+    where
+        F: FnOnce(&CoverNode) -> T,
+    {
+        match staged.nodes.get(&address) {
+            Some(Some(node)) => Ok(f(node)),
+            Some(None) => Err(MalwareBrotError::NodeNotInTree(address)),
+            None => self
+                .layers
+                .get(self.parameters.internal_index(address.0))
+                .and_then(|l| l.get_node_and(&address.1, f))
+                .ok_or(MalwareBrotError::NodeNotInTree(address)),
+        }
+    }
+
+    /// Grabs a node to modify, from the staged nodes or as of the last refresh.
+    fn staged_node<'a>(
+        &self,
+        staged: &'a mut StagedNodes,
+        address: NodeAddress,
+    ) -> MalwareBrotResult<&'a mut CoverNode> {
+        if !staged.nodes.contains_key(&address) {
+            let node = self.staged_node_and(staged, address, |n| n.clone())?;
+            staged.nodes.insert(address, Some(node));
+        }
+        staged
+            .nodes
+            .get_mut(&address)
+            .and_then(|n| n.as_mut())
+            .ok_or(MalwareBrotError::NodeNotInTree(address))
+    }
+
+    /// Builds a new subtree with the builder and stages its nodes.
+    fn stage_subtree(
+        &self,
+        address: NodeAddress,
+        indexes: Vec<PointIndex>,
+        staged: &mut StagedNodes,
+    ) -> MalwareBrotResult<()> {
+        for node in build_subtree(&self.parameters, address, indexes)? {
+            staged
+                .nodes
+                .insert((*node.scale_index(), *node.center_index()), Some(node));
+        }
+        Ok(())
+    }
+
+    fn stage_insert(&self, point_index: PointIndex, staged: &mut StagedNodes) -> MalwareBrotResult<()> {
+        let parameters = &self.parameters;
+        let point_cloud = &parameters.point_cloud;
+        let point = point_cloud.get_point(point_index)?;
+        let mut address = staged.root_address;
+        let mut dist = M::dense(point_cloud.get_point(address.1)?, point);
+        if dist > parameters.scale_base.powi(address.0) {
+            return self.stage_new_root(point_index, dist, staged);
+        }
+
+        loop {
+            let node = self.staged_node(staged, address)?;
+            node.set_radius(node.radius().max(dist));
+            let (nested_scale, child_addresses) = match node.children() {
+                Some((nested_scale, child_addresses)) => (nested_scale, Vec::from(child_addresses)),
+                None => {
+                    node.insert_singleton(point_index);
+                    if node.singleton_len() + 1 > parameters.cutoff && address.0 >= parameters.resolution {
+                        let indexes = Vec::from(node.singletons());
+                        return self.stage_subtree(address, indexes, staged);
+                    }
+                    return node.update_metasummary(point_cloud);
+                }
+            };
+
+            // The nested child has the same center, so we already have its distance.
+            let child_scale = parameters.scale_base.powi(nested_scale);
+            let child_centers: Vec<PointIndex> = child_addresses.iter().map(|(_si, pi)| *pi).collect();
+            let child_dists = point_cloud.distances_to_point(point, &child_centers)?;
+            let closest_child = std::iter::once(((nested_scale, address.1), dist))
+                .chain(child_addresses.iter().cloned().zip(child_dists))
+                .filter(|(ca, d)| *d <= parameters.scale_base.powi(ca.0))
+                .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            if let Some((child_address, child_dist)) = closest_child {
+                node.set_cover_count(node.cover_count() + 1);
+                address = child_address;
+                dist = child_dist;
+                continue;
+            }
+
+            let singletons = Vec::from(node.singletons());
+            let singleton_dists = point_cloud.distances_to_point(point, &singletons)?;
+            let closest_singleton = singletons
+                .iter()
+                .zip(singleton_dists)
+                .filter(|(_pi, d)| *d <= child_scale)
+                .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            if let Some((singleton, _d)) = closest_singleton {
+                // The new point is far enough from the other children to be one, the singleton might not be.
+                node.remove_singleton(*singleton);
+                node.insert_child((nested_scale, point_index), 2)?;
+                node.update_metasummary(point_cloud)?;
+                return self.stage_subtree((nested_scale, point_index), vec![*singleton], staged);
+            } else if parameters.use_singletons {
+                node.insert_singleton(point_index);
+                return node.update_metasummary(point_cloud);
+            } else {
+                node.insert_child((nested_scale, point_index), 1)?;
+                return self.stage_subtree((nested_scale, point_index), vec![], staged);
+            }
+        }
+    }
+
+    /// The point is too far from the root, so we put a new root above the old one that covers both.
+    fn stage_new_root(
+        &self,
+        point_index: PointIndex,
+        dist: f32,
+        staged: &mut StagedNodes,
+    ) -> MalwareBrotResult<()> {
+        let parameters = &self.parameters;
+        let old_address = staged.root_address;
+        let scale_index = (dist.log(parameters.scale_base).ceil() as i32).max(old_address.0 + 1);
+        self.stage_raise_root(scale_index, staged)?;
+
+        let root = self.staged_node(staged, staged.root_address)?;
+        root.set_radius(root.radius().max(dist));
+        if parameters.use_singletons {
+            root.insert_singleton(point_index);
+            root.update_metasummary(&parameters.point_cloud)
+        } else {
+            root.insert_child((old_address.0, point_index), 1)?;
+            self.stage_subtree((old_address.0, point_index), vec![], staged)
+        }
+    }
+
+    /// Puts a new root at the scale index above the old one, with the old root as its only child.
+    fn stage_raise_root(&self, scale_index: i32, staged: &mut StagedNodes) -> MalwareBrotResult<()> {
+        let old_address = staged.root_address;
+        let (old_radius, old_coverage) =
+            self.staged_node_and(staged, old_address, |n| (n.radius(), n.coverage()))?;
+        let address = (scale_index, old_address.1);
+
+        let mut root = CoverNode::new(address);
+        root.set_radius(old_radius);
+        root.insert_nested_child(old_address.0, old_coverage)?;
+        root.update_metasummary(&self.parameters.point_cloud)?;
+        staged.nodes.insert(address, Some(root));
+        staged.root_address = address;
+        Ok(())
+    }
+
+    /// Moves a node of another tree, and everything under it, into this tree. The node is grafted in whole if it fits under
+    /// one of our nodes, otherwise it's split up into its children and singletons. `point_map` takes the other tree's point
+    /// indexes to ours.
+    fn stage_graft(
+        &self,
+        other: &CoverTreeReader<M>,
+        address: NodeAddress,
+        point_map: &HashMap<PointIndex, PointIndex>,
+        staged: &mut StagedNodes,
+    ) -> MalwareBrotResult<()> {
+        let (radius, coverage, children, singletons) = other
+            .get_node_and(address, |n| {
+                (
+                    n.radius().max(0.0),
+                    n.coverage(),
+                    n.children().map(|(nested_scale, addresses)| (nested_scale, Vec::from(addresses))),
+                    Vec::from(n.singletons()),
+                )
+            })
+            .ok_or(MalwareBrotError::NodeNotInTree(address))?;
+        let center = point_map[&address.1];
+        let nested_scale = children.as_ref().map(|(nested_scale, _)| *nested_scale);
+
+        match self.graft_location(center, radius, nested_scale, staged)? {
+            Some((path, scale_index)) => {
+                let mut stack = vec![(address, scale_index)];
+                while let Some((other_address, scale_index)) = stack.pop() {
+                    let node = other
+                        .get_node_and(other_address, |n| {
+                            if let Some((nested_scale, addresses)) = n.children() {
+                                stack.push(((nested_scale, other_address.1), nested_scale));
+                                stack.extend(addresses.iter().map(|a| (*a, a.0)));
+                            }
+                            n.remapped(scale_index, point_map)
+                        })
+                        .ok_or(MalwareBrotError::NodeNotInTree(other_address))?;
+                    staged
+                        .nodes
+                        .insert((scale_index, point_map[&other_address.1]), Some(node));
+                }
+
+                let parent_index = path.len() - 1;
+                for (i, (ancestor, dist)) in path.into_iter().enumerate() {
+                    let node = self.staged_node(staged, ancestor)?;
+                    node.set_radius(node.radius().max(dist + radius));
+                    if i == parent_index {
+                        node.insert_child((scale_index, center), coverage)?;
+                    } else {
+                        node.set_cover_count(node.cover_count() + coverage);
+                    }
+                }
+                Ok(())
+            }
+            None => {
+                for pi in singletons {
+                    self.stage_insert(point_map[&pi], staged)?;
+                }
+                match children {
+                    Some((nested_scale, addresses)) => {
+                        self.stage_graft(other, (nested_scale, address.1), point_map, staged)?;
+                        for child_address in addresses {
+                            self.stage_graft(other, child_address, point_map, staged)?;
+                        }
+                        Ok(())
+                    }
+                    None => self.stage_insert(center, staged),
+                }
+            }
+        }
+    }
+
+    /// Finds where a subtree with this center and radius can be grafted in. It goes in as a child of the deepest node whose
+    /// ball, and the balls of all the nodes above it, contain the subtree's ball. The subtree's root is moved to that node's
+    /// nested scale, which has to cover its radius and be above the scale of its own children, if it has any. Like a
+    /// new child made by the builder, its center has to be further than that scale from the centers of the other children.
+    ///
+    /// Returns the path down to the new parent with the distances to their centers, and the scale index to graft at.
+    /// If the subtree is outside the root, the root is raised to cover it first.
+    fn graft_location(
+        &self,
+        center: PointIndex,
+        radius: f32,
+        nested_scale: Option<i32>,
+        staged: &mut StagedNodes,
+    ) -> MalwareBrotResult<Option<(Vec<(NodeAddress, f32)>, i32)>> {
+        let point_cloud = &self.parameters.point_cloud;
+        let scale_base = self.parameters.scale_base;
+        let point = point_cloud.get_point(center)?;
+        let mut address = staged.root_address;
+        let mut dist = M::dense(point_cloud.get_point(address.1)?, point);
+        if dist + radius > scale_base.powi(address.0) {
+            let scale_index = ((dist + radius).log(scale_base).ceil() as i32).max(address.0 + 1);
+            self.stage_raise_root(scale_index, staged)?;
+            address = staged.root_address;
+        }
+
+        let mut path = Vec::new();
+        loop {
+            let children = self.staged_node_and(staged, address, |n| {
+                n.children()
+                    .map(|(child_scale, addresses)| (child_scale, Vec::from(addresses)))
+            })?;
+            let (child_scale_index, child_addresses) = match children {
+                Some((child_scale_index, child_addresses))
+                    if radius <= scale_base.powi(child_scale_index)
+                        && nested_scale.map(|ns| ns < child_scale_index).unwrap_or(true) =>
+                {
+                    (child_scale_index, child_addresses)
+                }
+                // Either this is the root, or we came down into this node because it contains the subtree's ball. In
+                // that case the subtree can't go in next to it either, they'd be too close.
+                _ => return Ok(None),
+            };
+            path.push((address, dist));
+
+            // The nested child has the same center, so we already have its distance.
+            let child_scale = scale_base.powi(child_scale_index);
+            let child_centers: Vec<PointIndex> = child_addresses.iter().map(|(_si, pi)| *pi).collect();
+            let child_dists = point_cloud.distances_to_point(point, &child_centers)?;
+            let children: Vec<(NodeAddress, f32)> = std::iter::once(((child_scale_index, address.1), dist))
+                .chain(child_addresses.iter().cloned().zip(child_dists))
+                .collect();
+            let closest_child = children
+                .iter()
+                .filter(|(ca, d)| d + radius <= scale_base.powi(ca.0))
+                .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            match closest_child {
+                Some((child_address, child_dist)) => {
+                    address = *child_address;
+                    dist = *child_dist;
+                }
+                None => {
+                    // Keep the children apart. If the center is close to a child that can't hold the whole subtree, it
+                    // has to be split up.
+                    if children.iter().any(|(_ca, d)| *d <= child_scale) {
+                        return Ok(None);
+                    }
+                    return Ok(Some((path, child_scale_index)));
+                }
+            }
+        }
+    }
+
+    fn stage_remove(&self, point_index: PointIndex, staged: &mut StagedNodes) -> MalwareBrotResult<()> {
+        let (path, home) = self.find_home(point_index, staged)?;
+        match home {
+            PointHome::Singleton(address) => {
+                let node = self.staged_node(staged, address)?;
+                node.remove_singleton(point_index);
+                node.update_metasummary(&self.parameters.point_cloud)?;
+                for ancestor in &path {
+                    let node = self.staged_node(staged, *ancestor)?;
+                    node.set_cover_count(node.cover_count().saturating_sub(1));
+                }
+                self.stage_compaction(address, staged)?;
+                if let Some(parent) = path.last() {
+                    self.stage_compaction(*parent, staged)?;
+                }
+                Ok(())
+            }
+            PointHome::Center(address) => {
+                let coverage = self.staged_node_and(staged, address, |n| n.coverage())?;
+                if let Some((parent, ancestors)) = path.split_last() {
+                    self.staged_node(staged, *parent)?.remove_child(address, coverage);
+                    for ancestor in ancestors {
+                        let node = self.staged_node(staged, *ancestor)?;
+                        node.set_cover_count(node.cover_count().saturating_sub(coverage));
+                    }
+                }
+                let (replacement, orphans) = self.stage_promotion(address, path.is_empty(), staged)?;
+                match (replacement, path.split_last()) {
+                    (Some(replacement), Some((parent, ancestors))) => {
+                        let before = self.staged_node_and(staged, *parent, |n| n.cover_count())?;
+                        self.stage_adoption(*parent, replacement, staged)?;
+                        let coverage = self.staged_node(staged, *parent)?.cover_count() - before;
+                        for ancestor in ancestors {
+                            let node = self.staged_node(staged, *ancestor)?;
+                            node.set_cover_count(node.cover_count() + coverage);
+                        }
+                    }
+                    (Some(replacement), None) => staged.root_address = replacement,
+                    (None, Some(_)) => {}
+                    (None, None) => return Err(MalwareBrotError::EmptyTree),
+                }
+                for pi in orphans {
+                    self.stage_insert(pi, staged)?;
+                }
+                if let Some(parent) = path.last() {
+                    self.stage_compaction(*parent, staged)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Takes the center out of a node and the chain of nested nodes below it, and puts a node with a new center in its
+    /// place. This works from the bottom of the chain up. A leaf is replaced by one centered on its closest singleton.
+    /// Above that the replacement of the old nested child becomes the new node's nested child, and its center the new
+    /// center, so the new center is as close to the old one as we can get. If the chain below had nothing left, the
+    /// closest child on the highest scale is promoted instead. The other children stay, less the points that are further
+    /// than the node's scale from the new center, see `stage_trim`. A child that's too close to a kept child on its scale
+    /// is taken apart and its pieces are tried in its place. The singletons stay if they're within the scale.
+    ///
+    /// The root has nothing above it to fit in, so if `grow` is set the new node's scale is raised until it covers all of
+    /// the old node's children and singletons instead.
+    ///
+    /// Returns the address of the new node, `None` if the center was the only point left, and the points that didn't
+    /// fit and have to be put back in the tree. The new node isn't attached to the parent.
+    fn stage_promotion(
+        &self,
+        address: NodeAddress,
+        grow: bool,
+        staged: &mut StagedNodes,
+    ) -> MalwareBrotResult<(Option<NodeAddress>, Vec<PointIndex>)> {
+        let point_cloud = &self.parameters.point_cloud;
+        let scale = self.parameters.scale_base.powi(address.0);
+        let (children, singletons) = self.staged_node_and(staged, address, |n| {
+            (
+                n.children()
+                    .map(|(nested_scale, addresses)| (nested_scale, Vec::from(addresses))),
+                Vec::from(n.singletons()),
+            )
+        })?;
+        staged.nodes.insert(address, None);
+
+        let (nested_scale, child_addresses) = match children {
+            Some(children) => children,
+            None => return self.stage_leaf_promotion(address, singletons, grow, staged),
+        };
+        let (nested_replacement, mut orphans) =
+            self.stage_promotion((nested_scale, address.1), false, staged)?;
+        let nested = match nested_replacement {
+            Some(nested_replacement) => nested_replacement,
+            None => {
+                // Children can be on lower scales than the nested one, after earlier removals.
+                let top_scale = child_addresses.iter().map(|(si, _pi)| *si).max();
+                let promotable: Vec<NodeAddress> = child_addresses
+                    .iter()
+                    .filter(|(si, _pi)| Some(*si) == top_scale)
+                    .cloned()
+                    .collect();
+                let promotable_centers: Vec<PointIndex> = promotable.iter().map(|(_si, pi)| *pi).collect();
+                let promotable_dists = point_cloud.distances_to_point_index(address.1, &promotable_centers)?;
+                let promoted = promotable
+                    .iter()
+                    .zip(promotable_dists)
+                    .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+                    .map(|(child_address, _d)| *child_address);
+                match promoted {
+                    Some(promoted) => promoted,
+                    None => return self.stage_leaf_promotion(address, singletons, grow, staged),
+                }
+            }
+        };
+        let mut candidates: Vec<NodeAddress> = child_addresses.into_iter().filter(|a| *a != nested).collect();
+        let mut singletons = singletons;
+
+        let mut node = CoverNode::new((address.0, nested.1));
+        let (nested_radius, nested_coverage) =
+            self.staged_node_and(staged, nested, |n| (n.radius().max(0.0), n.coverage()))?;
+        node.insert_nested_child(nested.0, nested_coverage)?;
+        let mut radius = nested_radius;
+        let mut kept = vec![nested];
+        while let Some(candidate) = candidates.pop() {
+            let siblings: Vec<PointIndex> = kept
+                .iter()
+                .filter(|(si, _pi)| *si == candidate.0)
+                .map(|(_si, pi)| *pi)
+                .collect();
+            let sibling_scale = self.parameters.scale_base.powi(candidate.0);
+            let separated = point_cloud
+                .distances_to_point_index(candidate.1, &siblings)?
+                .iter()
+                .all(|d| *d > sibling_scale);
+            if !separated {
+                // The center is too close to a kept child on the same scale, so the candidate is taken apart and its
+                // pieces are tried in its place. They're on smaller scales than it was.
+                let (children, candidate_singletons) = self.staged_node_and(staged, candidate, |n| {
+                    (
+                        n.children()
+                            .map(|(nested_scale, addresses)| (nested_scale, Vec::from(addresses))),
+                        Vec::from(n.singletons()),
+                    )
+                })?;
+                staged.nodes.insert(candidate, None);
+                singletons.extend(candidate_singletons);
+                match children {
+                    Some((nested_scale, addresses)) => {
+                        candidates.push((nested_scale, candidate.1));
+                        candidates.extend(addresses);
+                    }
+                    None => singletons.push(candidate.1),
+                }
+                continue;
+            }
+            let trimmed = if grow {
+                candidate
+            } else {
+                let (trimmed, trimmed_orphans) = self.stage_trim(candidate, nested.1, scale, staged)?;
+                orphans.extend(trimmed_orphans);
+                match trimmed {
+                    Some(trimmed) if trimmed == candidate => trimmed,
+                    // A point was promoted in place of the candidate's center, which has to be checked again.
+                    Some(trimmed) => {
+                        candidates.push(trimmed);
+                        continue;
+                    }
+                    None => continue,
+                }
+            };
+            let (trimmed_radius, trimmed_coverage) =
+                self.staged_node_and(staged, trimmed, |n| (n.radius().max(0.0), n.coverage()))?;
+            let d = point_cloud.distances_to_point_index(nested.1, &[trimmed.1])?[0];
+            node.insert_child(trimmed, trimmed_coverage)?;
+            radius = if grow {
+                radius.max(d + trimmed_radius)
+            } else {
+                radius.max((d + trimmed_radius).min(scale))
+            };
+            kept.push(trimmed);
+        }
+        let singleton_dists = point_cloud.distances_to_point_index(nested.1, &singletons)?;
+        let mut kept_singletons = Vec::new();
+        for (pi, d) in singletons.into_iter().zip(singleton_dists) {
+            if grow || d <= scale {
+                kept_singletons.push(pi);
+                radius = radius.max(d);
+            } else {
+                orphans.push(pi);
+            }
+        }
+        node.insert_singletons(kept_singletons);
+        node.set_radius(radius);
+        node.update_metasummary(point_cloud)?;
+        let scale_index = if grow && radius > scale {
+            radius.log(self.parameters.scale_base).ceil() as i32
+        } else {
+            address.0
+        };
+        let new_address = (scale_index, nested.1);
+        staged.nodes.insert(new_address, Some(node.rescaled(scale_index)));
+        self.stage_compaction(new_address, staged)?;
+        Ok((Some(new_address), orphans))
+    }
+
+    /// Replaces a node that only has singletons left with a new subtree, centered on the singleton that's closest to the
+    /// old center. Singletons that are further than the scale from the new center are returned to be put back, unless
+    /// we can `grow` the scale to cover them.
+    fn stage_leaf_promotion(
+        &self,
+        address: NodeAddress,
+        singletons: Vec<PointIndex>,
+        grow: bool,
+        staged: &mut StagedNodes,
+    ) -> MalwareBrotResult<(Option<NodeAddress>, Vec<PointIndex>)> {
+        let point_cloud = &self.parameters.point_cloud;
+        let dists = point_cloud.distances_to_point_index(address.1, &singletons)?;
+        let center = match singletons
+            .iter()
+            .zip(dists)
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        {
+            Some((pi, _d)) => *pi,
+            None => return Ok((None, Vec::new())),
+        };
+        let scale = self.parameters.scale_base.powi(address.0);
+        let dists = point_cloud.distances_to_point_index(center, &singletons)?;
+        let mut covered = Vec::new();
+        let mut orphans = Vec::new();
+        let mut radius: f32 = 0.0;
+        for (pi, d) in singletons.into_iter().zip(dists) {
+            if pi == center {
+                continue;
+            }
+            if grow || d <= scale {
+                covered.push(pi);
+                radius = radius.max(d);
+            } else {
+                orphans.push(pi);
+            }
+        }
+        let scale_index = if radius > scale {
+            radius.log(self.parameters.scale_base).ceil() as i32
+        } else {
+            address.0
+        };
+        let new_address = (scale_index, center);
+        self.stage_subtree(new_address, covered, staged)?;
+        Ok((Some(new_address), orphans))
+    }
+
+    /// Takes the points of the subtree that are further than the scale from the center out of it, so that the rest can
+    /// go under a node at that center. The triangle inequality with the node radii settles most of the subtree, this only
+    /// goes below the nodes whose ball sticks out. A node whose own center is out has a point promoted in its place. Returns
+    /// the address the subtree ends up at, if anything is left of it, and the points that have to be put back.
+    fn stage_trim(
+        &self,
+        address: NodeAddress,
+        center: PointIndex,
+        scale: f32,
+        staged: &mut StagedNodes,
+    ) -> MalwareBrotResult<(Option<NodeAddress>, Vec<PointIndex>)> {
+        let point_cloud = &self.parameters.point_cloud;
+        let (radius, children, singletons) = self.staged_node_and(staged, address, |n| {
+            (
+                n.radius().max(0.0),
+                n.children()
+                    .map(|(nested_scale, addresses)| (nested_scale, Vec::from(addresses))),
+                Vec::from(n.singletons()),
+            )
+        })?;
+        let d = point_cloud.distances_to_point_index(center, &[address.1])?[0];
+        if d + radius <= scale {
+            return Ok((Some(address), Vec::new()));
+        }
+        if d > scale {
+            let (replacement, mut orphans) = self.stage_promotion(address, false, staged)?;
+            orphans.push(address.1);
+            return match replacement {
+                Some(replacement) => {
+                    let (trimmed, trimmed_orphans) = self.stage_trim(replacement, center, scale, staged)?;
+                    orphans.extend(trimmed_orphans);
+                    Ok((trimmed, orphans))
+                }
+                None => Ok((None, orphans)),
+            };
+        }
+
+        let mut orphans = Vec::new();
+        let singleton_dists = point_cloud.distances_to_point_index(center, &singletons)?;
+        for (pi, d) in singletons.into_iter().zip(singleton_dists) {
+            if d > scale {
+                self.staged_node(staged, address)?.remove_singleton(pi);
+                orphans.push(pi);
+            }
+        }
+        if let Some((nested_scale, addresses)) = children {
+            let nested = (nested_scale, address.1);
+            let before = self.staged_node_and(staged, nested, |n| n.coverage())?;
+            // The nested child shares the node's center, which is in, so it stays where it is.
+            let (_, nested_orphans) = self.stage_trim(nested, center, scale, staged)?;
+            orphans.extend(nested_orphans);
+            let after = self.staged_node_and(staged, nested, |n| n.coverage())?;
+            let node = self.staged_node(staged, address)?;
+            node.set_cover_count(node.cover_count() + after - before);
+
+            for child in addresses {
+                let before = self.staged_node_and(staged, child, |n| n.coverage())?;
+                let (trimmed, child_orphans) = self.stage_trim(child, center, scale, staged)?;
+                orphans.extend(child_orphans);
+                self.staged_node(staged, address)?.remove_child(child, before);
+                if let Some(trimmed) = trimmed {
+                    self.stage_adoption(address, trimmed, staged)?;
+                }
+            }
+        }
+        self.staged_node(staged, address)?.update_metasummary(point_cloud)?;
+        self.stage_compaction(address, staged)?;
+        Ok((Some(address), orphans))
+    }
+
+    /// Puts a subtree that came from below a routing node back under it. If its center is too close to one of the node's
+    /// children on its scale it's taken apart, and its pieces go in the same way, down to singletons.
+    fn stage_adoption(
+        &self,
+        parent: NodeAddress,
+        address: NodeAddress,
+        staged: &mut StagedNodes,
+    ) -> MalwareBrotResult<()> {
+        let mut stack = vec![address];
+        while let Some(address) = stack.pop() {
+            if self.separated_from_children(parent, address, staged)? {
+                let coverage = self.staged_node_and(staged, address, |n| n.coverage())?;
+                self.staged_node(staged, parent)?.insert_child(address, coverage)?;
+                continue;
+            }
+            let (children, singletons) = self.staged_node_and(staged, address, |n| {
+                (
+                    n.children()
+                        .map(|(nested_scale, addresses)| (nested_scale, Vec::from(addresses))),
+                    Vec::from(n.singletons()),
+                )
+            })?;
+            staged.nodes.insert(address, None);
+            let node = self.staged_node(staged, parent)?;
+            node.insert_singletons(singletons);
+            match children {
+                Some((nested_scale, addresses)) => {
+                    stack.push((nested_scale, address.1));
+                    stack.extend(addresses);
+                }
+                None => node.insert_singleton(address.1),
+            }
+        }
+        self.staged_node(staged, parent)?
+            .update_metasummary(&self.parameters.point_cloud)
+    }
+
+    /// Checks that the node's center is further than its scale from the centers of the parent's children on its scale.
+    fn separated_from_children(
+        &self,
+        parent: NodeAddress,
+        address: NodeAddress,
+        staged: &StagedNodes,
+    ) -> MalwareBrotResult<bool> {
+        let siblings: Vec<PointIndex> = self
+            .staged_node_and(staged, parent, |n| {
+                n.children().map(|(nested_scale, addresses)| {
+                    std::iter::once(&(nested_scale, parent.1))
+                        .chain(addresses)
+                        .filter(|(si, pi)| *si == address.0 && *pi != address.1)
+                        .map(|(_si, pi)| *pi)
+                        .collect()
+                })
+            })?
+            .unwrap_or_default();
+        let scale = self.parameters.scale_base.powi(address.0);
+        Ok(self
+            .parameters
+            .point_cloud
+            .distances_to_point_index(address.1, &siblings)?
+            .iter()
+            .all(|d| *d > scale))
+    }
+
+    /// Tidies up a node that lost points. Children that are leaves covering only their center become singletons, if we're
+    /// using singletons. Then if the nested child is the only thing left under the node, the node takes over its children and
+    /// singletons and the nested child is removed.
+    fn stage_compaction(&self, address: NodeAddress, staged: &mut StagedNodes) -> MalwareBrotResult<()> {
+        let mut compacted = false;
+        if self.parameters.use_singletons {
+            let children = self.staged_node_and(staged, address, |n| {
+                n.children().map(|(_ns, addresses)| Vec::from(addresses))
+            })?;
+            for child in children.unwrap_or_default() {
+                if self.staged_node_and(staged, child, |n| n.is_leaf() && n.singleton_len() == 0)? {
+                    let node = self.staged_node(staged, address)?;
+                    node.remove_child(child, 1);
+                    node.insert_singleton(child.1);
+                    staged.nodes.insert(child, None);
+                    compacted = true;
+                }
+            }
+        }
+
+        loop {
+            let only_nested = self.staged_node_and(staged, address, |n| match n.children() {
+                Some((nested_scale, addresses)) if addresses.is_empty() && n.singleton_len() == 0 => {
+                    Some(nested_scale)
+                }
+                _ => None,
+            })?;
+            match only_nested {
+                Some(nested_scale) => {
+                    let nested_address = (nested_scale, address.1);
+                    let nested = self.staged_node_and(staged, nested_address, |n| n.clone())?;
+                    self.staged_node(staged, address)?.absorb_nested(nested);
+                    staged.nodes.insert(nested_address, None);
+                    compacted = true;
+                }
+                None => break,
+            }
+        }
+
+        if compacted {
+            self.staged_node(staged, address)?
+                .update_metasummary(&self.parameters.point_cloud)?;
+        }
+        Ok(())
+    }
+
+    /// Finds the node that the point is a singleton of, or the top node of the chain of nodes that it is the center of.
+    /// Also returns the path of ancestors from the root to that node.
+    fn find_home(
+        &self,
+        point_index: PointIndex,
+        staged: &StagedNodes,
+    ) -> MalwareBrotResult<(Vec<NodeAddress>, PointHome)> {
+        let point_cloud = &self.parameters.point_cloud;
+        let point = point_cloud.get_point(point_index)?;
+        let mut stack = vec![(staged.root_address, Vec::new())];
+        while let Some((address, path)) = stack.pop() {
+            if address.1 == point_index {
+                return Ok((path, PointHome::Center(address)));
+            }
+            let (children, is_singleton) = self.staged_node_and(staged, address, |n| {
+                let children = n.children().map(|(nested_scale, addresses)| {
+                    let mut children = vec![(nested_scale, address.1)];
+                    children.extend_from_slice(addresses);
+                    children
+                });
+                (children, n.singletons().contains(&point_index))
+            })?;
+            if is_singleton {
+                return Ok((path, PointHome::Singleton(address)));
+            }
+            if let Some(children) = children {
+                let child_centers: Vec<PointIndex> = children.iter().map(|(_si, pi)| *pi).collect();
+                let child_dists = point_cloud.distances_to_point(point, &child_centers)?;
+                let mut child_path = path.clone();
+                child_path.push(address);
+                for (child_address, d) in children.iter().zip(child_dists) {
+                    if d <= self.parameters.scale_base.powi(child_address.0) {
+                        stack.push((*child_address, child_path.clone()));
+                    }
+                }
+            }
+        }
+        Err(MalwareBrotError::PointNotInTree(point_index))
+    }
+
+    /// Writes the staged nodes into the layers, adding layers for a new root. Call `refresh` to publish them.
+    fn write_staged(&mut self, mut staged: StagedNodes) -> MalwareBrotResult<()> {
+        self.stage_subtree_summaries(&mut staged)?;
+        let top_index = self.parameters.internal_index(staged.root_address.0);
+        while self.layers.len() <= top_index {
+            let scale_index = self.parameters.resolution + self.layers.len() as i32 - 1;
+            self.layers.push(CoverLayerWriter::new(scale_index));
+        }
+        // Nodes made and dropped within the batch were never counted.
+        let mut added = 0;
+        let mut removed = 0;
+        for (address, node) in staged.nodes {
+            let existed = self
+                .layers
+                .get(self.parameters.internal_index(address.0))
+                .and_then(|l| l.get_node_and(&address.1, |_n| ()))
+                .is_some();
+            match node {
+                Some(node) => {
+                    if !existed {
+                        added += 1;
+                    }
+                    unsafe { self.insert_raw(address.0, address.1, node) }
+                }
+                None => {
+                    if existed {
+                        removed += 1;
+                    }
+                    unsafe { self.layer(address.0).remove_raw(address.1) }
+                }
+            }
+        }
+        if added >= removed {
+            self.parameters.total_nodes.fetch_add(added - removed, atomic::Ordering::SeqCst);
+        } else {
+            self.parameters.total_nodes.fetch_sub(removed - added, atomic::Ordering::SeqCst);
+        }
+        self.root_address = staged.root_address;
+        Ok(())
+    }
+
+    /// Recomputes the subtree summaries of the staged nodes, children before parents. Every ancestor of a changed node is
+    /// staged as its cover count changed, so this covers all the summaries that changed.
+    fn stage_subtree_summaries(&self, staged: &mut StagedNodes) -> MalwareBrotResult<()> {
+        let mut addresses: Vec<NodeAddress> = staged
+            .nodes
+            .iter()
+            .filter(|(_address, node)| node.is_some())
+            .map(|(address, _node)| *address)
+            .collect();
+        addresses.sort();
+        for address in addresses {
+            let children = self.staged_node_and(staged, address, |n| {
+                n.children().map(|(nested_scale, children)| {
+                    let mut addresses = vec![(nested_scale, address.1)];
+                    addresses.extend_from_slice(children);
+                    addresses
+                })
+            })?;
+            let children_summaries: Vec<Option<MetaSummary>> = children
+                .unwrap_or_default()
+                .iter()
+                .map(|child| {
+                    self.staged_node_and(staged, *child, |n| n.subtree_summary().cloned())
+                        .unwrap_or(None)
+                })
+                .collect();
+            self.staged_node(staged, address)?
+                .update_subtree_summary(&children_summaries[..], &self.parameters.point_cloud)?;
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::query_tools::LabelFilter;
     use crate::utils::cover_tree_from_yaml;
+    use pointcloud::errors::PointCloudError;
+    use pointcloud::labels::values::{Number, Value, Vector};
+    use pointcloud::labels::LabelScheme;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
     use std::path::Path;
 
     pub(crate) fn build_mnist_tree() -> CoverTreeWriter<L2> {
@@ -408,8 +2355,133 @@ pub(crate) mod tests {
         cover_tree_from_yaml(&path).unwrap()
     }
 
+    /// The builder the tests share. It's seeded so a failing test builds the same tree when rerun.
+    pub(crate) fn test_builder() -> CoverTreeBuilder {
+        CoverTreeBuilder {
+            scale_base: 1.5,
+            cutoff: 3,
+            resolution: -9,
+            use_singletons: true,
+            cluster_min: 5,
+            verbosity: 0,
+            seed: Some(0),
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
+        }
+    }
+
+    /// `count` points drawn from the unit square with a seeded generator, all labeled 0.
+    pub(crate) fn random_point_cloud(count: usize, seed: u64) -> PointCloud<L2> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let data: Vec<f32> = (0..2 * count).map(|_i| rng.gen::<f32>()).collect();
+        let labels: Vec<f32> = vec![0.0; count];
+        PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(labels), 1).unwrap()
+    }
+
+    /// A tree over `random_point_cloud(count, seed)`, built with `test_builder`.
+    pub(crate) fn random_tree(count: usize, seed: u64) -> CoverTreeWriter<L2> {
+        test_builder().build(random_point_cloud(count, seed)).unwrap()
+    }
+
+    #[test]
+    fn greedy_knn_nodes() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            cutoff: 1,
+            resolution: -9,
+            use_singletons: false,
+            cluster_min: 5,
+            verbosity: 0,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let point = [-0.5];
+
+        let mut query_heap = KnnQueryHeap::new(5, reader.parameters.scale_base);
+        let dist_to_root = reader
+            .parameters
+            .point_cloud
+            .distances_to_point(&point, &[reader.root_address().1])
+            .unwrap()[0];
+        query_heap.push_nodes(&[reader.root_address()], &[dist_to_root], None);
+
+        assert_eq!(
+            reader.root_address(),
+            query_heap.closest_unvisited_child_covering_address().unwrap().1
+        );
+
+        reader.greedy_knn_nodes(&point,&mut query_heap);
+        println!("{:#?}", query_heap);
+        println!("{:#?}",query_heap.closest_unvisited_child_covering_address());
+    }
+    
+    #[test]
+    fn knn_singletons_on() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            cutoff: 1,
+            resolution: -9,
+            use_singletons: true,
+            cluster_min: 5,
+            verbosity: 0,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        println!("2 nearest neighbors of 0.0 are 0.48 and 0.0");
+        let zero_nbrs = reader.knn(&[0.1],2).unwrap();
+        println!("{:?}", zero_nbrs);
+        assert!(zero_nbrs[0].1 == 4);
+        assert!(zero_nbrs[1].1 == 2);
+    }
+
+    #[test]
+    fn knn_singletons_off() {
+        let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
+        let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
+
+        let point_cloud =
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 1, Box::from(labels), 1).unwrap();
+        let builder = CoverTreeBuilder {
+            scale_base: 2.0,
+            cutoff: 1,
+            resolution: -9,
+            use_singletons: false,
+            cluster_min: 5,
+            verbosity: 0,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
+        };
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        println!("2 nearest neighbors of 0.1 are 0.48 and 0.0");
+        let zero_nbrs = reader.knn(&[0.1],2).unwrap();
+        println!("{:?}", zero_nbrs);
+        assert!(zero_nbrs[0].1 == 4);
+        assert!(zero_nbrs[1].1 == 2);
+    }
+
     #[test]
-    fn greedy_knn_nodes() {
+    fn range_query_singletons_on() {
         let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
         let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
 
@@ -419,35 +2491,118 @@ pub(crate) mod tests {
             scale_base: 2.0,
             cutoff: 1,
             resolution: -9,
-            use_singletons: false,
+            use_singletons: true,
             cluster_min: 5,
             verbosity: 0,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
 
-        let point = [-0.5];
+        println!("Points within 0.45 of 0.1 are 0.0, 0.48, 0.49, and 0.499");
+        let nbrs = reader.range_query(&[0.1], 0.45).unwrap();
+        println!("{:?}", nbrs);
+        assert!(nbrs.len() == 4);
+        assert!(nbrs[0].1 == 4);
+        assert!(nbrs[1].1 == 2);
+        assert!(nbrs[2].1 == 1);
+        assert!(nbrs[3].1 == 0);
+    }
 
-        let mut query_heap = KnnQueryHeap::new(5, reader.parameters.scale_base);
-        let dist_to_root = reader
-            .parameters
-            .point_cloud
-            .distances_to_point(&point, &[reader.root_address().1])
-            .unwrap()[0];
-        query_heap.push_nodes(&[reader.root_address()], &[dist_to_root], None);
+    #[test]
+    fn range_query_matches_brute_force() {
+        let count = 500;
+        let tree = random_tree(count, 0);
+        let reader = tree.reader();
 
-        assert_eq!(
-            reader.root_address(),
-            query_heap.closest_unvisited_child_covering_address().unwrap().1
+        let point = [0.5, 0.5];
+        let radius = 0.2;
+        let nbrs = reader.range_query(&point, radius).unwrap();
+        let brute: Vec<PointIndex> = (0..count as PointIndex)
+            .filter(|i| {
+                L2::dense(&point, reader.point_cloud().get_point(*i).unwrap()) <= radius
+            })
+            .collect();
+        println!("Found {} points, expected {}", nbrs.len(), brute.len());
+        assert!(nbrs.len() == brute.len());
+        for w in nbrs.windows(2) {
+            assert!(w[0].0 <= w[1].0);
+        }
+        for (_d, pi) in &nbrs {
+            assert!(brute.contains(pi));
+        }
+    }
+
+    #[test]
+    fn knn_batch_matches_knn() {
+        let count = 500;
+        let tree = random_tree(count, 0);
+        let reader = tree.reader();
+
+        let k = 5;
+        let queries: Vec<f32> = (0..2 * 100).map(|_i| rand::random::<f32>()).collect();
+        let (dists, indexes) = reader.knn_batch(&queries, 2, k).unwrap();
+        assert!(dists.len() == 100 * k);
+        assert!(indexes.len() == 100 * k);
+        for (i, point) in queries.chunks(2).enumerate() {
+            let nbrs = reader.knn(point, k).unwrap();
+            for (j, (d, pi)) in nbrs.iter().enumerate() {
+                assert!(dists[i * k + j] == *d);
+                assert!(indexes[i * k + j] == *pi);
+            }
+        }
+
+        assert!(reader.knn_batch(&queries, 3, k).is_err());
+    }
+
+    #[test]
+    fn approximate_knn_status() {
+        let count = 500;
+        let tree = random_tree(count, 0);
+        let reader = tree.reader();
+
+        let point = [0.5, 0.5];
+        let k = 5;
+        let exact_nbrs = reader.knn(&point, k).unwrap();
+
+        let exact = reader
+            .approximate_knn(&point, k, &ApproximateKnnParameters::default())
+            .unwrap();
+        assert!(exact.status == KnnStatus::Exact);
+        assert!(exact.neighbors == exact_nbrs);
+
+        let epsilon = 0.5;
+        let relaxed_params = ApproximateKnnParameters {
+            epsilon,
+            max_nodes: None,
+            max_distances: None,
+        };
+        let relaxed = reader.approximate_knn(&point, k, &relaxed_params).unwrap();
+        println!(
+            "Exact: {} distances, relaxed: {} distances",
+            exact.distance_evaluations, relaxed.distance_evaluations
         );
+        assert!(relaxed.status == KnnStatus::WithinEpsilon);
+        assert!(relaxed.neighbors.len() == k);
+        assert!(relaxed.distance_evaluations <= exact.distance_evaluations);
+        for ((d, _), (true_d, _)) in relaxed.neighbors.iter().zip(&exact_nbrs) {
+            assert!(*d <= (1.0 + epsilon) * true_d);
+        }
 
-        reader.greedy_knn_nodes(&point,&mut query_heap);
-        println!("{:#?}", query_heap);
-        println!("{:#?}",query_heap.closest_unvisited_child_covering_address());
+        let cut_params = ApproximateKnnParameters {
+            epsilon: 0.0,
+            max_nodes: None,
+            max_distances: Some(20),
+        };
+        let cut = reader.approximate_knn(&point, k, &cut_params).unwrap();
+        assert!(cut.status == KnnStatus::CutOff);
+        assert!(cut.distance_evaluations <= 20);
     }
-    
+
     #[test]
-    fn knn_singletons_on() {
+    fn knn_by_index_and_name() {
         let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
         let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
 
@@ -460,19 +2615,213 @@ pub(crate) mod tests {
             use_singletons: true,
             cluster_min: 5,
             verbosity: 0,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
 
-        println!("2 nearest neighbors of 0.0 are 0.48 and 0.0");
-        let zero_nbrs = reader.knn(&[0.1],2).unwrap();
-        println!("{:?}", zero_nbrs);
-        assert!(zero_nbrs[0].1 == 4);
-        assert!(zero_nbrs[1].1 == 2);
+        let with_self = reader.knn_index(1, 2, false).unwrap();
+        assert!(with_self.len() == 2);
+        assert!(with_self[0].1 == 1);
+        assert!(with_self[1].1 == 0);
+
+        let without_self = reader.knn_index(1, 2, true).unwrap();
+        assert!(without_self.len() == 2);
+        assert!(without_self[0].1 == 0);
+        assert!(without_self[1].1 == 2);
+
+        let by_name = reader.knn_name(&"1".to_string(), 2, true).unwrap();
+        assert!(by_name == without_self);
+
+        match reader.knn_name(&"not a point".to_string(), 2, true) {
+            Err(MalwareBrotError::NameNotInTree(name)) => assert!(name == "not a point"),
+            _ => panic!("Unknown name should not be in the tree"),
+        }
+        assert!(reader.knn_index(10, 2, true).is_err());
     }
 
     #[test]
-    fn knn_singletons_off() {
+    fn layers_have_their_scale_index() {
+        let tree = random_tree(100, 0);
+        let reader = tree.reader();
+
+        let mut layer_count = 0;
+        let mut node_count = 0;
+        for (si, layer) in reader.layers() {
+            layer_count += 1;
+            node_count += layer.node_count();
+            if si < reader.parameters().resolution {
+                assert!(si == reader.parameters().resolution - 1);
+            } else {
+                assert!(layer.scale_index() == si);
+                layer.for_each_node(|_pi, n| assert!(*n.scale_index() == si));
+            }
+        }
+        assert!(layer_count == reader.layers.len());
+        assert!(node_count > 0);
+        assert!(node_count == reader.node_count());
+        assert!(reader.root_address().0 == reader.parameters().resolution - 2 + layer_count as i32);
+    }
+
+    #[test]
+    fn knn_filtered_matches_brute_force() {
+        let count = 500;
+        let mut rng = StdRng::seed_from_u64(0);
+        let data: Vec<f32> = (0..2 * count).map(|_i| rng.gen::<f32>()).collect();
+        let mut label_scheme = LabelScheme::new();
+        label_scheme.add_f32("score".to_string());
+        let mut labels = label_scheme.empty();
+        for i in 0..count {
+            let mut metadata = Metadata::new();
+            let score = (i % 10) as f32 / 10.0;
+            metadata.insert("score".to_string(), Value::Number(Number::Real(score)));
+            labels.push(None, metadata).unwrap();
+        }
+
+        let point_cloud = PointCloud::<L2>::from_ram(Box::from(data), 2, labels).unwrap();
+        let builder = test_builder();
+        let tree = builder.build(point_cloud).unwrap();
+        let reader = tree.reader();
+
+        let point = [0.5, 0.5];
+        let k = 5;
+        let filter = LabelFilter::GreaterThan("score".to_string(), 0.75);
+        let nbrs = reader.knn_filtered(&point, k, &filter).unwrap();
+
+        let mut brute: Vec<(f32, PointIndex)> = (0..count as PointIndex)
+            .filter(|i| filter.matches(&reader.point_cloud().get_metadata(*i).unwrap()))
+            .map(|i| (L2::dense(&point, reader.point_cloud().get_point(i).unwrap()), i))
+            .collect();
+        brute.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert!(nbrs.len() == k);
+        for ((d, pi), (true_d, _)) in nbrs.iter().zip(&brute) {
+            assert_approx_eq!(*d, *true_d);
+            assert!(filter.matches(&reader.point_cloud().get_metadata(*pi).unwrap()));
+        }
+
+        let closure_nbrs = reader
+            .knn_filtered(&point, k, &|m: &Metadata| filter.matches(m))
+            .unwrap();
+        assert!(closure_nbrs == nbrs);
+
+        let none = LabelFilter::Equals("score".to_string(), Value::Number(Number::Real(2.0)));
+        assert!(reader.knn_filtered(&point, k, &none).unwrap().is_empty());
+    }
+
+    /// Counts how often the filter is checked, each check is a metadata lookup or a node summary lookup.
+    struct CountingFilter {
+        filter: LabelFilter,
+        checks: std::cell::Cell<usize>,
+    }
+
+    impl MetadataFilter for CountingFilter {
+        fn matches(&self, metadata: &Metadata) -> bool {
+            self.checks.set(self.checks.get() + 1);
+            self.filter.matches(metadata)
+        }
+        fn could_match(&self, summary: &MetaSummary) -> bool {
+            self.checks.set(self.checks.get() + 1);
+            self.filter.could_match(summary)
+        }
+    }
+
+    #[test]
+    fn knn_filtered_prunes_subtrees() {
+        let count = 1000;
+        let rare = 5;
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut data: Vec<f32> = (0..2 * (count - rare)).map(|_i| rng.gen::<f32>()).collect();
+        for i in 0..rare {
+            data.push(0.99 + 0.001 * i as f32);
+            data.push(0.99);
+        }
+        let point_cloud = || {
+            let mut label_scheme = LabelScheme::new();
+            label_scheme.add_f32("score".to_string());
+            let mut labels = label_scheme.empty();
+            for i in 0..count {
+                let mut metadata = Metadata::new();
+                let score = if i < count - rare { 0.0 } else { 1.0 };
+                metadata.insert("score".to_string(), Value::Number(Number::Real(score)));
+                labels.push(None, metadata).unwrap();
+            }
+            PointCloud::<L2>::from_ram(Box::from(data.clone()), 2, labels).unwrap()
+        };
+        let mut builder = test_builder();
+        let unsummarized = builder.build(point_cloud()).unwrap();
+        let unsummarized_reader = unsummarized.reader();
+        let root_address = unsummarized_reader.root_address();
+        assert!(unsummarized_reader
+            .get_node_and(root_address, |n| n.subtree_summary().is_none())
+            .unwrap());
+
+        let tree = builder.set_summarize_subtrees(true).build(point_cloud()).unwrap();
+        let reader = tree.reader();
+
+        let point = [0.0, 0.0];
+        let k = 3;
+        let filter = CountingFilter {
+            filter: LabelFilter::GreaterThan("score".to_string(), 0.5),
+            checks: std::cell::Cell::new(0),
+        };
+        let nbrs = reader.knn_filtered(&point, k, &filter).unwrap();
+        let pruned_checks = filter.checks.get();
+
+        // The summaries are saved, so a loaded tree prunes too.
+        let loaded = CoverTreeWriter::load(&tree.save(), point_cloud()).unwrap();
+        assert!(loaded.parameters.summarize_subtrees);
+        filter.checks.set(0);
+        let loaded_nbrs = loaded.reader().knn_filtered(&point, k, &filter).unwrap();
+        let loaded_checks = filter.checks.get();
+        assert!(loaded_nbrs == nbrs);
+
+        filter.checks.set(0);
+        let unpruned_nbrs = reader
+            .knn_filtered(&point, k, &|m: &Metadata| filter.matches(m))
+            .unwrap();
+        let unpruned_checks = filter.checks.get();
+
+        assert!(nbrs.len() == k);
+        assert!(nbrs == unpruned_nbrs);
+        for (_d, pi) in &nbrs {
+            assert!(*pi >= (count - rare) as PointIndex);
+        }
+        // Without the summaries every point's metadata is checked, with them we only go down to the rare points.
+        assert!(unpruned_checks >= count);
+        assert!(pruned_checks * 10 < unpruned_checks);
+        assert!(loaded_checks * 10 < unpruned_checks);
+    }
+
+    #[test]
+    fn nearest_neighbors_iterates_in_order() {
+        let count = 500;
+        let tree = random_tree(count, 0);
+        let reader = tree.reader();
+
+        let point = [0.5, 0.5];
+        let first: Vec<(f32, PointIndex)> = reader.nearest_neighbors(&point).unwrap().take(5).collect();
+        let knn = reader.knn(&point, 5).unwrap();
+        for ((d, _), (true_d, _)) in first.iter().zip(&knn) {
+            assert_approx_eq!(*d, *true_d);
+        }
+
+        let mut nbrs = reader.nearest_neighbors(&point).unwrap();
+        let all: Vec<(f32, PointIndex)> = nbrs.by_ref().collect();
+        assert!(nbrs.take_error().is_none());
+        assert!(all.len() == count);
+        for w in all.windows(2) {
+            assert!(w[0].0 <= w[1].0);
+        }
+        let mut indexes: Vec<PointIndex> = all.iter().map(|(_d, pi)| *pi).collect();
+        indexes.sort();
+        indexes.dedup();
+        assert!(indexes.len() == count);
+    }
+
+    #[test]
+    fn trace_follows_covering_nodes() {
         let data = vec![0.499, 0.49, 0.48, -0.49, 0.0];
         let labels = vec![0.0, 0.0, 0.0, 1.0, 1.0];
 
@@ -482,17 +2831,596 @@ pub(crate) mod tests {
             scale_base: 2.0,
             cutoff: 1,
             resolution: -9,
-            use_singletons: false,
+            use_singletons: true,
             cluster_min: 5,
             verbosity: 0,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
         };
         let tree = builder.build(point_cloud).unwrap();
         let reader = tree.reader();
 
-        println!("2 nearest neighbors of 0.1 are 0.48 and 0.0");
-        let zero_nbrs = reader.knn(&[0.1],2).unwrap();
-        println!("{:?}", zero_nbrs);
-        assert!(zero_nbrs[0].1 == 4);
-        assert!(zero_nbrs[1].1 == 2);
+        let trace = reader.trace(&[0.495]).unwrap();
+        let report: Vec<String> = trace.iter().map(|step| step.to_json()).collect();
+        println!("[{}]", report.join(","));
+        assert!(trace[0].address == reader.root_address());
+        for w in trace.windows(2) {
+            assert!(w[0].address.0 > w[1].address.0);
+            assert!(w[1].dist <= reader.scale(w[1].address.0));
+        }
+        for step in &trace {
+            let center = reader.point_cloud().get_point(step.address.1).unwrap();
+            assert_approx_eq!(step.dist, L2::dense(center, &[0.495]));
+            assert!(step.radius >= 0.0);
+        }
+    }
+
+    #[test]
+    fn trace_json_handles_infinite_radius() {
+        let step = TraceStep {
+            address: (-3, 7),
+            dist: std::f32::NAN,
+            radius: std::f32::NEG_INFINITY,
+            cover_count: 1,
+            summary: None,
+        };
+        let json: serde_json::Value = serde_json::from_str(&step.to_json()).unwrap();
+        assert!(json["address"] == serde_json::json!([-3, 7]));
+        assert!(json["dist"].is_null());
+        assert!(json["radius"].is_null());
+        assert!(json["cover_count"] == 1);
+        assert!(json["summary"].is_null());
+    }
+
+    /// Walks the subtree, checking that every point is within `b^i` of the centers above it, and returns the points.
+    fn check_covering(reader: &CoverTreeReader<L2>, address: NodeAddress) -> Vec<PointIndex> {
+        let (children, singletons) = reader
+            .get_node_and(address, |n| {
+                let children = n.children().map(|(nested_scale, addresses)| {
+                    let mut children = vec![(nested_scale, address.1)];
+                    children.extend_from_slice(addresses);
+                    children
+                });
+                (children, Vec::from(n.singletons()))
+            })
+            .unwrap();
+        let mut points = singletons;
+        match children {
+            Some(children) => {
+                for child_address in children {
+                    points.extend(check_covering(reader, child_address));
+                }
+            }
+            None => points.push(address.1),
+        }
+        let point_cloud = reader.point_cloud();
+        let center = point_cloud.get_point(address.1).unwrap();
+        for pi in &points {
+            let d = L2::dense(center, point_cloud.get_point(*pi).unwrap());
+            assert!(d <= reader.scale(address.0));
+        }
+        points
+    }
+
+    #[test]
+    fn merge_matches_brute_force() {
+        // Each point is labeled with its index, so the labels show whether any got lost.
+        let build = |count: usize, seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let data: Vec<f32> = (0..2 * count).map(|_i| rng.gen::<f32>()).collect();
+            let labels: Vec<f32> = (0..count).map(|i| i as f32).collect();
+            let point_cloud =
+                PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(labels), 1).unwrap();
+            test_builder().build(point_cloud).unwrap()
+        };
+        let tree = CoverTreeWriter::merge(build(200, 1), build(300, 2)).unwrap();
+        let reader = tree.reader();
+        assert!(reader.no_dangling_refs());
+
+        assert!(reader.validate().unwrap().is_valid());
+        let mut points = check_covering(&reader, reader.root_address());
+        points.sort();
+        let all_points = reader.point_cloud().reference_indexes();
+        assert!(points == all_points);
+        assert!(all_points.len() == 500);
+        let root_count = reader.get_node_and(reader.root_address(), |n| n.cover_count());
+        assert!(root_count == Some(500));
+
+        // The smaller tree's labels came along with its points
+        let label_sum: f32 = all_points[300..]
+            .iter()
+            .map(|pi| match reader.point_cloud().get_metadata(*pi).unwrap().get("y") {
+                Some(Value::Vector(Vector::Real(y))) => y[0],
+                _ => panic!("missing label"),
+            })
+            .sum();
+        assert_approx_eq!(label_sum, (0..200).sum::<usize>() as f32);
+
+        let k = 5;
+        for _i in 0..20 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            let found = reader.knn(&query, k).unwrap();
+            let mut expected = reader
+                .point_cloud()
+                .distances_to_point(&query, &all_points)
+                .unwrap();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert!(found.len() == k);
+            for ((d, _pi), true_d) in found.iter().zip(&expected) {
+                assert_approx_eq!(*d, *true_d);
+            }
+        }
+    }
+
+    #[test]
+    fn merge_subsets_of_one_point_cloud() {
+        let count = 400;
+        let point_cloud = Arc::new(random_point_cloud(count, 0));
+        let builder = test_builder();
+        let all_points = point_cloud.reference_indexes();
+        let (first, second) = all_points.split_at(count / 2);
+        let first = builder.build_subset(Arc::clone(&point_cloud), first).unwrap();
+        let second = builder.build_subset(Arc::clone(&point_cloud), second).unwrap();
+        let tree = CoverTreeWriter::merge(first, second).unwrap();
+        let reader = tree.reader();
+
+        // Nothing was appended, the points kept their indexes.
+        assert!(reader.point_cloud().len() == count);
+        assert!(reader.no_dangling_refs());
+        assert!(reader.validate_points(&all_points).unwrap().is_valid());
+        let mut points = check_covering(&reader, reader.root_address());
+        points.sort();
+        assert!(points == all_points);
+        let root_count = reader.get_node_and(reader.root_address(), |n| n.cover_count());
+        assert!(root_count == Some(count));
+
+        for pi in &all_points[..20] {
+            let knn = reader.knn(point_cloud.get_point(*pi).unwrap(), 1).unwrap();
+            assert!(knn[0] == (0.0, *pi));
+        }
+    }
+
+    #[test]
+    fn insert_matches_brute_force() {
+        let count = 400;
+        let point_cloud = random_point_cloud(count, 0);
+
+        // A tree that only has the first point, the rest get inserted
+        let parameters = Arc::new(CoverTreeParameters {
+            total_nodes: atomic::AtomicUsize::new(1),
+            scale_base: 1.5,
+            cutoff: 3,
+            resolution: -9,
+            use_singletons: true,
+            cluster_min: 5,
+            point_cloud: Arc::new(point_cloud),
+            verbosity: 0,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
+        });
+        let mut tree = CoverTreeWriter::new(
+            parameters,
+            vec![CoverLayerWriter::new(-9), CoverLayerWriter::new(-9)],
+            (-9, 0),
+        );
+        unsafe {
+            tree.insert_raw(-9, 0, CoverNode::new((-9, 0)));
+        }
+        tree.refresh();
+        let old_reader = tree.reader();
+
+        tree.insert(1).unwrap();
+        let rest: Vec<PointIndex> = (2..count as PointIndex).collect();
+        tree.insert_batch(&rest).unwrap();
+        let reader = tree.reader();
+        assert!(old_reader.knn(&[0.5, 0.5], 5).unwrap().len() == 5);
+
+        assert!(reader.validate().unwrap().is_valid());
+        let mut points = check_covering(&reader, reader.root_address());
+        points.sort();
+        assert!(points == (0..count as PointIndex).collect::<Vec<PointIndex>>());
+        let root_count = reader.get_node_and(reader.root_address(), |n| n.cover_count());
+        assert!(root_count == Some(count));
+
+        for _i in 0..20 {
+            let point = [rand::random::<f32>(), rand::random::<f32>()];
+            let knn = reader.knn(&point, 5).unwrap();
+            let mut brute: Vec<f32> = (0..count as PointIndex)
+                .map(|i| L2::dense(&point, reader.point_cloud().get_point(i).unwrap()))
+                .collect();
+            brute.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            for ((d, _pi), true_d) in knn.iter().zip(&brute) {
+                assert_approx_eq!(*d, *true_d);
+            }
+        }
+    }
+
+    #[test]
+    fn remove_matches_brute_force() {
+        let count = 500;
+        let mut tree = random_tree(count, 0);
+        let old_reader = tree.reader();
+
+        // The root's center, and a chunk of the others
+        let root_center = tree.root_address.1;
+        tree.remove(root_center).unwrap();
+        let removed: Vec<PointIndex> = (0..count as PointIndex)
+            .filter(|pi| *pi != root_center && pi % 5 == 0)
+            .collect();
+        tree.remove_batch(&removed).unwrap();
+        assert!(tree.remove(root_center).is_err());
+
+        // A batch that fails part way doesn't write anything, and doesn't change the node count.
+        let node_count = tree.reader().node_count();
+        assert!(tree.parameters.total_nodes.load(atomic::Ordering::SeqCst) == node_count);
+        let kept = (0..count as PointIndex)
+            .find(|pi| *pi != root_center && !removed.contains(pi))
+            .unwrap();
+        match tree.remove_batch(&[kept, root_center]) {
+            Err(MalwareBrotError::PointNotInTree(pi)) => assert!(pi == root_center),
+            _ => panic!("removing a point twice should fail"),
+        }
+        assert!(tree.reader().node_count() == node_count);
+        assert!(tree.parameters.total_nodes.load(atomic::Ordering::SeqCst) == node_count);
+
+        let reader = tree.reader();
+        let remaining: Vec<PointIndex> = (0..count as PointIndex)
+            .filter(|pi| *pi != root_center && !removed.contains(pi))
+            .collect();
+        assert!(reader.validate_points(&remaining).unwrap().is_valid());
+        let mut points = check_covering(&reader, reader.root_address());
+        points.sort();
+        assert!(points == remaining);
+        let root_count = reader.get_node_and(reader.root_address(), |n| n.cover_count());
+        assert!(root_count == Some(remaining.len()));
+
+        // A reader made before the root's center was removed follows the new root.
+        assert!(old_reader.root_address() == reader.root_address());
+        for _i in 0..20 {
+            let point = [rand::random::<f32>(), rand::random::<f32>()];
+            let mut brute: Vec<f32> = remaining
+                .iter()
+                .map(|i| L2::dense(&point, reader.point_cloud().get_point(*i).unwrap()))
+                .collect();
+            brute.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            for r in &[&reader, &old_reader] {
+                let knn = r.knn(&point, 5).unwrap();
+                assert!(knn.len() == 5);
+                for ((d, pi), true_d) in knn.iter().zip(&brute) {
+                    assert!(remaining.contains(pi));
+                    assert_approx_eq!(*d, *true_d);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn readers_see_whole_refreshes() {
+        let count = 400;
+        let mut tree = random_tree(count, 11);
+        let reader = tree.reader();
+
+        // Take out and put back the root's center, and chunks of the rest, while the reader queries.
+        let writer = std::thread::spawn(move || {
+            for round in 0..20 {
+                let root_center = tree.root_address.1;
+                let chunk: Vec<PointIndex> = (0..count as PointIndex)
+                    .filter(|pi| *pi != root_center && pi % 20 == round)
+                    .chain(std::iter::once(root_center))
+                    .collect();
+                tree.remove_batch(&chunk).unwrap();
+                tree.insert_batch(&chunk).unwrap();
+            }
+            tree
+        });
+        let mut rng = StdRng::seed_from_u64(11);
+        while !writer.is_finished() {
+            let point = [rng.gen::<f32>(), rng.gen::<f32>()];
+            let knn = reader.knn(&point, 5).unwrap();
+            assert!(knn.len() == 5);
+            assert!(knn.windows(2).all(|w| w[0].0 <= w[1].0));
+            assert!(reader.node_count() > 0);
+        }
+        let tree = writer.join().unwrap();
+        assert!(reader.root_address() == tree.root_address);
+        assert!(reader.validate_points(&(0..count as PointIndex).collect::<Vec<_>>()).unwrap().is_valid());
+
+        // A lazy query stops when the tree changes under it, rather than mixing two trees.
+        let mut tree = tree;
+        let mut nbrs = reader.nearest_neighbors(&[0.5, 0.5]).unwrap();
+        assert!(nbrs.next().is_some());
+        tree.remove(tree.root_address.1).unwrap();
+        assert!(nbrs.next().is_none());
+        assert!(matches!(nbrs.take_error(), Some(MalwareBrotError::TreeRefreshed)));
+    }
+
+    /// The cover count, children and singletons of every node.
+    type NodeContents = HashMap<NodeAddress, (usize, Option<(i32, Vec<NodeAddress>)>, Vec<PointIndex>)>;
+
+    /// The contents of every node, to see which nodes a change touched.
+    fn node_contents(reader: &CoverTreeReader<L2>) -> NodeContents {
+        let mut contents = HashMap::new();
+        for (_si, layer) in reader.layers() {
+            layer.for_each_node(|pi, n| {
+                let children = n.children().map(|(nested_scale, addresses)| (nested_scale, Vec::from(addresses)));
+                contents.insert(
+                    (*n.scale_index(), *pi),
+                    (n.cover_count(), children, Vec::from(n.singletons())),
+                );
+            });
+        }
+        contents
+    }
+
+    /// The number of nodes that were changed, added or removed.
+    fn touched_count(before: &NodeContents, after: &NodeContents) -> usize {
+        before
+            .iter()
+            .filter(|(address, contents)| after.get(address) != Some(contents))
+            .count()
+            + after.keys().filter(|address| !before.contains_key(address)).count()
+    }
+
+    #[test]
+    fn remove_center_promotes_a_child() {
+        let count = 2000;
+        let mut tree = random_tree(count, 0);
+        let mut remaining: Vec<PointIndex> = (0..count as PointIndex).collect();
+
+        // Rebuilding the subtree of the removed center would touch every node.
+        let before = node_contents(&tree.reader());
+        let root_center = tree.root_address.1;
+        tree.remove(root_center).unwrap();
+        remaining.retain(|pi| *pi != root_center);
+        let reader = tree.reader();
+        let after = node_contents(&reader);
+        assert!(touched_count(&before, &after) * 2 < before.len());
+        assert!(reader.validate_points(&remaining).unwrap().is_valid());
+        let mut points = check_covering(&reader, reader.root_address());
+        points.sort();
+        assert!(points == remaining);
+
+        let before = after;
+        let (_, center) = before
+            .iter()
+            .filter(|(address, contents)| address.0 == -4 && contents.1.is_some())
+            .map(|(address, _contents)| *address)
+            .min()
+            .unwrap();
+        tree.remove(center).unwrap();
+        remaining.retain(|pi| *pi != center);
+        let reader = tree.reader();
+        let after = node_contents(&reader);
+        assert!(touched_count(&before, &after) * 10 < before.len());
+        assert!(reader.validate_points(&remaining).unwrap().is_valid());
+        let mut points = check_covering(&reader, reader.root_address());
+        points.sort();
+        assert!(points == remaining);
+    }
+
+    #[test]
+    fn push_grows_the_point_cloud() {
+        let count = 300;
+        let mut tree = random_tree(count, 0);
+        let reader = tree.reader();
+
+        let mut metadata = Metadata::new();
+        metadata.insert("y".to_string(), Value::Vector(Vector::Real(vec![1.0])));
+        let far_point = [5.0, 5.0];
+        let pi = tree.push(&far_point, metadata.clone()).unwrap();
+        assert!(pi == count as PointIndex);
+
+        let batch: Vec<f32> = (0..2 * 100).map(|_i| rand::random::<f32>()).collect();
+        let names: Vec<PointName> = (0..100).map(|i| format!("new_{}", i)).collect();
+        let indexes = tree
+            .push_batch(&batch, vec![metadata.clone(); 100], Some(names))
+            .unwrap();
+        assert!(indexes[0] == count as PointIndex + 1);
+        let duplicate = tree.push_batch(&far_point, vec![metadata.clone()], Some(vec!["new_3".to_string()]));
+        assert!(duplicate.is_err());
+        let mut mistyped = Metadata::new();
+        mistyped.insert("y".to_string(), Value::Vector(Vector::Real(vec![1.0, 2.0])));
+        assert!(tree.push_batch(&far_point, vec![mistyped], None).is_err());
+        assert!(tree.push_batch(&batch[..3], vec![metadata.clone()], None).is_err());
+
+        // The old reader sees the new points in the point cloud, but not the tree's new root.
+        let point_cloud = reader.point_cloud();
+        assert!(point_cloud.len() == count + 101);
+        assert!(point_cloud.get_point(pi).unwrap() == &far_point[..]);
+        assert!(point_cloud.get_index(&"new_3".to_string()).is_none());
+        assert!(point_cloud.get_appended_index(&"new_3".to_string()) == Some(indexes[3]));
+        assert!(point_cloud.get_appended_name(&indexes[3]) == Some("new_3".to_string()));
+        let summary = point_cloud.get_metasummary(&indexes).unwrap();
+        assert!(summary.get("y").is_some());
+
+        let reader = tree.reader();
+        assert!(reader.knn(&far_point, 1).unwrap()[0] == (0.0, pi));
+        for (j, pi) in indexes.iter().enumerate() {
+            let knn = reader.knn(&batch[2 * j..2 * j + 2], 1).unwrap();
+            assert!(knn[0] == (0.0, *pi));
+        }
+    }
+
+    #[test]
+    fn failed_merges_and_pushes_report_their_points() {
+        // The other tree's labels don't fit our scheme, so the merge stops before it appends anything.
+        let count = 200;
+        let mut tree = random_tree(count, 0);
+        let mut label_scheme = LabelScheme::new();
+        label_scheme.add_f32("score".to_string());
+        let mut labels = label_scheme.empty();
+        for _i in 0..300 {
+            let mut metadata = Metadata::new();
+            metadata.insert("score".to_string(), Value::Number(Number::Real(0.0)));
+            labels.push(None, metadata).unwrap();
+        }
+        let mut rng = StdRng::seed_from_u64(1);
+        let data: Vec<f32> = (0..2 * 300).map(|_i| rng.gen::<f32>()).collect();
+        let other_point_cloud = PointCloud::<L2>::from_ram(Box::from(data), 2, labels).unwrap();
+        let other = test_builder().build(other_point_cloud).unwrap();
+        match tree.merge_from(&other.reader()) {
+            Err(MalwareBrotError::PointCloudError(PointCloudError::MetadataMismatch(key))) => {
+                assert!(key == "y")
+            }
+            _ => panic!("the merge should fail on the labels"),
+        }
+        let reader = tree.reader();
+        assert!(reader.point_cloud().len() == count);
+        assert!(reader.validate().unwrap().is_valid());
+
+        // A root whose nested child is missing, so nothing can be inserted under it.
+        let parameters = Arc::new(CoverTreeParameters {
+            total_nodes: atomic::AtomicUsize::new(1),
+            scale_base: 1.5,
+            cutoff: 3,
+            resolution: -9,
+            use_singletons: true,
+            cluster_min: 5,
+            point_cloud: Arc::new(random_point_cloud(2, 0)),
+            verbosity: 0,
+            seed: None,
+            center_selection: CenterSelection::Random,
+            summarize_subtrees: false,
+        });
+        let point = Vec::from(parameters.point_cloud.get_point(0).unwrap());
+        let mut tree = CoverTreeWriter::new(
+            parameters,
+            vec![
+                CoverLayerWriter::new(-9),
+                CoverLayerWriter::new(-9),
+                CoverLayerWriter::new(-8),
+            ],
+            (-8, 0),
+        );
+        let mut root = CoverNode::new((-8, 0));
+        root.insert_nested_child(-9, 1).unwrap();
+        unsafe {
+            tree.insert_raw(-8, 0, root);
+        }
+        tree.refresh();
+
+        let mut metadata = Metadata::new();
+        metadata.insert("y".to_string(), Value::Vector(Vector::Real(vec![0.0])));
+        match tree.push_batch(&point, vec![metadata], None) {
+            Err(MalwareBrotError::PointsNotInserted {
+                point_indexes,
+                error,
+            }) => {
+                assert!(point_indexes == vec![2]);
+                match *error {
+                    MalwareBrotError::NodeNotInTree(address) => assert!(address == (-9, 0)),
+                    _ => panic!("the missing child should be the cause"),
+                }
+            }
+            _ => panic!("the push should report the point it couldn't insert"),
+        }
+        assert!(tree.reader().point_cloud().len() == 3);
+    }
+
+    #[test]
+    fn cluster_matches_brute_force() {
+        let count = 500;
+        let mut tree = random_tree(count, 0);
+        assert!(tree.reader().root_cluster_address().is_none());
+        tree.cluster().unwrap();
+        let reader = tree.reader();
+        let point_cloud = reader.point_cloud();
+
+        let mut clustered_nodes = 0;
+        let mut pending = vec![reader.root_cluster_address().unwrap()];
+        while let Some((si, id)) = pending.pop() {
+            let cluster = reader.get_cluster_and((si, id), |c| c.clone()).unwrap();
+            clustered_nodes += cluster.indexes.len();
+
+            // The children clusters split the children of the cluster's nodes.
+            let mut children: Vec<NodeAddress> = Vec::new();
+            for pi in &cluster.indexes {
+                reader.layer(si).get_node_children_and(pi, |na, nas| {
+                    children.push(na);
+                    children.extend(nas);
+                });
+            }
+            let mut clustered_children: Vec<NodeAddress> = Vec::new();
+            let mut components: Vec<Vec<NodeAddress>> = Vec::new();
+            for child_address in &cluster.children_ids {
+                let child = reader.get_cluster_and(*child_address, |c| c.clone()).unwrap();
+                let component: Vec<NodeAddress> =
+                    child.indexes.iter().map(|pi| (child_address.0, *pi)).collect();
+                clustered_children.extend(&component);
+                components.push(component);
+                pending.push(*child_address);
+            }
+            children.sort();
+            clustered_children.sort();
+            assert!(children == clustered_children);
+
+            // Brute force the overlap components of the children.
+            let radius = |na: NodeAddress| reader.get_node_and(na, |n| n.radius().max(0.0)).unwrap();
+            let mut brute_components: Vec<Vec<NodeAddress>> = Vec::new();
+            for child in &children {
+                let center = point_cloud.get_point(child.1).unwrap();
+                let mut merged = vec![*child];
+                brute_components.retain(|component| {
+                    let overlaps = component.iter().any(|other| {
+                        let dist = point_cloud.distances_to_point(center, &[other.1]).unwrap()[0];
+                        other.0 == child.0 && dist < radius(*child) + radius(*other)
+                    });
+                    if overlaps {
+                        merged.extend(component);
+                    }
+                    !overlaps
+                });
+                brute_components.push(merged);
+            }
+            for component in brute_components.iter_mut().chain(components.iter_mut()) {
+                component.sort();
+            }
+            brute_components.sort();
+            components.sort();
+            assert!(brute_components == components);
+        }
+        assert!(clustered_nodes == reader.node_count());
+
+        // Clustering again starts over.
+        let first_root = reader.get_cluster_and(reader.root_cluster_address().unwrap(), |c| c.clone());
+        tree.cluster().unwrap();
+        let reader = tree.reader();
+        let total_clusters: usize = reader.layers().map(|(_si, l)| l.cluster_count()).sum();
+        assert!(total_clusters > 1);
+        assert!(reader.get_cluster_and(reader.root_cluster_address().unwrap(), |c| c.clone()) == first_root);
+    }
+
+    #[test]
+    fn clusters_survive_save_and_load() {
+        let count = 300;
+        let mut tree = random_tree(count, 0);
+        tree.cluster().unwrap();
+        let saved = tree.save();
+
+        let mut loaded = CoverTreeWriter::load(&saved, random_point_cloud(count, 0)).unwrap();
+        let reader = tree.reader();
+        let loaded_reader = loaded.reader();
+        let node_count = reader.node_count();
+        assert!(loaded.parameters.total_nodes.load(atomic::Ordering::SeqCst) == node_count);
+        assert!(loaded_reader.root_cluster_address() == reader.root_cluster_address());
+        for ((si, layer), (_lsi, loaded_layer)) in reader.layers().zip(loaded_reader.layers()) {
+            assert!(layer.cluster_count() == loaded_layer.cluster_count());
+            layer.for_each_cluster(|id, cluster| {
+                let loaded_cluster = loaded_reader.get_cluster_and((si, *id), |c| c.clone());
+                assert!(loaded_cluster.as_ref() == Some(cluster));
+            });
+        }
+        // The ids are handed out from 0, so the counter picks up after the last cluster.
+        for layer_proto in saved.get_layers() {
+            assert!(layer_proto.get_cluster_index() as usize == layer_proto.get_clusters().len());
+        }
+        assert!(loaded.save() == saved);
+
+        // The loaded tree keeps counting its nodes from there.
+        loaded.remove(loaded.root_address.1).unwrap();
+        let total_nodes = loaded.parameters.total_nodes.load(atomic::Ordering::SeqCst);
+        assert!(total_nodes == loaded_reader.node_count());
     }
 }
@@ -38,6 +38,7 @@ pub struct NodeProto {
     pub outlier_point_indexes: ::std::vec::Vec<u64>,
     pub outlier_summary_json: ::std::string::String,
     pub radius: f32,
+    pub subtree_summary_json: ::std::string::String,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -229,6 +230,32 @@ impl NodeProto {
     pub fn set_radius(&mut self, v: f32) {
         self.radius = v;
     }
+
+    // string subtree_summary_json = 10;
+
+
+    pub fn get_subtree_summary_json(&self) -> &str {
+        &self.subtree_summary_json
+    }
+    pub fn clear_subtree_summary_json(&mut self) {
+        self.subtree_summary_json.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_subtree_summary_json(&mut self, v: ::std::string::String) {
+        self.subtree_summary_json = v;
+    }
+
+    // Mutable pointer to the field.
+    // If field is not initialized, it is initialized with default value first.
+    pub fn mut_subtree_summary_json(&mut self) -> &mut ::std::string::String {
+        &mut self.subtree_summary_json
+    }
+
+    // Take field
+    pub fn take_subtree_summary_json(&mut self) -> ::std::string::String {
+        ::std::mem::replace(&mut self.subtree_summary_json, ::std::string::String::new())
+    }
 }
 
 impl ::protobuf::Message for NodeProto {
@@ -287,6 +314,9 @@ impl ::protobuf::Message for NodeProto {
                     let tmp = is.read_float()?;
                     self.radius = tmp;
                 },
+                10 => {
+                    ::protobuf::rt::read_singular_proto3_string_into(wire_type, is, &mut self.subtree_summary_json)?;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -326,6 +356,9 @@ impl ::protobuf::Message for NodeProto {
         if self.radius != 0. {
             my_size += 5;
         }
+        if !self.subtree_summary_json.is_empty() {
+            my_size += ::protobuf::rt::string_size(10, &self.subtree_summary_json);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -359,6 +392,9 @@ impl ::protobuf::Message for NodeProto {
         if self.radius != 0. {
             os.write_float(9, self.radius)?;
         }
+        if !self.subtree_summary_json.is_empty() {
+            os.write_string(10, &self.subtree_summary_json)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -446,6 +482,11 @@ impl ::protobuf::Message for NodeProto {
                     |m: &NodeProto| { &m.radius },
                     |m: &mut NodeProto| { &mut m.radius },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeString>(
+                    "subtree_summary_json",
+                    |m: &NodeProto| { &m.subtree_summary_json },
+                    |m: &mut NodeProto| { &mut m.subtree_summary_json },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<NodeProto>(
                     "NodeProto",
                     fields,
@@ -477,6 +518,7 @@ impl ::protobuf::Clear for NodeProto {
         self.outlier_point_indexes.clear();
         self.outlier_summary_json.clear();
         self.radius = 0.;
+        self.subtree_summary_json.clear();
         self.unknown_fields.clear();
     }
 }
@@ -493,11 +535,298 @@ impl ::protobuf::reflect::ProtobufValue for NodeProto {
     }
 }
 
+#[derive(PartialEq,Clone,Default)]
+pub struct ClusterProto {
+    // message fields
+    pub id: u64,
+    pub indexes: ::std::vec::Vec<u64>,
+    pub children_scale_indexes: ::std::vec::Vec<i32>,
+    pub children_cluster_ids: ::std::vec::Vec<u64>,
+    // special fields
+    pub unknown_fields: ::protobuf::UnknownFields,
+    pub cached_size: ::protobuf::CachedSize,
+}
+
+impl<'a> ::std::default::Default for &'a ClusterProto {
+    fn default() -> &'a ClusterProto {
+        <ClusterProto as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl ClusterProto {
+    pub fn new() -> ClusterProto {
+        ::std::default::Default::default()
+    }
+
+    // uint64 id = 1;
+
+
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+    pub fn clear_id(&mut self) {
+        self.id = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_id(&mut self, v: u64) {
+        self.id = v;
+    }
+
+    // repeated uint64 indexes = 2;
+
+
+    pub fn get_indexes(&self) -> &[u64] {
+        &self.indexes
+    }
+    pub fn clear_indexes(&mut self) {
+        self.indexes.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_indexes(&mut self, v: ::std::vec::Vec<u64>) {
+        self.indexes = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_indexes(&mut self) -> &mut ::std::vec::Vec<u64> {
+        &mut self.indexes
+    }
+
+    // Take field
+    pub fn take_indexes(&mut self) -> ::std::vec::Vec<u64> {
+        ::std::mem::replace(&mut self.indexes, ::std::vec::Vec::new())
+    }
+
+    // repeated int32 children_scale_indexes = 3;
+
+
+    pub fn get_children_scale_indexes(&self) -> &[i32] {
+        &self.children_scale_indexes
+    }
+    pub fn clear_children_scale_indexes(&mut self) {
+        self.children_scale_indexes.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_children_scale_indexes(&mut self, v: ::std::vec::Vec<i32>) {
+        self.children_scale_indexes = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_children_scale_indexes(&mut self) -> &mut ::std::vec::Vec<i32> {
+        &mut self.children_scale_indexes
+    }
+
+    // Take field
+    pub fn take_children_scale_indexes(&mut self) -> ::std::vec::Vec<i32> {
+        ::std::mem::replace(&mut self.children_scale_indexes, ::std::vec::Vec::new())
+    }
+
+    // repeated uint64 children_cluster_ids = 4;
+
+
+    pub fn get_children_cluster_ids(&self) -> &[u64] {
+        &self.children_cluster_ids
+    }
+    pub fn clear_children_cluster_ids(&mut self) {
+        self.children_cluster_ids.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_children_cluster_ids(&mut self, v: ::std::vec::Vec<u64>) {
+        self.children_cluster_ids = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_children_cluster_ids(&mut self) -> &mut ::std::vec::Vec<u64> {
+        &mut self.children_cluster_ids
+    }
+
+    // Take field
+    pub fn take_children_cluster_ids(&mut self) -> ::std::vec::Vec<u64> {
+        ::std::mem::replace(&mut self.children_cluster_ids, ::std::vec::Vec::new())
+    }
+}
+
+impl ::protobuf::Message for ClusterProto {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        while !is.eof()? {
+            let (field_number, wire_type) = is.read_tag_unpack()?;
+            match field_number {
+                1 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint64()?;
+                    self.id = tmp;
+                },
+                2 => {
+                    ::protobuf::rt::read_repeated_uint64_into(wire_type, is, &mut self.indexes)?;
+                },
+                3 => {
+                    ::protobuf::rt::read_repeated_int32_into(wire_type, is, &mut self.children_scale_indexes)?;
+                },
+                4 => {
+                    ::protobuf::rt::read_repeated_uint64_into(wire_type, is, &mut self.children_cluster_ids)?;
+                },
+                _ => {
+                    ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u32 {
+        let mut my_size = 0;
+        if self.id != 0 {
+            my_size += ::protobuf::rt::value_size(1, self.id, ::protobuf::wire_format::WireTypeVarint);
+        }
+        for value in &self.indexes {
+            my_size += ::protobuf::rt::value_size(2, *value, ::protobuf::wire_format::WireTypeVarint);
+        };
+        for value in &self.children_scale_indexes {
+            my_size += ::protobuf::rt::value_size(3, *value, ::protobuf::wire_format::WireTypeVarint);
+        };
+        for value in &self.children_cluster_ids {
+            my_size += ::protobuf::rt::value_size(4, *value, ::protobuf::wire_format::WireTypeVarint);
+        };
+        my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
+        self.cached_size.set(my_size);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::ProtobufResult<()> {
+        if self.id != 0 {
+            os.write_uint64(1, self.id)?;
+        }
+        for v in &self.indexes {
+            os.write_uint64(2, *v)?;
+        };
+        for v in &self.children_scale_indexes {
+            os.write_int32(3, *v)?;
+        };
+        for v in &self.children_cluster_ids {
+            os.write_uint64(4, *v)?;
+        };
+        os.write_unknown_fields(self.get_unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn get_cached_size(&self) -> u32 {
+        self.cached_size.get()
+    }
+
+    fn get_unknown_fields(&self) -> &::protobuf::UnknownFields {
+        &self.unknown_fields
+    }
+
+    fn mut_unknown_fields(&mut self) -> &mut ::protobuf::UnknownFields {
+        &mut self.unknown_fields
+    }
+
+    fn as_any(&self) -> &dyn (::std::any::Any) {
+        self as &dyn (::std::any::Any)
+    }
+    fn as_any_mut(&mut self) -> &mut dyn (::std::any::Any) {
+        self as &mut dyn (::std::any::Any)
+    }
+    fn into_any(self: Box<Self>) -> ::std::boxed::Box<dyn (::std::any::Any)> {
+        self
+    }
+
+    fn descriptor(&self) -> &'static ::protobuf::reflect::MessageDescriptor {
+        Self::descriptor_static()
+    }
+
+    fn new() -> ClusterProto {
+        ClusterProto::new()
+    }
+
+    fn descriptor_static() -> &'static ::protobuf::reflect::MessageDescriptor {
+        static mut descriptor: ::protobuf::lazy::Lazy<::protobuf::reflect::MessageDescriptor> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ::protobuf::reflect::MessageDescriptor,
+        };
+        unsafe {
+            descriptor.get(|| {
+                let mut fields = ::std::vec::Vec::new();
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "id",
+                    |m: &ClusterProto| { &m.id },
+                    |m: &mut ClusterProto| { &mut m.id },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "indexes",
+                    |m: &ClusterProto| { &m.indexes },
+                    |m: &mut ClusterProto| { &mut m.indexes },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeInt32>(
+                    "children_scale_indexes",
+                    |m: &ClusterProto| { &m.children_scale_indexes },
+                    |m: &mut ClusterProto| { &mut m.children_scale_indexes },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_vec_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "children_cluster_ids",
+                    |m: &ClusterProto| { &m.children_cluster_ids },
+                    |m: &mut ClusterProto| { &mut m.children_cluster_ids },
+                ));
+                ::protobuf::reflect::MessageDescriptor::new::<ClusterProto>(
+                    "ClusterProto",
+                    fields,
+                    file_descriptor_proto()
+                )
+            })
+        }
+    }
+
+    fn default_instance() -> &'static ClusterProto {
+        static mut instance: ::protobuf::lazy::Lazy<ClusterProto> = ::protobuf::lazy::Lazy {
+            lock: ::protobuf::lazy::ONCE_INIT,
+            ptr: 0 as *const ClusterProto,
+        };
+        unsafe {
+            instance.get(ClusterProto::new)
+        }
+    }
+}
+
+impl ::protobuf::Clear for ClusterProto {
+    fn clear(&mut self) {
+        self.id = 0;
+        self.indexes.clear();
+        self.children_scale_indexes.clear();
+        self.children_cluster_ids.clear();
+        self.unknown_fields.clear();
+    }
+}
+
+impl ::std::fmt::Debug for ClusterProto {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+        ::protobuf::text_format::fmt(self, f)
+    }
+}
+
+impl ::protobuf::reflect::ProtobufValue for ClusterProto {
+    fn as_ref(&self) -> ::protobuf::reflect::ProtobufValueRef {
+        ::protobuf::reflect::ProtobufValueRef::Message(self)
+    }
+}
+
 #[derive(PartialEq,Clone,Default)]
 pub struct LayerProto {
     // message fields
     pub scale_index: i32,
     pub nodes: ::protobuf::RepeatedField<NodeProto>,
+    pub clusters: ::protobuf::RepeatedField<ClusterProto>,
+    pub cluster_index: u64,
     // special fields
     pub unknown_fields: ::protobuf::UnknownFields,
     pub cached_size: ::protobuf::CachedSize,
@@ -553,6 +882,46 @@ impl LayerProto {
     pub fn take_nodes(&mut self) -> ::protobuf::RepeatedField<NodeProto> {
         ::std::mem::replace(&mut self.nodes, ::protobuf::RepeatedField::new())
     }
+
+    // repeated .CoverTree.ClusterProto clusters = 3;
+
+
+    pub fn get_clusters(&self) -> &[ClusterProto] {
+        &self.clusters
+    }
+    pub fn clear_clusters(&mut self) {
+        self.clusters.clear();
+    }
+
+    // Param is passed by value, moved
+    pub fn set_clusters(&mut self, v: ::protobuf::RepeatedField<ClusterProto>) {
+        self.clusters = v;
+    }
+
+    // Mutable pointer to the field.
+    pub fn mut_clusters(&mut self) -> &mut ::protobuf::RepeatedField<ClusterProto> {
+        &mut self.clusters
+    }
+
+    // Take field
+    pub fn take_clusters(&mut self) -> ::protobuf::RepeatedField<ClusterProto> {
+        ::std::mem::replace(&mut self.clusters, ::protobuf::RepeatedField::new())
+    }
+
+    // uint64 cluster_index = 4;
+
+
+    pub fn get_cluster_index(&self) -> u64 {
+        self.cluster_index
+    }
+    pub fn clear_cluster_index(&mut self) {
+        self.cluster_index = 0;
+    }
+
+    // Param is passed by value, moved
+    pub fn set_cluster_index(&mut self, v: u64) {
+        self.cluster_index = v;
+    }
 }
 
 impl ::protobuf::Message for LayerProto {
@@ -562,6 +931,11 @@ impl ::protobuf::Message for LayerProto {
                 return false;
             }
         };
+        for v in &self.clusters {
+            if !v.is_initialized() {
+                return false;
+            }
+        };
         true
     }
 
@@ -579,6 +953,16 @@ impl ::protobuf::Message for LayerProto {
                 2 => {
                     ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.nodes)?;
                 },
+                3 => {
+                    ::protobuf::rt::read_repeated_message_into(wire_type, is, &mut self.clusters)?;
+                },
+                4 => {
+                    if wire_type != ::protobuf::wire_format::WireTypeVarint {
+                        return ::std::result::Result::Err(::protobuf::rt::unexpected_wire_type(wire_type));
+                    }
+                    let tmp = is.read_uint64()?;
+                    self.cluster_index = tmp;
+                },
                 _ => {
                     ::protobuf::rt::read_unknown_or_skip_group(field_number, wire_type, is, self.mut_unknown_fields())?;
                 },
@@ -598,6 +982,13 @@ impl ::protobuf::Message for LayerProto {
             let len = value.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
         };
+        for value in &self.clusters {
+            let len = value.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint32_size(len) + len;
+        };
+        if self.cluster_index != 0 {
+            my_size += ::protobuf::rt::value_size(4, self.cluster_index, ::protobuf::wire_format::WireTypeVarint);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.get_unknown_fields());
         self.cached_size.set(my_size);
         my_size
@@ -612,6 +1003,14 @@ impl ::protobuf::Message for LayerProto {
             os.write_raw_varint32(v.get_cached_size())?;
             v.write_to_with_cached_sizes(os)?;
         };
+        for v in &self.clusters {
+            os.write_tag(3, ::protobuf::wire_format::WireTypeLengthDelimited)?;
+            os.write_raw_varint32(v.get_cached_size())?;
+            v.write_to_with_cached_sizes(os)?;
+        };
+        if self.cluster_index != 0 {
+            os.write_uint64(4, self.cluster_index)?;
+        }
         os.write_unknown_fields(self.get_unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -664,6 +1063,16 @@ impl ::protobuf::Message for LayerProto {
                     |m: &LayerProto| { &m.nodes },
                     |m: &mut LayerProto| { &mut m.nodes },
                 ));
+                fields.push(::protobuf::reflect::accessor::make_repeated_field_accessor::<_, ::protobuf::types::ProtobufTypeMessage<ClusterProto>>(
+                    "clusters",
+                    |m: &LayerProto| { &m.clusters },
+                    |m: &mut LayerProto| { &mut m.clusters },
+                ));
+                fields.push(::protobuf::reflect::accessor::make_simple_field_accessor::<_, ::protobuf::types::ProtobufTypeUint64>(
+                    "cluster_index",
+                    |m: &LayerProto| { &m.cluster_index },
+                    |m: &mut LayerProto| { &mut m.cluster_index },
+                ));
                 ::protobuf::reflect::MessageDescriptor::new::<LayerProto>(
                     "LayerProto",
                     fields,
@@ -688,6 +1097,8 @@ impl ::protobuf::Clear for LayerProto {
     fn clear(&mut self) {
         self.scale_index = 0;
         self.nodes.clear();
+        self.clusters.clear();
+        self.cluster_index = 0;
         self.unknown_fields.clear();
     }
 }
@@ -1161,7 +1572,7 @@ impl ::protobuf::reflect::ProtobufValue for CoreProto {
 }
 
 static file_descriptor_proto_data: &'static [u8] = b"\
-    \n\x16tree_file_format.proto\x12\tCoverTree\"\x80\x03\n\tNodeProto\x12\
+    \n\x16tree_file_format.proto\x12\tCoverTree\"\xb2\x03\n\tNodeProto\x12\
     \x1f\n\x0bcover_count\x18\x01\x20\x01(\x04R\ncoverCount\x12!\n\x0ccenter\
     _index\x18\x02\x20\x01(\x04R\x0bcenterIndex\x12\x17\n\x07is_leaf\x18\x03\
     \x20\x01(\x08R\x06isLeaf\x12,\n\x12nested_scale_index\x18\x04\x20\x01(\
@@ -1170,16 +1581,24 @@ static file_descriptor_proto_data: &'static [u8] = b"\
     \x06\x20\x03(\x05R\x14childrenScaleIndexes\x122\n\x15outlier_point_index\
     es\x18\x07\x20\x03(\x04R\x13outlierPointIndexes\x120\n\x14outlier_summar\
     y_json\x18\x08\x20\x01(\tR\x12outlierSummaryJson\x12\x16\n\x06radius\x18\
-    \t\x20\x01(\x02R\x06radius\"Y\n\nLayerProto\x12\x1f\n\x0bscale_index\x18\
-    \x01\x20\x01(\x05R\nscaleIndex\x12*\n\x05nodes\x18\x02\x20\x03(\x0b2\x14\
-    .CoverTree.NodeProtoR\x05nodes\"\x9e\x02\n\tCoreProto\x12%\n\x0euse_sing\
-    letons\x18\x01\x20\x01(\x08R\ruseSingletons\x12\x1d\n\nscale_base\x18\
-    \x02\x20\x01(\x02R\tscaleBase\x12\x16\n\x06cutoff\x18\x03\x20\x01(\x04R\
-    \x06cutoff\x12\x1e\n\nresolution\x18\x04\x20\x01(\x11R\nresolution\x12\
-    \x10\n\x03dim\x18\x07\x20\x01(\x04R\x03dim\x12\x14\n\x05count\x18\x08\
-    \x20\x01(\x04R\x05count\x12\x1d\n\nroot_scale\x18\t\x20\x01(\x05R\trootS\
-    cale\x12\x1d\n\nroot_index\x18\n\x20\x01(\x04R\trootIndex\x12-\n\x06laye\
-    rs\x18\x0b\x20\x03(\x0b2\x15.CoverTree.LayerProtoR\x06layersb\x06proto3\
+    \t\x20\x01(\x02R\x06radius\x120\n\x14subtree_summary_json\x18\n\x20\x01(\
+    \tR\x12subtreeSummaryJson\"\xa0\x01\n\x0cClusterProto\x12\x0e\n\x02id\
+    \x18\x01\x20\x01(\x04R\x02id\x12\x18\n\x07indexes\x18\x02\x20\x03(\x04R\
+    \x07indexes\x124\n\x16children_scale_indexes\x18\x03\x20\x03(\x05R\x14ch\
+    ildrenScaleIndexes\x120\n\x14children_cluster_ids\x18\x04\x20\x03(\x04R\
+    \x12childrenClusterIds\"\xb3\x01\n\nLayerProto\x12\x1f\n\x0bscale_index\
+    \x18\x01\x20\x01(\x05R\nscaleIndex\x12*\n\x05nodes\x18\x02\x20\x03(\x0b2\
+    \x14.CoverTree.NodeProtoR\x05nodes\x123\n\x08clusters\x18\x03\x20\x03(\
+    \x0b2\x17.CoverTree.ClusterProtoR\x08clusters\x12#\n\rcluster_index\x18\
+    \x04\x20\x01(\x04R\x0cclusterIndex\"\x9e\x02\n\tCoreProto\x12%\n\x0euse_\
+    singletons\x18\x01\x20\x01(\x08R\ruseSingletons\x12\x1d\n\nscale_base\
+    \x18\x02\x20\x01(\x02R\tscaleBase\x12\x16\n\x06cutoff\x18\x03\x20\x01(\
+    \x04R\x06cutoff\x12\x1e\n\nresolution\x18\x04\x20\x01(\x11R\nresolution\
+    \x12\x10\n\x03dim\x18\x07\x20\x01(\x04R\x03dim\x12\x14\n\x05count\x18\
+    \x08\x20\x01(\x04R\x05count\x12\x1d\n\nroot_scale\x18\t\x20\x01(\x05R\tr\
+    ootScale\x12\x1d\n\nroot_index\x18\n\x20\x01(\x04R\trootIndex\x12-\n\x06\
+    layers\x18\x0b\x20\x03(\x0b2\x15.CoverTree.LayerProtoR\x06layersb\x06pro\
+    to3\
 ";
 
 static mut file_descriptor_proto_lazy: ::protobuf::lazy::Lazy<::protobuf::descriptor::FileDescriptorProto> = ::protobuf::lazy::Lazy {
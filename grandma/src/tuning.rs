@@ -0,0 +1,482 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Parameter Tuning
+//! Proposes the `scale_base`, `cutoff` and `resolution` for a point cloud, instead of guessing them.
+//!
+//! The tuner samples the point cloud and computes all the distances within the sample. From those it gets the distance
+//! distribution and the intrinsic dimension, with the two nearest neighbors estimator of Facco et al. The dimension tells
+//! us how much closer the nearest neighbors are in the full point cloud than in the sample, and the resolution is set to
+//! the scale of those nearest neighbor distances.
+//!
+//! Then it builds a tree over the sample for each candidate scale base and cutoff, measures its memory and KNN query time,
+//! and scales them up to the size of the point cloud. Memory grows linearly with the number of points, and query time
+//! with its log. These are rough, but they're good enough to rank the candidates. The candidate that meets the target
+//! and is best at the other measure is proposed.
+
+use crate::errors::MalwareBrotError;
+use crate::*;
+use node::CoverNode;
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::{thread_rng, RngCore, SeedableRng};
+use std::fmt;
+use std::mem::size_of;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What the proposed tree has to meet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TuningTarget {
+    /// The tree should fit in this many bytes. The fastest tree that fits is proposed.
+    MemoryBudget(usize),
+    /// A KNN query should take at most this long. The smallest tree that's fast enough is proposed.
+    QueryLatency(Duration),
+}
+
+/// The estimates for one of the configurations the tuner tried.
+#[derive(Debug, Clone)]
+pub struct TuningCandidate {
+    /// The scale base that was tried
+    pub scale_base: f32,
+    /// The cutoff that was tried
+    pub cutoff: usize,
+    /// The resolution for this scale base
+    pub resolution: i32,
+    /// The estimated number of nodes in the full tree
+    pub estimated_nodes: usize,
+    /// The estimated memory used by the full tree's nodes, in bytes. This doesn't include the point cloud.
+    pub estimated_memory: usize,
+    /// The estimated time of a KNN query on the full tree
+    pub estimated_query_time: Duration,
+    /// If these estimates meet the target
+    pub meets_target: bool,
+}
+
+/// What the tuner found and what it proposes.
+#[derive(Debug, Clone)]
+pub struct TuningReport {
+    /// The target the tuner aimed for
+    pub target: TuningTarget,
+    /// The number of points in the point cloud
+    pub point_count: usize,
+    /// The number of points that were sampled
+    pub sample_size: usize,
+    /// The 1st, 50th and 99th percentile of the distances within the sample
+    pub distance_percentiles: [f32; 3],
+    /// The median distance to the nearest neighbor in the sample
+    pub sample_neighbor_distance: f32,
+    /// The estimated median distance to the nearest neighbor in the full point cloud
+    pub neighbor_distance: f32,
+    /// The estimated intrinsic dimension
+    pub intrinsic_dimension: f32,
+    /// Every configuration that was tried
+    pub candidates: Vec<TuningCandidate>,
+    /// The position of the proposed configuration in `candidates`
+    pub chosen: usize,
+    /// A builder with the proposed configuration
+    pub builder: CoverTreeBuilder,
+}
+
+impl TuningReport {
+    /// The proposed configuration.
+    pub fn chosen(&self) -> &TuningCandidate {
+        &self.candidates[self.chosen]
+    }
+}
+
+impl fmt::Display for TuningReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Sampled {} of {} points. Distances: 1% {}, median {}, 99% {}.",
+            self.sample_size,
+            self.point_count,
+            self.distance_percentiles[0],
+            self.distance_percentiles[1],
+            self.distance_percentiles[2]
+        )?;
+        writeln!(
+            f,
+            "Intrinsic dimension about {:.1}, nearest neighbors about {} apart ({} in the sample).",
+            self.intrinsic_dimension, self.neighbor_distance, self.sample_neighbor_distance
+        )?;
+        writeln!(f, "Target: {:?}", self.target)?;
+        writeln!(f, "scale_base\tcutoff\tresolution\tnodes\tmemory\tquery\tmeets target")?;
+        for (i, c) in self.candidates.iter().enumerate() {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}\t{}\t{:?}\t{}{}",
+                c.scale_base,
+                c.cutoff,
+                c.resolution,
+                c.estimated_nodes,
+                c.estimated_memory,
+                c.estimated_query_time,
+                c.meets_target,
+                if i == self.chosen { "\t<- proposed" } else { "" }
+            )?;
+        }
+        if !self.chosen().meets_target {
+            writeln!(
+                f,
+                "Nothing met the target, the proposed configuration is the closest."
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Samples a point cloud and proposes a builder configuration for it, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct ParameterTuner {
+    /// The number of points to sample. All the distances within the sample are computed, so this is quadratic.
+    pub sample_size: usize,
+    /// The number of neighbors in the timed KNN queries
+    pub k: usize,
+    /// The number of timed KNN queries per candidate
+    pub query_count: usize,
+    /// The scale bases to try
+    pub scale_bases: Vec<f32>,
+    /// The cutoffs to try
+    pub cutoffs: Vec<usize>,
+    /// Passed on to the builders, see `CoverTreeBuilder::use_singletons`
+    pub use_singletons: bool,
+    /// Seed for the sample and the sample trees
+    pub seed: Option<u64>,
+}
+
+impl Default for ParameterTuner {
+    fn default() -> ParameterTuner {
+        ParameterTuner::new()
+    }
+}
+
+impl ParameterTuner {
+    /// Creates a new tuner with sensible defaults.
+    pub fn new() -> ParameterTuner {
+        ParameterTuner {
+            sample_size: 1000,
+            k: 10,
+            query_count: 100,
+            scale_bases: vec![1.2, 1.3, 1.5, 1.7, 2.0],
+            cutoffs: vec![1, 5, 20, 50],
+            use_singletons: true,
+            seed: None,
+        }
+    }
+    /// Set the sample size.
+    pub fn set_sample_size(&mut self, x: usize) -> &mut Self {
+        self.sample_size = x;
+        self
+    }
+    /// Set the k of the timed queries.
+    pub fn set_k(&mut self, x: usize) -> &mut Self {
+        self.k = x;
+        self
+    }
+    /// Set the number of timed queries.
+    pub fn set_query_count(&mut self, x: usize) -> &mut Self {
+        self.query_count = x;
+        self
+    }
+    /// Set the scale bases to try.
+    pub fn set_scale_bases(&mut self, x: Vec<f32>) -> &mut Self {
+        self.scale_bases = x;
+        self
+    }
+    /// Set the cutoffs to try.
+    pub fn set_cutoffs(&mut self, x: Vec<usize>) -> &mut Self {
+        self.cutoffs = x;
+        self
+    }
+    /// Set the use_singletons flag of the proposed builder.
+    pub fn set_use_singletons(&mut self, x: bool) -> &mut Self {
+        self.use_singletons = x;
+        self
+    }
+    /// Set the seed.
+    pub fn set_seed(&mut self, x: u64) -> &mut Self {
+        self.seed = Some(x);
+        self
+    }
+
+    /// Samples the point cloud, tries the candidates on the sample and proposes one. Returns an `EmptyTree` error if the
+    /// sample has fewer than 2 points, as there are no distances to estimate from.
+    pub fn tune<M: Metric>(
+        &self,
+        point_cloud: Arc<PointCloud<M>>,
+        target: TuningTarget,
+    ) -> MalwareBrotResult<TuningReport> {
+        let mut rng: Box<dyn RngCore> = match self.seed {
+            Some(seed) => Box::new(StdRng::seed_from_u64(seed)),
+            None => Box::new(thread_rng()),
+        };
+        let point_indexes = point_cloud.reference_indexes();
+        let point_count = point_indexes.len();
+        let sample_size = self.sample_size.min(point_count);
+        if sample_size < 2 {
+            return Err(MalwareBrotError::EmptyTree);
+        }
+        let mut sample_indexes: Vec<PointIndex> = sample(&mut rng, point_count, sample_size)
+            .into_iter()
+            .map(|i| point_indexes[i])
+            .collect();
+        sample_indexes.sort();
+
+        let mut distances = Vec::with_capacity(sample_size * (sample_size - 1) / 2);
+        let mut nearest = Vec::with_capacity(sample_size);
+        let mut log_ratio_sum = 0.0;
+        let mut ratio_count = 0;
+        for pi in &sample_indexes {
+            let mut row = point_cloud.distances_to_point_index(*pi, &sample_indexes)?;
+            distances.extend(row.iter().filter(|d| **d > 0.0).cloned());
+            // Duplicates and the point itself are at 0, they'd throw off the dimension estimate.
+            row.retain(|d| *d > 0.0);
+            row.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            if row.len() >= 2 {
+                nearest.push(row[0]);
+                log_ratio_sum += (row[1] / row[0]).ln();
+                ratio_count += 1;
+            }
+        }
+        if nearest.is_empty() {
+            return Err(MalwareBrotError::EmptyTree);
+        }
+        let distance_percentiles = [
+            percentile(&mut distances, 0.01),
+            percentile(&mut distances, 0.5),
+            percentile(&mut distances, 0.99),
+        ];
+        let intrinsic_dimension = if log_ratio_sum > 0.0 {
+            ratio_count as f32 / log_ratio_sum
+        } else {
+            1.0
+        };
+        let sample_neighbor_distance = percentile(&mut nearest, 0.5);
+        // The nearest neighbor distance shrinks like n^(-1/d)
+        let neighbor_distance = sample_neighbor_distance
+            * (sample_size as f32 / point_count as f32).powf(1.0 / intrinsic_dimension.max(1.0));
+
+        let memory_scale = point_count as f32 / sample_size as f32;
+        let latency_scale = (point_count as f32).ln() / (sample_size as f32).ln();
+        let query_count = self.query_count.min(sample_size).max(1);
+        let queries: Vec<PointIndex> = sample(&mut rng, sample_size, query_count)
+            .into_iter()
+            .map(|i| sample_indexes[i])
+            .collect();
+
+        let mut candidates = Vec::new();
+        for scale_base in &self.scale_bases {
+            let resolution = neighbor_distance.log(*scale_base).floor() as i32;
+            for cutoff in &self.cutoffs {
+                let builder = self.builder(*scale_base, *cutoff, resolution);
+                let tree = builder.build_subset(Arc::clone(&point_cloud), &sample_indexes)?;
+                let reader = tree.reader();
+
+                let (nodes, memory) = node_memory(&reader);
+                let now = Instant::now();
+                for pi in &queries {
+                    reader.knn(point_cloud.get_point(*pi)?, self.k)?;
+                }
+                let query_time = now.elapsed() / query_count as u32;
+
+                let estimated_query_time = query_time.mul_f32(latency_scale);
+                let estimated_memory = (memory as f32 * memory_scale) as usize;
+                let meets_target = match target {
+                    TuningTarget::MemoryBudget(budget) => estimated_memory <= budget,
+                    TuningTarget::QueryLatency(latency) => estimated_query_time <= latency,
+                };
+                candidates.push(TuningCandidate {
+                    scale_base: *scale_base,
+                    cutoff: *cutoff,
+                    resolution,
+                    estimated_nodes: (nodes as f32 * memory_scale) as usize,
+                    estimated_memory,
+                    estimated_query_time,
+                    meets_target,
+                });
+            }
+        }
+        if candidates.is_empty() {
+            return Err(MalwareBrotError::EmptyTree);
+        }
+
+        // The best one at the other measure of those that meet the target, otherwise the one closest to the target.
+        let meets_target = candidates.iter().any(|c| c.meets_target);
+        let chosen = (0..candidates.len())
+            .filter(|i| candidates[*i].meets_target || !meets_target)
+            .min_by_key(|i| {
+                let c = &candidates[*i];
+                match (target, meets_target) {
+                    (TuningTarget::MemoryBudget(_), true) => c.estimated_query_time.as_nanos(),
+                    (TuningTarget::MemoryBudget(_), false) => c.estimated_memory as u128,
+                    (TuningTarget::QueryLatency(_), true) => c.estimated_memory as u128,
+                    (TuningTarget::QueryLatency(_), false) => c.estimated_query_time.as_nanos(),
+                }
+            })
+            .unwrap();
+        let builder = {
+            let c = &candidates[chosen];
+            self.builder(c.scale_base, c.cutoff, c.resolution)
+        };
+
+        Ok(TuningReport {
+            target,
+            point_count,
+            sample_size,
+            distance_percentiles,
+            sample_neighbor_distance,
+            neighbor_distance,
+            intrinsic_dimension,
+            candidates,
+            chosen,
+            builder,
+        })
+    }
+
+    fn builder(&self, scale_base: f32, cutoff: usize, resolution: i32) -> CoverTreeBuilder {
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_scale_base(scale_base)
+            .set_cutoff(cutoff)
+            .set_resolution(resolution)
+            .set_use_singletons(self.use_singletons)
+            .set_verbosity(0);
+        if let Some(seed) = self.seed {
+            builder.set_seed(seed);
+        }
+        builder
+    }
+}
+
+/// Sorts the values and picks the one at the fraction.
+fn percentile(values: &mut [f32], fraction: f32) -> f32 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let i = ((values.len() - 1) as f32 * fraction).round() as usize;
+    values[i]
+}
+
+/// The number of nodes and a rough count of the bytes they take up. The layers are evmaps, which keep two copies of
+/// every node.
+fn node_memory<M: Metric>(reader: &CoverTreeReader<M>) -> (usize, usize) {
+    let mut nodes = 0;
+    let mut memory = 0;
+    for (_si, layer) in reader.layers() {
+        layer.for_each_node(|_pi, n| {
+            nodes += 1;
+            memory += size_of::<PointIndex>()
+                + size_of::<CoverNode>()
+                + n.singleton_len() * size_of::<PointIndex>()
+                + n.children_len() * size_of::<NodeAddress>();
+        });
+    }
+    (nodes, 2 * memory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tuner_meets_targets() {
+        let count = 2000;
+        // Points on a line in 3 dimensions, so the intrinsic dimension is 1.
+        let mut data = Vec::with_capacity(3 * count);
+        for _i in 0..count {
+            let t = rand::random::<f32>();
+            data.extend(&[t, 2.0 * t, -t]);
+        }
+        let labels: Vec<f32> = vec![0.0; count];
+        let point_cloud = Arc::new(
+            PointCloud::<L2>::simple_from_ram(Box::from(data), 3, Box::from(labels), 1).unwrap(),
+        );
+        let mut tuner = ParameterTuner::new();
+        tuner
+            .set_sample_size(300)
+            .set_query_count(20)
+            .set_scale_bases(vec![1.3, 2.0])
+            .set_cutoffs(vec![1, 20])
+            .set_seed(0);
+
+        let report = tuner
+            .tune(Arc::clone(&point_cloud), TuningTarget::MemoryBudget(usize::MAX))
+            .unwrap();
+        assert!(report.candidates.len() == 4);
+        assert!(report.sample_size == 300);
+        assert!(report.intrinsic_dimension > 0.5 && report.intrinsic_dimension < 1.5);
+        assert!(report.neighbor_distance < report.sample_neighbor_distance);
+        assert!(report.candidates.iter().all(|c| c.meets_target));
+        let fastest = report
+            .candidates
+            .iter()
+            .map(|c| c.estimated_query_time)
+            .min()
+            .unwrap();
+        assert!(report.chosen().estimated_query_time == fastest);
+
+        // Nothing fits, so the smallest is proposed.
+        let report = tuner
+            .tune(Arc::clone(&point_cloud), TuningTarget::MemoryBudget(1))
+            .unwrap();
+        assert!(!report.chosen().meets_target);
+        let smallest = report.candidates.iter().map(|c| c.estimated_memory).min().unwrap();
+        assert!(report.chosen().estimated_memory == smallest);
+
+        let chosen = report.chosen().clone();
+        assert!(report.builder.scale_base == chosen.scale_base);
+        assert!(report.builder.cutoff == chosen.cutoff);
+        assert!(report.builder.resolution == chosen.resolution);
+        let tree = report
+            .builder
+            .build_subset(Arc::clone(&point_cloud), &(0..100).collect::<Vec<PointIndex>>())
+            .unwrap();
+        assert!(tree.reader().validate().unwrap().is_valid());
+
+        // Everything is fast enough, so the smallest is proposed.
+        let report = tuner
+            .tune(Arc::clone(&point_cloud), TuningTarget::QueryLatency(Duration::from_secs(3600)))
+            .unwrap();
+        assert!(report.candidates.iter().all(|c| c.meets_target));
+        let smallest = report.candidates.iter().map(|c| c.estimated_memory).min().unwrap();
+        assert!(report.chosen().estimated_memory == smallest);
+
+        // Nothing is fast enough, so the fastest is proposed.
+        let report = tuner
+            .tune(Arc::clone(&point_cloud), TuningTarget::QueryLatency(Duration::from_secs(0)))
+            .unwrap();
+        assert!(!report.chosen().meets_target);
+        let fastest = report
+            .candidates
+            .iter()
+            .map(|c| c.estimated_query_time)
+            .min()
+            .unwrap();
+        assert!(report.chosen().estimated_query_time == fastest);
+
+        tuner.set_sample_size(1);
+        match tuner.tune(point_cloud, TuningTarget::MemoryBudget(usize::MAX)) {
+            Err(MalwareBrotError::EmptyTree) => {}
+            _ => panic!("a sample of one point can't be tuned on"),
+        }
+    }
+}
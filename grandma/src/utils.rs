@@ -21,108 +21,175 @@
 
 use crate::errors::MalwareBrotResult;
 use crate::tree_file_format::*;
+use pointcloud::config::*;
+use pointcloud::errors::{ParsingError, PointCloudError};
 use pointcloud::*;
 use protobuf::{CodedInputStream, CodedOutputStream, Message};
+use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::fs::{remove_file, OpenOptions};
-use std::io::Read;
 use std::path::Path;
-use yaml_rust::{Yaml, YamlLoader};
+use yaml_rust::Yaml;
 
-use crate::builders::CoverTreeBuilder;
+use crate::builders::{CenterSelection, CoverTreeBuilder};
 use crate::errors::MalwareBrotError;
 use crate::tree::CoverTreeWriter;
 
-/// Given a yaml file on disk, it builds a covertree.
+/// Everything needed to build a cover tree: the point cloud's config and the builder's parameters. The fields all sit at
+/// the top level of the file.
 ///
 /// ```yaml
 /// ---
 /// cutoff: 5
 /// resolution: -10
 /// scale_base: 1.3
+/// use_singletons: true
+/// verbosity: 0 (optional, defaults to 2)
+/// seed: 42 (optional)
+/// center_selection: random (optional, or farthest_first, approximate_medoid, density_weighted)
 /// data_path: DATAMEMMAPs
 /// labels_path: LABELS_CSVs
 /// count: NUMBER_OF_DATA_POINTS
 /// data_dim: 784
-/// in_ram: True
-/// file: mnist.tree (optional, if here and the file exists it loads it)
+/// in_ram: true
 /// schema:
 ///    natural: u32
 ///    integer: i32
 ///    real: f32
-///    string: String
+///    string: string
 ///    boolean: bool
 /// ```
-/// Example without a schema:
-/// ```yaml
-/// ---
-/// cutoff: 5
-/// resolution: -10
-/// scale_base: 1.3
-/// data_path: DATAMEMMAPs
-/// labels_path: LABELS_CSV_OR_MEMMAPs
-/// count: NUMBER_OF_DATA_POINTS
-/// data_dim: 784
-/// labels_dim: 10
-/// in_ram: True
-/// file: mnist.tree (optional, if here and the file exists it loads it)
-/// ```
-///
-pub fn cover_tree_from_yaml<P: AsRef<Path>>(path: P) -> MalwareBrotResult<CoverTreeWriter<L2>> {
-    let mut config_file = File::open(&path).expect("Unable to open config file");
-
-    let mut config = String::new();
-
-    config_file
-        .read_to_string(&mut config)
-        .expect("Unable to read config file");
-    let params_files = YamlLoader::load_from_str(&config).unwrap();
-    let params = &params_files[0];
-
-    let point_cloud: PointCloud<L2> = PointCloud::<L2>::from_yaml(&params)?;
-    if let Some(count) = params["count"].as_i64() {
-        if count as usize != point_cloud.len() {
-            panic!(
-                "We expected {:?} points, but the file has {:?} points at dim {:?}",
-                count,
-                point_cloud.len(),
-                point_cloud.dim()
-            );
+/// See `PointCloudConfig` for the point cloud's fields. The same fields can be written in JSON or TOML.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CoverTreeConfig {
+    /// Where the data is and how to read it
+    #[serde(flatten)]
+    pub point_cloud: PointCloudConfig,
+    /// See `CoverTreeBuilder::scale_base`
+    pub scale_base: f32,
+    /// See `CoverTreeBuilder::cutoff`
+    pub cutoff: usize,
+    /// See `CoverTreeBuilder::resolution`
+    pub resolution: i32,
+    /// See `CoverTreeBuilder::use_singletons`
+    #[serde(deserialize_with = "deserialize_flag")]
+    pub use_singletons: bool,
+    /// See `CoverTreeBuilder::verbosity`
+    #[serde(default = "default_verbosity")]
+    pub verbosity: u32,
+    /// See `CoverTreeBuilder::seed`
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// See `CoverTreeBuilder::center_selection`
+    #[serde(default = "default_center_selection")]
+    pub center_selection: CenterSelection,
+    /// See `CoverTreeBuilder::summarize_subtrees`
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    pub summarize_subtrees: bool,
+}
+
+fn default_verbosity() -> u32 {
+    2
+}
+
+fn default_center_selection() -> CenterSelection {
+    CenterSelection::Random
+}
+
+impl CoverTreeConfig {
+    /// Reads the config out of a config file's fields. Missing fields are reported as
+    /// `ParsingError::MissingYamlError`, and mistyped or unknown ones as `ParsingError::MalformedYamlError`.
+    pub fn from_fields(fields: &ConfigFields) -> MalwareBrotResult<CoverTreeConfig> {
+        // The point cloud's fields are read on their own first. Once they're flattened into the tree's, serde can't
+        // say which of them was mistyped.
+        let point_cloud = PointCloudConfig::from_part_of(fields)?;
+        let config: CoverTreeConfig = fields.deserialize()?;
+        if config.scale_base > 1.0 {
+            Ok(CoverTreeConfig {
+                point_cloud,
+                ..config
+            })
+        } else {
+            Err(fields.malformed("scale_base").into())
+        }
+    }
+
+    /// Reads a config file, in YAML, JSON or TOML depending on the extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> MalwareBrotResult<CoverTreeConfig> {
+        CoverTreeConfig::from_fields(&ConfigFields::from_path(path)?)
+    }
+
+    /// Reads the text of a config file.
+    pub fn from_str(text: &str, format: ConfigFormat) -> MalwareBrotResult<CoverTreeConfig> {
+        CoverTreeConfig::from_fields(&ConfigFields::from_str(text, format, "config")?)
+    }
+
+    /// A builder with these parameters.
+    pub fn builder(&self) -> CoverTreeBuilder {
+        let mut builder = CoverTreeBuilder::new();
+        builder
+            .set_scale_base(self.scale_base)
+            .set_cutoff(self.cutoff)
+            .set_resolution(self.resolution)
+            .set_use_singletons(self.use_singletons)
+            .set_verbosity(self.verbosity)
+            .set_center_selection(self.center_selection)
+            .set_summarize_subtrees(self.summarize_subtrees);
+        if let Some(seed) = self.seed {
+            builder.set_seed(seed);
         }
+        builder
+    }
+
+    /// Loads the point cloud and builds the tree.
+    pub fn build<M: Metric>(&self) -> MalwareBrotResult<CoverTreeWriter<M>> {
+        let point_cloud = PointCloud::<M>::from_config(&self.point_cloud)?;
+        self.builder().build(point_cloud)
     }
+}
 
-    let (scale_base, cutoff, resolution, use_singletons) = read_ct_params_yaml(&params);
+/// Given a config file on disk, it builds a covertree. See `CoverTreeConfig` for the fields, the file can be YAML, JSON
+/// or TOML depending on its extension.
+pub fn cover_tree_from_yaml<P: AsRef<Path>>(path: P) -> MalwareBrotResult<CoverTreeWriter<L2>> {
+    let config = CoverTreeConfig::from_path(path)?;
     println!(
-        "Loaded dataset, building a cover tree with scale base {}, cutoff {}, min resolution {}, and use_singletons {}",
-        scale_base, cutoff, resolution, use_singletons
+        "Loading dataset, building a cover tree with scale base {}, cutoff {}, min resolution {}, and use_singletons {}",
+        config.scale_base, config.cutoff, config.resolution, config.use_singletons
     );
-    let mut builder = CoverTreeBuilder::new();
-    let verbosity = params["verbosity"].as_i64().unwrap_or(2) as u32;
-    builder
-        .set_scale_base(scale_base)
-        .set_cutoff(cutoff)
-        .set_resolution(resolution)
-        .set_use_singletons(use_singletons)
-        .set_verbosity(verbosity);
-    Ok(builder.build(point_cloud)?)
+    config.build()
+}
+
+/// Helper function for the above, reads the scale base, cutoff, resolution and use_singletons.
+pub fn read_ct_params_yaml(params: &Yaml) -> MalwareBrotResult<(f32, usize, i32, bool)> {
+    let fields = ConfigFields::from_yaml(params, "yaml")?;
+    Ok((
+        read_scale_base(&fields)?,
+        fields.get("cutoff")?,
+        fields.get("resolution")?,
+        read_use_singletons(&fields)?,
+    ))
+}
+
+fn read_scale_base(fields: &ConfigFields) -> MalwareBrotResult<f32> {
+    let scale_base: f32 = fields.get("scale_base")?;
+    if scale_base > 1.0 {
+        Ok(scale_base)
+    } else {
+        Err(fields.malformed("scale_base").into())
+    }
 }
 
-/// Helper function for the above
-pub fn read_ct_params_yaml(params: &Yaml) -> (f32, usize, i32, bool) {
-    (
-        params["scale_base"]
-            .as_f64()
-            .expect("Unable to read the 'scale_base' during yaml load") as f32,
-        params["cutoff"]
-            .as_i64()
-            .expect("Unable to read the 'cutoff'") as usize,
-        params["resolution"]
-            .as_i64()
-            .expect("Unable to read the 'resolution'") as i32,
-        params["use_singletons"]
-            .as_bool()
-            .expect("Unable to read the 'use_singletons'"),
-    )
+fn read_use_singletons(fields: &ConfigFields) -> MalwareBrotResult<bool> {
+    if fields.contains("use_singletons") {
+        Ok(fields.get_flag("use_singletons", true)?)
+    } else {
+        Err(PointCloudError::ParsingError(ParsingError::MissingYamlError {
+            file_name: fields.file_name().to_string(),
+            field: "use_singletons".to_string(),
+        })
+        .into())
+    }
 }
 
 /// Helper function that handles the file I/O and protobuf decoding for you.
@@ -193,3 +260,103 @@ pub fn save_tree<P: AsRef<Path>, M: Metric>(
     cos.flush().map_err(|e| MalwareBrotError::from(e))?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_formats_match_and_report_errors() {
+        let yaml = "
+scale_base: 1.3
+cutoff: 5
+resolution: -10
+use_singletons: True
+verbosity: 0
+center_selection: farthest_first
+data_path: data/mnist.dat
+labels_path: data/mnist.csv
+data_dim: 784
+schema:
+    y: u32
+    id: name
+";
+        let json = r#"{
+            "scale_base": 1.3, "cutoff": 5, "resolution": -10, "use_singletons": true, "verbosity": 0,
+            "center_selection": "farthest_first", "data_path": "data/mnist.dat", "labels_path": "data/mnist.csv",
+            "data_dim": 784, "schema": {"y": "u32", "id": "name"}
+        }"#;
+        let toml = r#"
+scale_base = 1.3
+cutoff = 5
+resolution = -10
+use_singletons = true
+verbosity = 0
+center_selection = "farthest_first"
+data_path = "data/mnist.dat"
+labels_path = "data/mnist.csv"
+data_dim = 784
+[schema]
+y = "u32"
+id = "name"
+"#;
+        let config = CoverTreeConfig::from_str(yaml, ConfigFormat::Yaml).unwrap();
+        assert!(config == CoverTreeConfig::from_str(json, ConfigFormat::Json).unwrap());
+        assert!(config == CoverTreeConfig::from_str(toml, ConfigFormat::Toml).unwrap());
+        assert!(config.point_cloud.in_ram);
+        assert!(config.point_cloud.metric == MetricName::L2);
+        assert!(config.center_selection == CenterSelection::FarthestFirst);
+        assert!(config.seed.is_none());
+
+        let missing = yaml.replace("cutoff: 5\n", "");
+        match CoverTreeConfig::from_str(&missing, ConfigFormat::Yaml) {
+            Err(MalwareBrotError::PointCloudError(PointCloudError::ParsingError(
+                ParsingError::MissingYamlError { field, .. },
+            ))) => assert!(field == "cutoff"),
+            _ => panic!("The cutoff should be missing"),
+        }
+        let malformed = yaml.replace("data_dim: 784", "data_dim: seven");
+        match CoverTreeConfig::from_str(&malformed, ConfigFormat::Yaml) {
+            Err(MalwareBrotError::PointCloudError(PointCloudError::ParsingError(
+                ParsingError::MalformedYamlError { field, .. },
+            ))) => assert!(field == "data_dim"),
+            _ => panic!("The data_dim should be malformed"),
+        }
+        let bad_type = yaml.replace("y: u32", "y: complex");
+        match CoverTreeConfig::from_str(&bad_type, ConfigFormat::Yaml) {
+            Err(MalwareBrotError::PointCloudError(PointCloudError::ParsingError(
+                ParsingError::MalformedYamlError { field, .. },
+            ))) => assert!(field == "schema.y"),
+            _ => panic!("The schema should be malformed"),
+        }
+        let mistyped = yaml.replace("resolution: -10", "resolution: low");
+        match CoverTreeConfig::from_str(&mistyped, ConfigFormat::Yaml) {
+            Err(MalwareBrotError::PointCloudError(PointCloudError::ParsingError(
+                ParsingError::MalformedYamlError { field, .. },
+            ))) => assert!(field == "resolution"),
+            _ => panic!("The resolution should be malformed"),
+        }
+        let unknown = yaml.replace("cutoff: 5", "cutoff: 5\nmin_points: 5");
+        match CoverTreeConfig::from_str(&unknown, ConfigFormat::Yaml) {
+            Err(MalwareBrotError::PointCloudError(PointCloudError::ParsingError(
+                ParsingError::MalformedYamlError { field, .. },
+            ))) => assert!(field == "min_points"),
+            _ => panic!("The unknown field should be malformed"),
+        }
+        let fields = ConfigFields::from_str(yaml, ConfigFormat::Yaml, "tree.yml").unwrap();
+        let config = CoverTreeConfig::from_fields(&fields).unwrap();
+        match PointCloud::<L1>::from_config(&config.point_cloud) {
+            Err(PointCloudError::ParsingError(ParsingError::MalformedYamlError { file_name, field })) => {
+                assert!(file_name == "tree.yml");
+                assert!(field == "metric");
+            }
+            _ => panic!("The metric should be malformed"),
+        }
+        match CoverTreeConfig::from_str("scale_base: [1.3", ConfigFormat::Yaml) {
+            Err(MalwareBrotError::PointCloudError(PointCloudError::ParsingError(
+                ParsingError::MalformedYamlError { .. },
+            ))) => {}
+            _ => panic!("The file should be malformed"),
+        }
+    }
+}
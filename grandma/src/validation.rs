@@ -0,0 +1,482 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Tree Validation
+//! Brute force checks of the cover tree invariants, see `CoverTreeReader::validate`.
+//!
+//! The checks are:
+//! * Nesting: every routing node has its nested child, and its children are at lower scales. Every node has the address
+//!   it's stored under.
+//! * Covering: every point below a node is within the node's radius, and the radius is within the node's scale.
+//! * Separation: the centers of the children of a node on the same scale `b^i` are more than `b^i` apart.
+//! * Counts: a routing node's `cover_count` is the number of points below it, and a leaf's is its number of singletons.
+//! * Reachability: every node in the layers is reached from the root, and every point is in exactly one place, as a
+//!   singleton or as the center of a leaf. With `CoverTreeReader::validate_points`, every point that should be in the
+//!   tree is reached too.
+//!
+//! This computes distances from every node to every point below it, so it's slow on big trees. It's meant for tests and
+//! for checking trees after they're loaded, not for every query.
+
+use crate::errors::MalwareBrotError;
+use crate::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Floating point slack for the distance checks. The radii were computed with the same metric, but maybe not in the same
+/// order of operations.
+const TOLERANCE: f32 = 1.0e-5;
+
+/// A broken invariant.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// A node references a child that isn't in its layer
+    DanglingReference {
+        /// The node with the reference
+        parent: NodeAddress,
+        /// The missing child
+        child: NodeAddress,
+    },
+    /// A child isn't at a lower scale than its parent
+    BadNesting {
+        /// The parent
+        parent: NodeAddress,
+        /// The child
+        child: NodeAddress,
+    },
+    /// A point below the node is outside of its radius
+    OutsideRadius {
+        /// The node
+        address: NodeAddress,
+        /// The point that's too far away
+        point_index: PointIndex,
+        /// The distance from the node's center to the point
+        distance: f32,
+        /// The node's radius
+        radius: f32,
+    },
+    /// The node's radius is larger than its scale
+    RadiusAboveScale {
+        /// The node
+        address: NodeAddress,
+        /// The node's radius
+        radius: f32,
+        /// The scale of the node's layer
+        scale: f32,
+    },
+    /// Two children of a node on the same scale are too close together
+    SiblingsTooClose {
+        /// The parent
+        parent: NodeAddress,
+        /// The first child
+        first: NodeAddress,
+        /// The second child
+        second: NodeAddress,
+        /// The distance between their centers
+        distance: f32,
+        /// The scale of the children's layer, the centers should be further apart than this
+        scale: f32,
+    },
+    /// The node's `cover_count` isn't the number of points below it
+    WrongCoverCount {
+        /// The node
+        address: NodeAddress,
+        /// The count it has
+        cover_count: usize,
+        /// The number of points that are actually below it
+        actual: usize,
+    },
+    /// A point is in the tree more than once
+    DuplicatePoint {
+        /// The point
+        point_index: PointIndex,
+        /// The node it was found in first
+        first: NodeAddress,
+        /// The node it was found in again
+        second: NodeAddress,
+    },
+    /// A node is in its layer, but can't be reached from the root
+    UnreachableNode(NodeAddress),
+    /// A point that should be in the tree isn't reached from the root
+    MissingPoint(PointIndex),
+    /// A node's own address isn't the one it's stored and referenced under
+    MisplacedNode {
+        /// Where the node is
+        address: NodeAddress,
+        /// The address the node has
+        found: NodeAddress,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::DanglingReference { parent, child } => {
+                write!(f, "{:?} references {:?}, which is not in the tree", parent, child)
+            }
+            Violation::BadNesting { parent, child } => {
+                write!(f, "{:?} has the child {:?}, which is not below it", parent, child)
+            }
+            Violation::OutsideRadius {
+                address,
+                point_index,
+                distance,
+                radius,
+            } => write!(
+                f,
+                "{:?} covers {} at distance {}, outside of its radius {}",
+                address, point_index, distance, radius
+            ),
+            Violation::RadiusAboveScale {
+                address,
+                radius,
+                scale,
+            } => write!(f, "{:?} has radius {}, above its scale {}", address, radius, scale),
+            Violation::SiblingsTooClose {
+                parent,
+                first,
+                second,
+                distance,
+                scale,
+            } => write!(
+                f,
+                "the children {:?} and {:?} of {:?} are {} apart, within their scale {}",
+                first, second, parent, distance, scale
+            ),
+            Violation::WrongCoverCount {
+                address,
+                cover_count,
+                actual,
+            } => write!(
+                f,
+                "{:?} has a cover count of {}, but covers {} points",
+                address, cover_count, actual
+            ),
+            Violation::DuplicatePoint {
+                point_index,
+                first,
+                second,
+            } => write!(
+                f,
+                "{} is in both {:?} and {:?}",
+                point_index, first, second
+            ),
+            Violation::UnreachableNode(address) => {
+                write!(f, "{:?} can't be reached from the root", address)
+            }
+            Violation::MissingPoint(point_index) => {
+                write!(f, "{} should be in the tree, but can't be reached from the root", point_index)
+            }
+            Violation::MisplacedNode { address, found } => {
+                write!(f, "{:?} is stored at {:?}", found, address)
+            }
+        }
+    }
+}
+
+/// The result of `CoverTreeReader::validate`.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    /// The number of nodes reached from the root
+    pub nodes_checked: usize,
+    /// The number of distinct points reached from the root
+    pub points_reached: usize,
+    /// Everything that's wrong with the tree
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationReport {
+    /// True if no invariant was broken.
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "checked {} nodes and {} points, found {} violations",
+            self.nodes_checked,
+            self.points_reached,
+            self.violations.len()
+        )?;
+        for violation in &self.violations {
+            writeln!(f, "\t{}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+struct Validator<'a, M: Metric> {
+    reader: &'a CoverTreeReader<M>,
+    report: ValidationReport,
+    visited: HashSet<NodeAddress>,
+    homes: HashMap<PointIndex, NodeAddress>,
+}
+
+impl<'a, M: Metric> Validator<'a, M> {
+    fn place(&mut self, point_index: PointIndex, address: NodeAddress) {
+        if let Some(first) = self.homes.insert(point_index, address) {
+            self.report.violations.push(Violation::DuplicatePoint {
+                point_index,
+                first,
+                second: address,
+            });
+        }
+    }
+
+    /// Checks the node and everything below it, returns the points below it.
+    fn check_node(&mut self, address: NodeAddress) -> MalwareBrotResult<Vec<PointIndex>> {
+        self.visited.insert(address);
+        self.report.nodes_checked += 1;
+        let (found, radius, cover_count, singletons, children) = self
+            .reader
+            .get_node_and(address, |n| {
+                let children = n.children().map(|(nested_scale, addresses)| {
+                    let mut children = vec![(nested_scale, address.1)];
+                    children.extend_from_slice(addresses);
+                    children
+                });
+                (
+                    (*n.scale_index(), *n.center_index()),
+                    n.radius(),
+                    n.cover_count(),
+                    Vec::from(n.singletons()),
+                    children,
+                )
+            })
+            .ok_or(MalwareBrotError::NodeNotInTree(address))?;
+        if found != address {
+            self.report
+                .violations
+                .push(Violation::MisplacedNode { address, found });
+        }
+
+        for pi in &singletons {
+            self.place(*pi, address);
+        }
+        let mut points = singletons;
+        match children {
+            Some(children) => {
+                self.check_separation(address, &children)?;
+                for child in children {
+                    if child.0 >= address.0 {
+                        self.report.violations.push(Violation::BadNesting {
+                            parent: address,
+                            child,
+                        });
+                        continue;
+                    }
+                    if self.reader.get_node_and(child, |_n| ()).is_none() {
+                        self.report.violations.push(Violation::DanglingReference {
+                            parent: address,
+                            child,
+                        });
+                        continue;
+                    }
+                    points.extend(self.check_node(child)?);
+                }
+                if cover_count != points.len() {
+                    self.report.violations.push(Violation::WrongCoverCount {
+                        address,
+                        cover_count,
+                        actual: points.len(),
+                    });
+                }
+            }
+            None => {
+                // A leaf's center isn't in its count.
+                if cover_count != points.len() {
+                    self.report.violations.push(Violation::WrongCoverCount {
+                        address,
+                        cover_count,
+                        actual: points.len(),
+                    });
+                }
+                self.place(address.1, address);
+                points.push(address.1);
+            }
+        }
+
+        // A leaf with no singletons has no radius, the builder leaves it at -inf.
+        let radius = radius.max(0.0);
+        let scale = self.reader.scale(address.0);
+        if radius > scale * (1.0 + TOLERANCE) {
+            self.report.violations.push(Violation::RadiusAboveScale {
+                address,
+                radius,
+                scale,
+            });
+        }
+        let distances = self
+            .reader
+            .point_cloud()
+            .distances_to_point_index(address.1, &points)?;
+        for (pi, d) in points.iter().zip(distances) {
+            if d > radius * (1.0 + TOLERANCE) + TOLERANCE {
+                self.report.violations.push(Violation::OutsideRadius {
+                    address,
+                    point_index: *pi,
+                    distance: d,
+                    radius,
+                });
+            }
+        }
+        Ok(points)
+    }
+
+    fn check_separation(
+        &mut self,
+        parent: NodeAddress,
+        children: &[NodeAddress],
+    ) -> MalwareBrotResult<()> {
+        let mut by_scale: HashMap<i32, Vec<PointIndex>> = HashMap::new();
+        for (si, pi) in children {
+            by_scale.entry(*si).or_default().push(*pi);
+        }
+        for (si, centers) in by_scale {
+            // The bottom layer holds everything below the resolution, it isn't separated.
+            if si < self.reader.parameters().resolution {
+                continue;
+            }
+            let scale = self.reader.scale(si);
+            for (i, first) in centers.iter().enumerate() {
+                let distances = self
+                    .reader
+                    .point_cloud()
+                    .distances_to_point_index(*first, &centers[i + 1..])?;
+                for (second, d) in centers[i + 1..].iter().zip(distances) {
+                    if d <= scale * (1.0 - TOLERANCE) {
+                        self.report.violations.push(Violation::SiblingsTooClose {
+                            parent,
+                            first: (si, *first),
+                            second: (si, *second),
+                            distance: d,
+                            scale,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn validate<M: Metric>(
+    reader: &CoverTreeReader<M>,
+    point_indexes: Option<&[PointIndex]>,
+) -> MalwareBrotResult<ValidationReport> {
+    reader.consistent(|root_address| {
+        let mut validator = Validator {
+            reader,
+            report: ValidationReport::default(),
+            visited: HashSet::new(),
+            homes: HashMap::new(),
+        };
+        validator.check_node(root_address)?;
+
+        for (_si, layer) in reader.layers() {
+            let mut unreachable = Vec::new();
+            // The bottom layer holds nodes of many scales, so use the node's own scale index.
+            layer.for_each_node(|pi, n| {
+                if !validator.visited.contains(&(*n.scale_index(), *pi)) {
+                    unreachable.push(Violation::UnreachableNode((*n.scale_index(), *pi)));
+                }
+            });
+            validator.report.violations.extend(unreachable);
+        }
+        for pi in point_indexes.unwrap_or(&[]) {
+            if !validator.homes.contains_key(pi) {
+                validator.report.violations.push(Violation::MissingPoint(*pi));
+            }
+        }
+        validator.report.points_reached = validator.homes.len();
+        Ok(validator.report)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::CoverNode;
+    use crate::tree::tests::random_tree;
+
+    #[test]
+    fn validate_finds_violations() {
+        let count = 300;
+        let mut tree = random_tree(count, 0);
+        let reader = tree.reader();
+        let report = reader.validate().unwrap();
+        assert!(report.is_valid());
+        assert!(report.nodes_checked == reader.node_count());
+        assert!(report.points_reached == count);
+        let missing = count as PointIndex;
+        let report = reader.validate_points(&[0, missing]).unwrap();
+        assert!(report.violations == vec![Violation::MissingPoint(missing)]);
+
+        // Put a point that's already in a leaf into the root's singletons, miscount the root, leave a node hanging off
+        // of nothing, and give another leaf the wrong scale index.
+        let root_address = reader.root_address();
+        let mut leaves = Vec::new();
+        for (si, layer) in reader.layers() {
+            layer.for_each_node(|pi, n| {
+                if n.is_leaf() && *pi != root_address.1 && si < root_address.0 {
+                    leaves.push((*n.scale_index(), *pi));
+                }
+            });
+        }
+        let leaf = leaves[0].1;
+        let moved = leaves[1];
+        let mut root = reader.get_node_and(root_address, |n| n.clone()).unwrap();
+        let mut misplaced = CoverNode::new((moved.0 - 1, moved.1));
+        reader
+            .get_node_and(moved, |n| {
+                misplaced.set_radius(n.radius());
+                misplaced.insert_singletons(Vec::from(n.singletons()));
+            })
+            .unwrap();
+        root.insert_singleton(leaf);
+        root.set_cover_count(count);
+        unsafe {
+            tree.insert_raw(root_address.0, root_address.1, root);
+            tree.insert_raw(root_address.0, leaf, CoverNode::new((root_address.0, leaf)));
+            tree.insert_raw(moved.0, moved.1, misplaced);
+        }
+        tree.refresh();
+
+        let report = tree.reader().validate().unwrap();
+        assert!(!report.is_valid());
+        assert!(report.violations.iter().any(|v| match v {
+            Violation::DuplicatePoint { point_index, .. } => *point_index == leaf,
+            _ => false,
+        }));
+        assert!(report.violations.iter().any(|v| match v {
+            Violation::WrongCoverCount { address, cover_count, actual } =>
+                *address == root_address && *cover_count == count && *actual == count + 1,
+            _ => false,
+        }));
+        assert!(report
+            .violations
+            .contains(&Violation::UnreachableNode((root_address.0, leaf))));
+        assert!(report.violations.contains(&Violation::MisplacedNode {
+            address: moved,
+            found: (moved.0 - 1, moved.1),
+        }));
+    }
+}
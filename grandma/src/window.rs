@@ -0,0 +1,354 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! # Sliding Window Tree
+//! A cover tree that only holds the points inserted within a time horizon, for streaming telemetry.
+//!
+//! This wraps a `CoverTreeWriter` and records when each point went in. Calling `evict` removes every point that's older
+//! than the horizon with a single `remove_batch`, so the nodes they touched are compacted together and readers see the
+//! eviction all at once. The evicted points stay in the point cloud, the tree just stops finding them.
+//!
+//! A cover tree can't be empty, so when every point in the window expires the writer is dropped, and the next insert
+//! builds a new tree over the same point cloud with the same parameters.
+
+use crate::errors::MalwareBrotError;
+use crate::*;
+use pointcloud::labels::values::Metadata;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// A cover tree writer that forgets points once they're older than the horizon.
+pub struct WindowedCoverTree<M: Metric> {
+    /// `None` while the window is empty.
+    writer: Option<CoverTreeWriter<M>>,
+    /// The parameters of the first tree, to build a new one with after the window empties.
+    parameters: Arc<CoverTreeParameters<M>>,
+    horizon: Duration,
+    times: HashMap<PointIndex, SystemTime>,
+    expiry_queue: BTreeMap<SystemTime, Vec<PointIndex>>,
+}
+
+impl<M: Metric> WindowedCoverTree<M> {
+    /// Wraps a built tree. All the points already in it are taken to have been inserted at `time`.
+    pub fn new(
+        writer: CoverTreeWriter<M>,
+        horizon: Duration,
+        time: SystemTime,
+    ) -> WindowedCoverTree<M> {
+        let point_indexes = writer.reader().point_indexes();
+        let mut windowed = WindowedCoverTree {
+            parameters: Arc::clone(&writer.parameters),
+            writer: Some(writer),
+            horizon,
+            times: HashMap::new(),
+            expiry_queue: BTreeMap::new(),
+        };
+        windowed.record(&point_indexes, time);
+        windowed
+    }
+
+    /// How long points are kept for.
+    pub fn horizon(&self) -> Duration {
+        self.horizon
+    }
+
+    /// Changes the horizon. This doesn't evict anything until the next call to `evict`.
+    pub fn set_horizon(&mut self, horizon: Duration) {
+        self.horizon = horizon;
+    }
+
+    /// The number of points currently in the window.
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    /// If there are no points in the window.
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// When the point was inserted, if it's still in the window.
+    pub fn insertion_time(&self, point_index: PointIndex) -> Option<SystemTime> {
+        self.times.get(&point_index).cloned()
+    }
+
+    /// Inserts a point that's already in the point cloud, see `CoverTreeWriter::insert`. If the window is empty this builds
+    /// a new tree.
+    pub fn insert(&mut self, point_index: PointIndex, time: SystemTime) -> MalwareBrotResult<()> {
+        self.insert_batch(&[point_index], time)
+    }
+
+    /// Inserts a batch of points that are already in the point cloud, see `CoverTreeWriter::insert_batch`.
+    pub fn insert_batch(
+        &mut self,
+        point_indexes: &[PointIndex],
+        time: SystemTime,
+    ) -> MalwareBrotResult<()> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.insert_batch(point_indexes)?,
+            None => self.rebuild(point_indexes)?,
+        }
+        self.record(point_indexes, time);
+        Ok(())
+    }
+
+    /// Appends a point to the point cloud and inserts it, see `CoverTreeWriter::push`.
+    pub fn push(
+        &mut self,
+        point: &[f32],
+        metadata: Metadata,
+        time: SystemTime,
+    ) -> MalwareBrotResult<PointIndex> {
+        let point_index = match self.writer.as_mut() {
+            Some(writer) => writer.push(point, metadata)?,
+            None => {
+                let point_index = self.parameters.point_cloud.push(point, metadata)?;
+                self.rebuild(&[point_index])?;
+                point_index
+            }
+        };
+        self.record(&[point_index], time);
+        Ok(point_index)
+    }
+
+    /// Appends a batch of points to the point cloud and inserts them, see `CoverTreeWriter::push_batch`.
+    pub fn push_batch(
+        &mut self,
+        points: &[f32],
+        metadata: Vec<Metadata>,
+        names: Option<Vec<PointName>>,
+        time: SystemTime,
+    ) -> MalwareBrotResult<Vec<PointIndex>> {
+        let point_indexes = match self.writer.as_mut() {
+            Some(writer) => writer.push_batch(points, metadata, names)?,
+            None => {
+                let point_indexes = self.parameters.point_cloud.push_batch(points, metadata, names)?;
+                self.rebuild(&point_indexes)?;
+                point_indexes
+            }
+        };
+        self.record(&point_indexes, time);
+        Ok(point_indexes)
+    }
+
+    /// The points inserted before `now - horizon`, oldest first.
+    fn expired(&self, now: SystemTime) -> Vec<PointIndex> {
+        match now.checked_sub(self.horizon) {
+            Some(cutoff) => self
+                .expiry_queue
+                .range(..cutoff)
+                .flat_map(|(_time, point_indexes)| point_indexes.iter().cloned())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Removes every point inserted before `now - horizon` and returns them, oldest first. If that's every point, the
+    /// window is left empty.
+    pub fn evict(&mut self, now: SystemTime) -> MalwareBrotResult<Vec<PointIndex>> {
+        let expired = self.expired(now);
+        if expired.is_empty() {
+            return Ok(expired);
+        }
+        if expired.len() == self.times.len() {
+            self.clear();
+            return Ok(expired);
+        }
+        if let Some(writer) = self.writer.as_mut() {
+            writer.remove_batch(&expired)?;
+        }
+
+        for pi in &expired {
+            if let Some(time) = self.times.remove(pi) {
+                self.expiry_queue.remove(&time);
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Removes a single point before it expires.
+    pub fn remove(&mut self, point_index: PointIndex) -> MalwareBrotResult<()> {
+        let time = self
+            .times
+            .get(&point_index)
+            .cloned()
+            .ok_or(MalwareBrotError::PointNotInTree(point_index))?;
+        if self.times.len() == 1 {
+            self.clear();
+            return Ok(());
+        }
+        if let Some(writer) = self.writer.as_mut() {
+            writer.remove(point_index)?;
+        }
+        self.times.remove(&point_index);
+        if let Some(point_indexes) = self.expiry_queue.get_mut(&time) {
+            point_indexes.retain(|pi| *pi != point_index);
+            if point_indexes.is_empty() {
+                self.expiry_queue.remove(&time);
+            }
+        }
+        Ok(())
+    }
+
+    /// A reader for the tree, see `CoverTreeWriter::reader`. This is `None` while the window is empty. Readers made before
+    /// the window emptied keep the last tree it had, as that writer is gone, so make a new one after inserting again.
+    pub fn reader(&self) -> Option<CoverTreeReader<M>> {
+        self.writer.as_ref().map(|w| w.reader())
+    }
+
+    /// The underlying writer, `None` while the window is empty.
+    pub fn writer(&self) -> Option<&CoverTreeWriter<M>> {
+        self.writer.as_ref()
+    }
+
+    /// Drops the insertion times and hands back the writer, `None` if the window is empty.
+    pub fn into_writer(self) -> Option<CoverTreeWriter<M>> {
+        self.writer
+    }
+
+    /// Builds a new tree over these points, for when the window is empty.
+    fn rebuild(&mut self, point_indexes: &[PointIndex]) -> MalwareBrotResult<()> {
+        let builder = CoverTreeBuilder {
+            scale_base: self.parameters.scale_base,
+            cutoff: self.parameters.cutoff,
+            resolution: self.parameters.resolution,
+            use_singletons: self.parameters.use_singletons,
+            cluster_min: self.parameters.cluster_min,
+            verbosity: self.parameters.verbosity,
+            seed: self.parameters.seed,
+            center_selection: self.parameters.center_selection,
+            summarize_subtrees: self.parameters.summarize_subtrees,
+        };
+        let writer = builder.build_subset(Arc::clone(&self.parameters.point_cloud), point_indexes)?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    /// Drops the tree and all the insertion times.
+    fn clear(&mut self) {
+        self.writer = None;
+        self.times.clear();
+        self.expiry_queue.clear();
+    }
+
+    fn record(&mut self, point_indexes: &[PointIndex], time: SystemTime) {
+        for pi in point_indexes {
+            // A point inserted again is moved to its new time.
+            if let Some(old_time) = self.times.insert(*pi, time) {
+                if let Some(old) = self.expiry_queue.get_mut(&old_time) {
+                    old.retain(|p| p != pi);
+                }
+            }
+            self.expiry_queue
+                .entry(time)
+                .or_default()
+                .push(*pi);
+        }
+        self.expiry_queue
+            .retain(|_time, point_indexes| !point_indexes.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::tests::random_tree;
+    use pointcloud::labels::values::{Value, Vector};
+
+    #[test]
+    fn evict_removes_old_points() {
+        let count = 200;
+        let start = SystemTime::now();
+        let horizon = Duration::from_secs(3600);
+        let mut tree = WindowedCoverTree::new(random_tree(count, 0), horizon, start);
+        assert!(tree.len() == count);
+
+        let new_count = 100;
+        let points: Vec<f32> = (0..2 * new_count).map(|_i| rand::random::<f32>()).collect();
+        let metadata: Vec<Metadata> = (0..new_count)
+            .map(|_i| {
+                let mut m = Metadata::new();
+                m.insert("y".to_string(), Value::Vector(Vector::Real(vec![1.0])));
+                m
+            })
+            .collect();
+        let new_indexes = tree
+            .push_batch(&points, metadata, None, start + Duration::from_secs(10))
+            .unwrap();
+
+        assert!(tree
+            .evict(start + Duration::from_secs(5))
+            .unwrap()
+            .is_empty());
+        let evicted = tree
+            .evict(start + horizon + Duration::from_secs(1))
+            .unwrap();
+        assert!(evicted.len() == count);
+        assert!(tree.len() == new_count);
+        assert!(tree.insertion_time(evicted[0]).is_none());
+
+        let reader = tree.reader().unwrap();
+        assert!(reader.no_dangling_refs());
+        assert!(reader.validate().unwrap().is_valid());
+        let root_count = reader.get_node_and(reader.root_address(), |n| n.cover_count());
+        assert!(root_count == Some(new_count));
+
+        let point_cloud = reader.point_cloud();
+        let k = 5;
+        for _i in 0..20 {
+            let query = [rand::random::<f32>(), rand::random::<f32>()];
+            let found = reader.knn(&query, k).unwrap();
+            let mut expected = point_cloud
+                .distances_to_point(&query, &new_indexes)
+                .unwrap();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert!(found.len() == k);
+            for ((d, pi), true_d) in found.iter().zip(&expected) {
+                assert!(new_indexes.contains(pi));
+                assert_approx_eq!(*d, *true_d);
+            }
+        }
+
+        // Once everything expires the window is empty, and the next push starts a new tree.
+        let later = start + horizon * 2;
+        let evicted = tree.evict(later).unwrap();
+        assert!(evicted == new_indexes);
+        assert!(tree.is_empty());
+        assert!(tree.reader().is_none());
+        assert!(tree.evict(later).unwrap().is_empty());
+
+        let mut metadata = Metadata::new();
+        metadata.insert("y".to_string(), Value::Vector(Vector::Real(vec![1.0])));
+        let point_index = tree.push(&[0.5, 0.5], metadata, later).unwrap();
+        assert!(tree.len() == 1);
+        let reader = tree.reader().unwrap();
+        assert!(reader.validate_points(&[point_index]).unwrap().is_valid());
+        assert!(reader.knn(&[0.0, 0.0], 3).unwrap() == vec![(L2::dense(&[0.0, 0.0], &[0.5, 0.5]), point_index)]);
+        tree.insert_batch(&new_indexes, later).unwrap();
+        assert!(tree.reader().unwrap().validate().unwrap().is_valid());
+
+        tree.remove(point_index).unwrap();
+        for pi in &new_indexes {
+            tree.remove(*pi).unwrap();
+        }
+        assert!(tree.is_empty());
+        assert!(tree.reader().is_none());
+    }
+}
@@ -0,0 +1,507 @@
+/*
+* Licensed to Elasticsearch B.V. under one or more contributor
+* license agreements. See the NOTICE file distributed with
+* this work for additional information regarding copyright
+* ownership. Elasticsearch B.V. licenses this file to you under
+* the Apache License, Version 2.0 (the "License"); you may
+* not use this file except in compliance with the License.
+* You may obtain a copy of the License at
+*
+*  http://www.apache.org/licenses/LICENSE-2.0
+*
+* Unless required by applicable law or agreed to in writing,
+* software distributed under the License is distributed on an
+* "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+* KIND, either express or implied.  See the License for the
+* specific language governing permissions and limitations
+* under the License.
+*/
+
+//! Typed configuration for loading a point cloud.
+//!
+//! A config file is read into `ConfigFields` first, and the typed configs are deserialized out of those. The fields are
+//! handed to serde one at a time, so a missing field is reported as a `ParsingError::MissingYamlError` and a mistyped
+//! or unknown one as a `ParsingError::MalformedYamlError`, both with the file and field name, instead of a panic. The
+//! file can be YAML, JSON or TOML, the fields are the same.
+
+use indexmap::IndexMap;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::any::TypeId;
+use std::cell::Cell;
+use std::fs::read_to_string;
+use std::path::Path;
+use yaml_rust::Yaml;
+
+use crate::errors::*;
+use crate::labels::LabelScheme;
+use crate::*;
+
+/// The formats a config file can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    /// YAML, the default
+    Yaml,
+    /// JSON
+    Json,
+    /// TOML
+    Toml,
+}
+
+impl ConfigFormat {
+    /// Picks the format from the file's extension, `.json` and `.toml` files are read as such and anything else is YAML.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> ConfigFormat {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase())
+            .as_deref()
+        {
+            Some("json") => ConfigFormat::Json,
+            Some("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+}
+
+/// The top level fields of a config file, with the file's name for the errors.
+#[derive(Debug, Clone)]
+pub struct ConfigFields {
+    file_name: String,
+    fields: Map<String, Value>,
+}
+
+impl ConfigFields {
+    /// Parses the text of a config file.
+    pub fn from_str(text: &str, format: ConfigFormat, file_name: &str) -> PointCloudResult<ConfigFields> {
+        let value: Result<Value, String> = match format {
+            ConfigFormat::Yaml => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::from_str(text).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::from_str(text).map_err(|e| e.to_string()),
+        };
+        match value {
+            Ok(value) => ConfigFields::from_value(value, file_name),
+            Err(message) => Err(malformed(file_name, &message)),
+        }
+    }
+
+    /// Reads and parses a config file, the format is picked from the extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> PointCloudResult<ConfigFields> {
+        let text = read_to_string(&path)?;
+        let file_name = path.as_ref().to_string_lossy();
+        ConfigFields::from_str(&text, ConfigFormat::from_path(&path), &file_name)
+    }
+
+    /// Takes the fields out of an already loaded YAML document.
+    pub fn from_yaml(yaml: &Yaml, file_name: &str) -> PointCloudResult<ConfigFields> {
+        ConfigFields::from_value(yaml_to_json(yaml), file_name)
+    }
+
+    fn from_value(value: Value, file_name: &str) -> PointCloudResult<ConfigFields> {
+        match value {
+            Value::Object(fields) => Ok(ConfigFields {
+                file_name: file_name.to_string(),
+                fields,
+            }),
+            _ => Err(malformed(file_name, "the file should be a map of fields")),
+        }
+    }
+
+    /// The name of the file the fields came from.
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    /// If the field is set.
+    pub fn contains(&self, field: &str) -> bool {
+        self.fields.get(field).map(|v| !v.is_null()).unwrap_or(false)
+    }
+
+    /// Reads a field that has to be there.
+    pub fn get<T: DeserializeOwned>(&self, field: &str) -> PointCloudResult<T> {
+        match self.get_optional(field)? {
+            Some(value) => Ok(value),
+            None => Err(PointCloudError::ParsingError(ParsingError::MissingYamlError {
+                file_name: self.file_name.clone(),
+                field: field.to_string(),
+            })),
+        }
+    }
+
+    /// Reads a field that can be left out.
+    pub fn get_optional<T: DeserializeOwned>(&self, field: &str) -> PointCloudResult<Option<T>> {
+        match self.fields.get(field) {
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => serde_json::from_value(value.clone())
+                .map(Some)
+                .map_err(|_e| malformed(&self.file_name, field)),
+        }
+    }
+
+    /// Reads a bool that can be left out. YAML 1.1 spells these `True` and `False` too, so those are read as well.
+    pub fn get_flag(&self, field: &str, default: bool) -> PointCloudResult<bool> {
+        match self.fields.get(field) {
+            None | Some(Value::Null) => Ok(default),
+            Some(Value::Bool(b)) => Ok(*b),
+            Some(Value::String(s)) if s.eq_ignore_ascii_case("true") => Ok(true),
+            Some(Value::String(s)) if s.eq_ignore_ascii_case("false") => Ok(false),
+            Some(_) => Err(malformed(&self.file_name, field)),
+        }
+    }
+
+    /// A malformed field error for this file, for checks beyond the field's type.
+    pub fn malformed(&self, field: &str) -> PointCloudError {
+        malformed(&self.file_name, field)
+    }
+
+    /// Deserializes a typed config out of all of the fields. A field the type doesn't have is malformed.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> PointCloudResult<T> {
+        self.deserialize_fields(false)
+    }
+
+    /// Deserializes a typed config out of the fields it has, ignoring the rest. For a config that's part of a larger
+    /// one, like the point cloud's fields of a cover tree config.
+    pub fn deserialize_part<T: DeserializeOwned>(&self) -> PointCloudResult<T> {
+        self.deserialize_fields(true)
+    }
+
+    fn deserialize_fields<T: DeserializeOwned>(&self, only_known: bool) -> PointCloudResult<T> {
+        let current = Cell::new(None);
+        let deserializer = FieldsDeserializer {
+            fields: &self.fields,
+            current: &current,
+            only_known,
+        };
+        T::deserialize(deserializer).map_err(|e| {
+            // Serde names the field in missing and unknown field errors, anything else is about the field it was on.
+            let message = e.to_string();
+            if let Some(field) = quoted_field(&message, "missing field `") {
+                PointCloudError::ParsingError(ParsingError::MissingYamlError {
+                    file_name: self.file_name.clone(),
+                    field: field.to_string(),
+                })
+            } else if let Some(field) = quoted_field(&message, "unknown field `") {
+                self.malformed(field)
+            } else {
+                self.malformed(current.get().unwrap_or(&message))
+            }
+        })
+    }
+}
+
+fn quoted_field<'a>(message: &'a str, prefix: &str) -> Option<&'a str> {
+    message
+        .strip_prefix(prefix)
+        .and_then(|rest| rest.split('`').next())
+}
+
+/// Hands the fields to serde as a map, remembering which one it's on.
+struct FieldsDeserializer<'a> {
+    fields: &'a Map<String, Value>,
+    current: &'a Cell<Option<&'a str>>,
+    only_known: bool,
+}
+
+impl<'de> Deserializer<'de> for FieldsDeserializer<'de> {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(FieldsAccess {
+            fields: self.fields.iter(),
+            known: None,
+            value: None,
+            current: self.current,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(FieldsAccess {
+            fields: self.fields.iter(),
+            known: if self.only_known { Some(fields) } else { None },
+            value: None,
+            current: self.current,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit unit_struct
+        newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+struct FieldsAccess<'a> {
+    fields: serde_json::map::Iter<'a>,
+    known: Option<&'static [&'static str]>,
+    value: Option<&'a Value>,
+    current: &'a Cell<Option<&'a str>>,
+}
+
+impl<'a> MapAccess<'a> for FieldsAccess<'a> {
+    type Error = serde_json::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'a>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        for (key, value) in &mut self.fields {
+            if self.known.map(|known| known.contains(&key.as_str())).unwrap_or(true) {
+                self.current.set(Some(key));
+                self.value = Some(value);
+                let key: de::value::StrDeserializer<Self::Error> = key.as_str().into_deserializer();
+                return seed.deserialize(key).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'a>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value),
+            None => Err(de::Error::custom("a value was read before its field")),
+        }
+    }
+}
+
+/// Deserializes a bool. YAML 1.1 spells these `True` and `False` too, so those are read as well.
+pub fn deserialize_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Bool(b) => Ok(b),
+        Value::String(ref s) if s.eq_ignore_ascii_case("true") => Ok(true),
+        Value::String(ref s) if s.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(de::Error::custom("expected true or false")),
+    }
+}
+
+fn malformed(file_name: &str, field: &str) -> PointCloudError {
+    PointCloudError::ParsingError(ParsingError::MalformedYamlError {
+        file_name: file_name.to_string(),
+        field: field.to_string(),
+    })
+}
+
+fn yaml_to_json(yaml: &Yaml) -> Value {
+    match yaml {
+        Yaml::Real(s) => s
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number)
+            .unwrap_or_else(|| Value::String(s.clone())),
+        Yaml::Integer(i) => Value::from(*i),
+        Yaml::String(s) => Value::String(s.clone()),
+        Yaml::Boolean(b) => Value::Bool(*b),
+        Yaml::Array(a) => Value::Array(a.iter().map(yaml_to_json).collect()),
+        Yaml::Hash(h) => Value::Object(
+            h.iter()
+                .filter_map(|(k, v)| {
+                    let key = match k {
+                        Yaml::String(s) => s.clone(),
+                        Yaml::Integer(i) => i.to_string(),
+                        Yaml::Real(s) => s.clone(),
+                        Yaml::Boolean(b) => b.to_string(),
+                        _ => return None,
+                    };
+                    Some((key, yaml_to_json(v)))
+                })
+                .collect(),
+        ),
+        Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => Value::Null,
+    }
+}
+
+/// The metrics that can be named in a config. The point cloud's type has to match the one in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum MetricName {
+    /// `L1`
+    L1,
+    /// `L2`, the default
+    L2,
+    /// `Linfty`
+    Linfty,
+    /// `CosineSim`
+    CosineSim,
+}
+
+impl MetricName {
+    /// If `M` is the metric with this name.
+    pub fn matches<M: Metric>(&self) -> bool {
+        let type_id = TypeId::of::<M>();
+        match self {
+            MetricName::L1 => type_id == TypeId::of::<L1>(),
+            MetricName::L2 => type_id == TypeId::of::<L2>(),
+            MetricName::Linfty => type_id == TypeId::of::<Linfty>(),
+            MetricName::CosineSim => type_id == TypeId::of::<CosineSim>(),
+        }
+    }
+}
+
+/// Everything needed to load a point cloud. Minimal example below, in YAML.
+/// ```yaml
+/// ---
+/// data_path: DATAMEMMAP
+/// labels_path: LABELS_CSV_OR_MEMMAP
+/// count: NUMBER_OF_DATA_POINTS
+/// data_dim: 784
+/// labels_dim: 10
+/// in_ram: true
+/// metric: L2
+/// ```
+/// The paths are globs, the matching data and label files are paired up in order. Instead of `labels_dim`, a schema for
+/// a labels CSV can be given
+/// ```yaml
+/// schema:
+///    natural: u32
+///    integer: i32
+///    real: f32
+///    string: string
+///    boolean: bool
+///    id: name
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PointCloudConfig {
+    /// The file the config was read from, for the errors. Not a field of the file.
+    #[serde(skip)]
+    pub file_name: String,
+    /// Glob of the data memmaps
+    pub data_path: String,
+    /// Glob of the label memmaps or CSVs
+    pub labels_path: String,
+    /// The dimension of the data
+    pub data_dim: usize,
+    /// The dimension of the label vector, when there's no schema
+    pub labels_dim: Option<usize>,
+    /// The type of each label column: `u32`, `i32`, `f32`, `bool`, `string`, or `name` for the column with the names
+    pub schema: Option<IndexMap<String, String>>,
+    /// Read the data into ram instead of leaving it in the memmaps, defaults to true
+    #[serde(default = "default_in_ram", deserialize_with = "deserialize_flag")]
+    pub in_ram: bool,
+    /// The number of points the files should have, if it's checked
+    pub count: Option<usize>,
+    /// The metric, defaults to `L2`
+    #[serde(default = "default_metric")]
+    pub metric: MetricName,
+}
+
+fn default_in_ram() -> bool {
+    true
+}
+
+fn default_metric() -> MetricName {
+    MetricName::L2
+}
+
+impl PointCloudConfig {
+    /// Reads the point cloud fields out of a config file's fields.
+    pub fn from_fields(fields: &ConfigFields) -> PointCloudResult<PointCloudConfig> {
+        PointCloudConfig::checked(fields.deserialize()?, fields)
+    }
+
+    /// Reads the point cloud fields out of a config file that has other fields as well.
+    pub fn from_part_of(fields: &ConfigFields) -> PointCloudResult<PointCloudConfig> {
+        PointCloudConfig::checked(fields.deserialize_part()?, fields)
+    }
+
+    fn checked(mut config: PointCloudConfig, fields: &ConfigFields) -> PointCloudResult<PointCloudConfig> {
+        config.file_name = fields.file_name().to_string();
+        match &config.schema {
+            Some(schema) => {
+                for (key, dtype) in schema {
+                    if !SCHEMA_TYPES.contains(&dtype.to_lowercase().as_str()) {
+                        return Err(fields.malformed(&format!("schema.{}", key)));
+                    }
+                }
+            }
+            None => {
+                if config.labels_dim.is_none() {
+                    return Err(PointCloudError::ParsingError(ParsingError::MissingYamlError {
+                        file_name: fields.file_name().to_string(),
+                        field: "labels_dim".to_string(),
+                    }));
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads a config file, in YAML, JSON or TOML depending on the extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> PointCloudResult<PointCloudConfig> {
+        PointCloudConfig::from_fields(&ConfigFields::from_path(path)?)
+    }
+
+    /// Reads the text of a config file.
+    pub fn from_str(text: &str, format: ConfigFormat) -> PointCloudResult<PointCloudConfig> {
+        PointCloudConfig::from_fields(&ConfigFields::from_str(text, format, "config")?)
+    }
+
+    /// The label scheme the config describes.
+    pub fn label_scheme(&self) -> LabelScheme {
+        let mut scheme = LabelScheme::new();
+        match (&self.schema, self.labels_dim) {
+            (Some(schema), _) => {
+                for (key, dtype) in schema {
+                    match dtype.to_lowercase().as_str() {
+                        "u32" => scheme.add_u32(key.clone()),
+                        "f32" => scheme.add_f32(key.clone()),
+                        "i32" => scheme.add_i32(key.clone()),
+                        "bool" => scheme.add_bool(key.clone()),
+                        "string" => scheme.add_string(key.clone()),
+                        _ => scheme.add_name_column(key),
+                    }
+                }
+            }
+            (None, Some(labels_dim)) => scheme.add_vector("y".to_string(), labels_dim, "f32"),
+            (None, None) => {}
+        }
+        scheme
+    }
+}
+
+const SCHEMA_TYPES: [&str; 6] = ["u32", "f32", "i32", "bool", "string", "name"];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_cloud_config_reports_fields_and_file() {
+        let yaml = "
+data_path: data/mnist.dat
+labels_path: data/mnist.csv
+data_dim: 784
+labels_dim: 10
+in_ram: False
+";
+        let fields = ConfigFields::from_str(yaml, ConfigFormat::Yaml, "cloud.yml").unwrap();
+        let config = PointCloudConfig::from_fields(&fields).unwrap();
+        assert!(config.file_name == "cloud.yml");
+        assert!(!config.in_ram);
+        assert!(config.metric == MetricName::L2);
+
+        let unknown = yaml.replace("labels_dim", "label_dim");
+        match PointCloudConfig::from_str(&unknown, ConfigFormat::Yaml) {
+            Err(PointCloudError::ParsingError(ParsingError::MalformedYamlError { field, .. })) => {
+                assert!(field == "label_dim")
+            }
+            _ => panic!("The misspelled labels_dim should be malformed"),
+        }
+        let flag = yaml.replace("in_ram: False", "in_ram: sometimes");
+        match PointCloudConfig::from_str(&flag, ConfigFormat::Yaml) {
+            Err(PointCloudError::ParsingError(ParsingError::MalformedYamlError { field, .. })) => {
+                assert!(field == "in_ram")
+            }
+            _ => panic!("The in_ram flag should be malformed"),
+        }
+        let missing = yaml.replace("data_path: data/mnist.dat\n", "");
+        match PointCloudConfig::from_str(&missing, ConfigFormat::Yaml) {
+            Err(PointCloudError::ParsingError(ParsingError::MissingYamlError { field, .. })) => {
+                assert!(field == "data_path")
+            }
+            _ => panic!("The data_path should be missing"),
+        }
+    }
+}
@@ -20,9 +20,11 @@
 //! Memmapped and Ram allocated data.
 
 use super::memmapf32::Mmapf32;
-use crate::errors::PointCloudError;
+use crate::errors::{PointCloudError, PointCloudResult};
 use std::fs::OpenOptions;
+use std::ops::Range;
 use std::path::Path;
+use std::sync::RwLock;
 use super::DataSource;
 
 /// This is a thin wrapper around `memmapf32` to give it dimensionality, and name so that if there are errors in this memmap we can notify the user.
@@ -120,3 +122,134 @@ impl DataSource for DataRam {
         self.name.clone()
     }
 }
+
+/// A batch of points in a `DataGrowable`, this owns the memory `data` points to.
+#[derive(Debug)]
+struct GrowableBatch {
+    start: usize,
+    len: usize,
+    data: *mut f32,
+}
+
+/// A ram data source that can be appended to while it's being read. Each pushed batch is its own allocation that's
+/// never moved, written to, or freed until the source is dropped, so the slices `get` hands out stay valid during a push.
+#[derive(Debug)]
+pub struct DataGrowable {
+    name: String,
+    batches: RwLock<Vec<GrowableBatch>>,
+    dim: usize,
+}
+
+// The batches are only read after they're pushed, and are only freed on drop.
+unsafe impl Send for DataGrowable {}
+unsafe impl Sync for DataGrowable {}
+
+impl DataGrowable {
+    /// Creates an empty growable source of the given dimension.
+    pub fn new(dim: usize) -> DataGrowable {
+        let name = "Growable RAM".to_string();
+        DataGrowable {
+            name,
+            batches: RwLock::new(Vec::new()),
+            dim,
+        }
+    }
+
+    /// Appends a batch of points, returns the range of indexes they were given. A batch that isn't a whole number of
+    /// points is a `BatchLengthMismatch`.
+    pub fn push(&self, data: Box<[f32]>) -> PointCloudResult<Range<usize>> {
+        let len = data.len() / self.dim;
+        if len * self.dim != data.len() {
+            return Err(PointCloudError::BatchLengthMismatch {
+                expected: (len + 1) * self.dim,
+                found: data.len(),
+            });
+        }
+        let mut batches = self.batches.write().unwrap();
+        let start = batches.last().map(|b| b.start + b.len).unwrap_or(0);
+        if len > 0 {
+            let data = Box::into_raw(data) as *mut f32;
+            batches.push(GrowableBatch { start, len, data });
+        }
+        Ok(start..(start + len))
+    }
+}
+
+impl Drop for DataGrowable {
+    fn drop(&mut self) {
+        for batch in self.batches.get_mut().unwrap().drain(..) {
+            let data = std::ptr::slice_from_raw_parts_mut(batch.data, batch.len * self.dim);
+            unsafe {
+                drop(Box::from_raw(data));
+            }
+        }
+    }
+}
+
+impl DataSource for DataGrowable {
+    #[inline]
+    fn get(&self, i: usize) -> Result<&[f32], PointCloudError> {
+        let batches = self.batches.read().unwrap();
+        // The last batch that starts at or before i
+        let batch = match batches.binary_search_by(|b| b.start.cmp(&i)) {
+            Ok(k) => batches.get(k),
+            Err(0) => None,
+            Err(k) => batches.get(k - 1),
+        };
+        match batch {
+            Some(b) if i < b.start + b.len => {
+                let offset = (i - b.start) * self.dim;
+                Ok(unsafe { std::slice::from_raw_parts(b.data.add(offset), self.dim) })
+            }
+            _ => Err(PointCloudError::data_access(i, self.name.clone())),
+        }
+    }
+    #[inline]
+    fn dim(&self) -> usize {
+        self.dim
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        let batches = self.batches.read().unwrap();
+        batches.last().map(|b| b.start + b.len).unwrap_or(0)
+    }
+    #[inline]
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn growable_reads_across_batches() {
+        let data = DataGrowable::new(2);
+        assert!(data.len() == 0);
+        assert!(data.get(0).is_err());
+
+        assert!(data.push(Box::from(vec![0.0, 0.0, 1.0, 1.0])).unwrap() == (0..2));
+        assert!(data.push(Box::from(vec![])).unwrap() == (2..2));
+        assert!(data.push(Box::from(vec![2.0, 2.0])).unwrap() == (2..3));
+        assert!(data.push(Box::from(vec![3.0, 3.0, 4.0, 4.0, 5.0, 5.0])).unwrap() == (3..6));
+        assert!(data.len() == 6);
+        for i in 0..6 {
+            let x = i as f32;
+            assert!(data.get(i).unwrap() == &[x, x][..]);
+        }
+        assert!(data.get(6).is_err());
+    }
+
+    #[test]
+    fn growable_rejects_partial_points() {
+        let data = DataGrowable::new(2);
+        data.push(Box::from(vec![0.0, 0.0])).unwrap();
+        match data.push(Box::from(vec![1.0, 1.0, 2.0])) {
+            Err(PointCloudError::BatchLengthMismatch { expected: 4, found: 3 }) => {}
+            _ => panic!("a partial point should be rejected"),
+        }
+        assert!(data.len() == 1);
+        assert!(data.push(Box::from(vec![1.0, 1.0])).unwrap() == (1..2));
+    }
+}
@@ -42,6 +42,17 @@ pub enum PointCloudError {
     IoError(io::Error),
     /// Parsing error when loading a CSV file
     ParsingError(ParsingError),
+    /// A pushed point has a name that's already in the point cloud
+    DuplicateName(String),
+    /// A pushed point's metadata is missing the key, or has a value of the wrong type for it
+    MetadataMismatch(String),
+    /// A pushed batch has the wrong number of values, like names or coordinates, for the number of points
+    BatchLengthMismatch {
+        /// The number of values there should be
+        expected: usize,
+        /// The number there were
+        found: usize,
+    },
     ///
     NodeNestingError {
         /// Exact nesting error
@@ -61,6 +72,15 @@ impl fmt::Display for PointCloudError {
             &PointCloudError::NameNotInTree { .. } => {
                 write!(f,"there was an issue grabbing a name from the known names")
             }
+            &PointCloudError::DuplicateName(..) => {
+                write!(f,"the name is already in the point cloud")
+            }
+            &PointCloudError::MetadataMismatch(..) => {
+                write!(f,"the metadata doesn't match the label scheme")
+            }
+            &PointCloudError::BatchLengthMismatch { .. } => {
+                write!(f,"the batch has the wrong number of values for its points")
+            }
             &PointCloudError::NodeNestingError { .. } => {
                 write!(f,"There is a temporary node in a working tree")
             }
@@ -81,6 +101,15 @@ impl Error for PointCloudError {
             &PointCloudError::NameNotInTree { .. } => {
                 "there was an issue grabbing a name from the known names"
             }
+            &PointCloudError::DuplicateName(..) => {
+                "the name is already in the point cloud"
+            }
+            &PointCloudError::MetadataMismatch(..) => {
+                "the metadata doesn't match the label scheme"
+            }
+            &PointCloudError::BatchLengthMismatch { .. } => {
+                "the batch has the wrong number of values for its points"
+            }
             &PointCloudError::NodeNestingError { .. } => {
                 "There is a temporary node in a working tree"
             }
@@ -93,6 +122,9 @@ impl Error for PointCloudError {
             &PointCloudError::ParsingError(ref e) => Some(e),
             &PointCloudError::DataAccessError { .. } => None,
             &PointCloudError::NameNotInTree { .. } => None,
+            &PointCloudError::DuplicateName(..) => None,
+            &PointCloudError::MetadataMismatch(..) => None,
+            &PointCloudError::BatchLengthMismatch { .. } => None,
             &PointCloudError::NodeNestingError { .. } => None,
         }
     }
@@ -283,6 +283,15 @@ impl MetadataList {
         Ok(())
     }
 
+    /// Appends metadata that's passed `LabelScheme::check` against this list's scheme, so it has every key and this
+    /// can't fail.
+    pub(crate) fn push_checked(&mut self, label: Metadata) {
+        for (k, list) in self.lists.iter_mut() {
+            list.push(label[k].clone());
+        }
+        self.count += 1;
+    }
+
     /// Outputs the scheme of the metadata. This is useful for creating new metadata object off of other data.
     pub fn scheme(&self) -> Result<LabelScheme, PointCloudError> {
         let mut deser = LabelScheme::new();
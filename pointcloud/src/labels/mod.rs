@@ -126,6 +126,18 @@ impl LabelScheme {
         self.schema.insert(name, Value::Vector(v));
     }
 
+    /// Checks that the metadata has every key of the schema, with a value of the same type. Errors with
+    /// `MetadataMismatch` for the first key that doesn't.
+    pub(crate) fn check(&self, metadata: &Metadata) -> Result<(), PointCloudError> {
+        for (k, v) in self.schema.iter() {
+            match metadata.get(k) {
+                Some(value) if v.same_type(value) => {}
+                _ => return Err(PointCloudError::MetadataMismatch(k.clone())),
+            }
+        }
+        Ok(())
+    }
+
     #[doc(hidden)]
     pub fn empty(&self) -> MetadataList {
         let mut metalist = MetadataList::new();
@@ -74,24 +74,32 @@ impl InternalValueList for NumberList {
     fn get_summary(&self, indexes: &[usize]) -> Result<ValueSummary, PointCloudError> {
         let mut sum_power1 = 0.0;
         let mut sum_power2 = 0.0;
+        let mut min = std::f32::INFINITY;
+        let mut max = std::f32::NEG_INFINITY;
         let count = indexes.len();
         match &self.data {
             Vector::Real(data) => {
                 for i in indexes {
                     sum_power1 += data[*i] as f32;
                     sum_power2 += (data[*i] * data[*i]) as f32;
+                    min = min.min(data[*i] as f32);
+                    max = max.max(data[*i] as f32);
                 }
             }
             Vector::Natural(data) => {
                 for i in indexes {
                     sum_power1 += data[*i] as f32;
                     sum_power2 += (data[*i] * data[*i]) as f32;
+                    min = min.min(data[*i] as f32);
+                    max = max.max(data[*i] as f32);
                 }
             }
             Vector::Integer(data) => {
                 for i in indexes {
                     sum_power1 += data[*i] as f32;
                     sum_power2 += (data[*i] * data[*i]) as f32;
+                    min = min.min(data[*i] as f32);
+                    max = max.max(data[*i] as f32);
                 }
             }
         }
@@ -99,6 +107,8 @@ impl InternalValueList for NumberList {
         Ok(ValueSummary::NumberSummary(NumberSummary {
             sum_power1,
             sum_power2,
+            min,
+            max,
             count,
         }))
     }
@@ -128,11 +138,13 @@ impl InternalValueList for NumberList {
     }
 }
 
-/// The first 2 moments of the emprical distrbution that this is summarising. And the count!
+/// The first 2 moments of the emprical distrbution that this is summarising, its range, and the count!
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct NumberSummary {
     sum_power1: f32,
     sum_power2: f32,
+    min: f32,
+    max: f32,
     count: usize,
 }
 
@@ -159,6 +171,14 @@ impl NumberSummary {
     pub fn count(&self) -> u64 {
         self.count as u64
     }
+    /// Minimum of the numeric data that produced this summary, infinity if there was none
+    pub fn min(&self) -> f32 {
+        self.min
+    }
+    /// Maximum of the numeric data that produced this summary, negative infinity if there was none
+    pub fn max(&self) -> f32 {
+        self.max
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -180,11 +200,15 @@ impl Summary for NumberSummary {
     fn combine(summaries: &[&ValueSummary]) -> Result<ValueSummary, PointCloudError> {
         let mut sum_power1 = 0.0;
         let mut sum_power2 = 0.0;
+        let mut min = std::f32::INFINITY;
+        let mut max = std::f32::NEG_INFINITY;
         let mut count = 0;
         for vs in summaries {
             if let ValueSummary::NumberSummary(vs) = vs {
                 sum_power1 += vs.sum_power1;
                 sum_power2 += vs.sum_power2;
+                min = min.min(vs.min);
+                max = max.max(vs.max);
                 count += vs.count;
             } else {
                 return Err(PointCloudError::data_access(
@@ -197,6 +221,8 @@ impl Summary for NumberSummary {
         Ok(ValueSummary::NumberSummary(NumberSummary {
             sum_power1,
             sum_power2,
+            min,
+            max,
             count,
         }))
     }
@@ -207,16 +233,22 @@ impl Summary for NumberSummary {
                 Number::Real(v) => {
                     self.sum_power1 += v;
                     self.sum_power2 += v * v;
+                    self.min = self.min.min(*v);
+                    self.max = self.max.max(*v);
                     self.count += 1;
                 }
                 Number::Natural(v) => {
                     self.sum_power1 += *v as f32;
                     self.sum_power2 += (v * v) as f32;
+                    self.min = self.min.min(*v as f32);
+                    self.max = self.max.max(*v as f32);
                     self.count += 1;
                 }
                 Number::Integer(v) => {
                     self.sum_power1 += *v as f32;
                     self.sum_power2 += (v * v) as f32;
+                    self.min = self.min.min(*v as f32);
+                    self.max = self.max.max(*v as f32);
                     self.count += 1;
                 }
             }
@@ -91,6 +91,21 @@ impl Value {
             Value::Vector(..) => "Vector",
         }
     }
+
+    /// True if the values can go in the same list, the numbers have to be of the same kind and the vectors of the same
+    /// kind and length.
+    pub(crate) fn same_type(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Bool(..), Value::Bool(..)) => true,
+            (Value::String(..), Value::String(..)) => true,
+            (Value::Number(a), Value::Number(b)) => std::mem::discriminant(a) == std::mem::discriminant(b),
+            (Value::Vector(a), Value::Vector(b)) => {
+                std::mem::discriminant(a) == std::mem::discriminant(b) && a.len() == b.len()
+            }
+            _ => false,
+        }
+    }
 }
 
 /// Basically a json entry with values being the supported values
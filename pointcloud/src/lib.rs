@@ -34,6 +34,7 @@ pub use point_cloud::PointCloud;
 
 mod distances;
 pub use distances::*;
+pub mod config;
 pub mod errors;
 
 pub mod labels;
@@ -21,15 +21,13 @@
 
 use indexmap::IndexMap;
 use std::collections::HashMap;
-use std::fs::File;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, RwLock};
 use std::fmt;
 
 use glob::{glob_with, MatchOptions};
-use std::io::Read;
-use yaml_rust::{Yaml, YamlLoader};
+use yaml_rust::Yaml;
 use std::marker::PhantomData;
 use std::cmp::min;
 use rayon::prelude::*;
@@ -38,6 +36,7 @@ use super::*;
 use super::errors::*;
 use super::labels::*;
 use super::labels::values::*;
+use crate::config::*;
 use crate::datasources::*;
 use crate::datasources::DataSource;
 use super::distances::*;
@@ -54,6 +53,10 @@ pub struct PointCloud<M: Metric> {
     data_sources: Vec<Box<dyn DataSource>>,
     label_sources: Vec<MetadataList>,
 
+    appended_base: PointIndex,
+    appended_data: DataGrowable,
+    appended: RwLock<AppendedPoints>,
+
     loaded_centers: Mutex<IndexMap<PointIndex, Arc<Vec<f32>>>>,
     data_dim: usize,
     labels_scheme: LabelScheme,
@@ -61,6 +64,26 @@ pub struct PointCloud<M: Metric> {
     metric: PhantomData<M>,
 }
 
+/// The index of the points pushed after the point cloud was made. These get the indexes after the original points,
+/// in the order they were pushed.
+struct AppendedPoints {
+    labels: MetadataList,
+    names_to_indexes: IndexMap<PointName, PointIndex>,
+    indexes_to_names: IndexMap<PointIndex, PointName>,
+    len: usize,
+}
+
+impl AppendedPoints {
+    fn new(labels_scheme: &LabelScheme) -> RwLock<AppendedPoints> {
+        RwLock::new(AppendedPoints {
+            labels: labels_scheme.empty(),
+            names_to_indexes: IndexMap::new(),
+            indexes_to_names: IndexMap::new(),
+            len: 0,
+        })
+    }
+}
+
 impl<M: Metric> fmt::Debug for PointCloud<M> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -131,6 +154,9 @@ impl<M: Metric> PointCloud<M> {
         Ok(PointCloud {
             data_sources: data_sources,
             label_sources: label_sources,
+            appended_base: current_count,
+            appended_data: DataGrowable::new(data_dim),
+            appended: AppendedPoints::new(&labels_scheme),
             names_to_indexes: names_to_indexes,
             indexes_to_names: indexes_to_names,
             addresses: addresses,
@@ -168,6 +194,9 @@ impl<M: Metric> PointCloud<M> {
         }
         let chunk = min(15000/data_dim,20);
         Ok(PointCloud {
+            appended_base: data_source.len() as PointIndex,
+            appended_data: DataGrowable::new(data_dim),
+            appended: AppendedPoints::new(&labels_scheme),
             data_sources: vec![data_source],
             label_sources: vec![label_source],
             names_to_indexes: names_to_indexes,
@@ -181,76 +210,48 @@ impl<M: Metric> PointCloud<M> {
         })
     }
 
-    /// Given a yaml file on disk, it builds a point cloud. Minimal example below.
-    /// ```yaml
-    /// ---
-    /// data_path: DATAMEMMAP
-    /// labels_path: LABELS_CSV_OR_MEMMAP
-    /// count: NUMBER_OF_DATA_POINTS
-    /// data_dim: 784
-    /// labels_dim: 10
-    /// in_ram: True
-    /// ```
-    /// This assumes that your labels are either a CSV or a memmap file.
-    /// If one specifies a schema then this is the minimal example
-    /// ```yaml
-    /// ---
-    /// data_path: DATAMEMMAP
-    /// labels_path: LABELS_CSV_OR_MEMMAP
-    /// count: NUMBER_OF_DATA_POINTS
-    /// data_dim: 784
-    /// schema:
-    ///    natural: u32
-    ///    integer: i32
-    ///    real: f32
-    ///    string: String
-    ///    boolean: bool
-    /// ```
-    pub fn from_yaml(params: &Yaml) -> PointCloudResult<PointCloud<M>> {
-        let data_paths = &get_file_list(
-            params["data_path"]
-                .as_str()
-                .expect("Unable to read the 'labels_path'"),
-        );
-        let labels_paths = &get_file_list(
-            params["labels_path"]
-                .as_str()
-                .expect("Unable to read the 'labels_path'"),
-        );
-        let data_dim = params["data_dim"]
-            .as_i64()
-            .expect("Unable to read the 'data_dim'") as usize;
-
-        let mut deser = LabelScheme::new();
-        if params["schema"].is_badvalue() {
-            let labels_dim = params["labels_dim"]
-                .as_i64()
-                .expect("Unable to read the 'labels_dim' or the 'schema'")
-                as usize;
-            deser.add_vector("y".to_string(), labels_dim, "f32");
-        } else {
-            build_label_schema_yaml(&mut deser, &params["schema"]);
+    /// Builds the point cloud from a config, see `PointCloudConfig`. Problems with the config, like a metric that isn't
+    /// `M` or a glob that doesn't match the same number of data and label files, are `ParsingError`s for the field.
+    pub fn from_config(config: &PointCloudConfig) -> PointCloudResult<PointCloud<M>> {
+        let field_error = |field: &str| {
+            PointCloudError::ParsingError(ParsingError::MalformedYamlError {
+                file_name: config.file_name.clone(),
+                field: field.to_string(),
+            })
+        };
+        if !config.metric.matches::<M>() {
+            return Err(field_error("metric"));
         }
+        let data_paths = get_file_list(&config.data_path).ok_or_else(|| field_error("data_path"))?;
+        let labels_paths =
+            get_file_list(&config.labels_path).ok_or_else(|| field_error("labels_path"))?;
+        if data_paths.len() != labels_paths.len() {
+            return Err(field_error("labels_path"));
+        }
+        let point_cloud = PointCloud::<M>::from_memmap_files(
+            config.data_dim,
+            config.label_scheme(),
+            &data_paths,
+            &labels_paths,
+            config.in_ram,
+        )?;
+        if let Some(count) = config.count {
+            if count != point_cloud.len() {
+                return Err(field_error("count"));
+            }
+        }
+        Ok(point_cloud)
+    }
 
-        let ram_bool = match params["in_ram"].as_bool() {
-            Some(b) => b,
-            None => true,
-        };
-        PointCloud::<M>::from_memmap_files(data_dim, deser, data_paths, labels_paths, ram_bool)
+    /// Given a yaml file on disk, it builds a point cloud. See `PointCloudConfig` for the fields.
+    pub fn from_yaml(params: &Yaml) -> PointCloudResult<PointCloud<M>> {
+        let fields = ConfigFields::from_yaml(params, "yaml")?;
+        PointCloud::<M>::from_config(&PointCloudConfig::from_fields(&fields)?)
     }
 
-    /// Runs `from_yaml` on the file at a given path
+    /// Loads the config at a given path, in YAML, JSON or TOML depending on the extension, and builds the point cloud.
     pub fn from_file<P: AsRef<Path>>(path: P) -> PointCloudResult<PointCloud<M>> {
-        let mut config_file =
-            File::open(&path).expect(&format!("Unable to read config file {:?}", &path.as_ref()));
-        let mut config = String::new();
-
-        config_file
-            .read_to_string(&mut config)
-            .expect(&format!("Unable to read config file {:?}", &path.as_ref()));
-        let params_files = YamlLoader::load_from_str(&config).unwrap();
-
-        PointCloud::<M>::from_yaml(&params_files[0])
+        PointCloud::<M>::from_config(&PointCloudConfig::from_path(path)?)
     }
 
 
@@ -268,7 +269,7 @@ impl<M: Metric> PointCloud<M> {
 
     /// Total number of points in the point cloud
     pub fn len(&self) -> usize {
-        self.data_sources.iter().fold(0, |acc, mm| acc + mm.len())
+        self.data_sources.iter().fold(0, |acc, mm| acc + mm.len()) + self.appended_len()
     }
 
     /// Dimension of the data in the point cloud
@@ -278,7 +279,99 @@ impl<M: Metric> PointCloud<M> {
 
     /// The names of the data are currently a shallow wrapper around a usize.
     pub fn reference_indexes(&self) -> Vec<PointIndex> {
-        self.addresses.keys().cloned().collect()
+        let mut indexes: Vec<PointIndex> = self.addresses.keys().cloned().collect();
+        let appended = self.appended.read().unwrap();
+        indexes.extend(appended.indexes_to_names.keys());
+        indexes
+    }
+
+    /// Appends a point and its metadata, see `push_batch`. The point's name is its index.
+    pub fn push(&self, point: &[f32], metadata: Metadata) -> PointCloudResult<PointIndex> {
+        let indexes = self.push_batch(point, vec![metadata], None)?;
+        Ok(indexes[0])
+    }
+
+    /// Appends a named point and its metadata, see `push_batch`.
+    pub fn push_named(
+        &self,
+        name: PointName,
+        point: &[f32],
+        metadata: Metadata,
+    ) -> PointCloudResult<PointIndex> {
+        let indexes = self.push_batch(point, vec![metadata], Some(vec![name]))?;
+        Ok(indexes[0])
+    }
+
+    /// Appends a batch of points, with their metadata and optionally their names, and returns their new indexes.
+    /// Points without names are named by their index, like in `from_ram`. The names have to be new, and the metadata has
+    /// to have every key of the point cloud's label scheme with a value of the same type, otherwise this is a
+    /// `MetadataMismatch`. A batch with the wrong number of coordinates or names is a `BatchLengthMismatch`. Nothing is
+    /// added if there's an error.
+    ///
+    /// This only needs a shared reference, so points can be pushed while the point cloud is being read. Readers see a
+    /// pushed point once its index is returned.
+    pub fn push_batch(
+        &self,
+        points: &[f32],
+        metadata: Vec<Metadata>,
+        names: Option<Vec<PointName>>,
+    ) -> PointCloudResult<Vec<PointIndex>> {
+        if points.len() != metadata.len() * self.data_dim {
+            return Err(PointCloudError::BatchLengthMismatch {
+                expected: metadata.len() * self.data_dim,
+                found: points.len(),
+            });
+        }
+        if let Some(names) = &names {
+            if names.len() != metadata.len() {
+                return Err(PointCloudError::BatchLengthMismatch {
+                    expected: metadata.len(),
+                    found: names.len(),
+                });
+            }
+        }
+        for label in &metadata {
+            self.check_metadata(label)?;
+        }
+
+        let mut appended = self.appended.write().unwrap();
+        let start = self.appended_base + appended.len as PointIndex;
+        let indexes: Vec<PointIndex> = (start..(start + metadata.len() as PointIndex)).collect();
+        let names = match names {
+            Some(names) => names,
+            None => indexes.iter().map(|i| format!("{}", i)).collect(),
+        };
+
+        let mut batch_names = HashMap::with_capacity(names.len());
+        for name in &names {
+            if self.names_to_indexes.contains_key(name) || appended.names_to_indexes.contains_key(name) {
+                return Err(PointCloudError::DuplicateName(name.clone()));
+            }
+            if batch_names.insert(name, ()).is_some() {
+                return Err(PointCloudError::DuplicateName(name.clone()));
+            }
+        }
+
+        // The data is the only thing that can fail from here on, so it goes in first. Readers don't see it until the
+        // length is bumped at the end.
+        self.appended_data.push(Box::from(points))?;
+        for ((pi, name), label) in indexes.iter().zip(names).zip(metadata) {
+            appended.labels.push_checked(label);
+            appended.names_to_indexes.insert(name.clone(), *pi);
+            appended.indexes_to_names.insert(*pi, name);
+        }
+        appended.len += indexes.len();
+        Ok(indexes)
+    }
+
+    /// Checks that the metadata could be pushed, it has to have every key of the label scheme with a value of the same
+    /// type. Errors with `MetadataMismatch` like `push_batch`.
+    pub fn check_metadata(&self, metadata: &Metadata) -> PointCloudResult<()> {
+        self.labels_scheme.check(metadata)
+    }
+
+    fn appended_len(&self) -> usize {
+        self.appended.read().unwrap().len
     }
 
     /// Returns a arc that points to a AVX2 packed point. This also acts like a cache for these center
@@ -293,11 +386,18 @@ impl<M: Metric> PointCloud<M> {
         ))
     }
 
+    /// The address of a point, pushed points are in the source after the last of `data_sources`.
     #[inline]
     fn get_address(&self,pn: PointIndex) -> PointCloudResult<(usize,usize)> {
         match self.addresses.get(&pn) {
             Some((i, j)) => Ok((*i,*j)),
-            None => panic!("Index not found"),
+            None if pn >= self.appended_base && ((pn - self.appended_base) as usize) < self.appended_len() => {
+                Ok((self.data_sources.len(), (pn - self.appended_base) as usize))
+            }
+            None => Err(PointCloudError::data_access(
+                pn as usize,
+                "Index not found".to_string(),
+            )),
         }
     }
 
@@ -305,22 +405,39 @@ impl<M: Metric> PointCloud<M> {
     /// like outliers or leaves.
     pub fn get_point(&self, pn: PointIndex) -> PointCloudResult<&[f32]> {
         let (i,j) = self.get_address(pn)?;
-        self.data_sources[i].get(j)
+        match self.data_sources.get(i) {
+            Some(data_source) => data_source.get(j),
+            None => self.appended_data.get(j),
+        }
     }
 
-    /// Gets the name from an index
+    /// Gets the name from an index. This only knows the points the point cloud was made with, see `get_appended_name`
+    /// for pushed points.
     pub fn get_name(&self, pi: &PointIndex) -> Option<&PointName> {
         self.indexes_to_names.get(pi)
     }
 
-    /// Gets the index from the name
+    /// Gets the index from the name. This only knows the points the point cloud was made with, see
+    /// `get_appended_index` for pushed points.
     pub fn get_index(&self, pn: &PointName) -> Option<&PointIndex> {
         self.names_to_indexes.get(pn)
     }
 
+    /// Gets the name of a pushed point from its index
+    pub fn get_appended_name(&self, pi: &PointIndex) -> Option<PointName> {
+        self.appended.read().unwrap().indexes_to_names.get(pi).cloned()
+    }
+
+    /// Gets the index of a pushed point from its name
+    pub fn get_appended_index(&self, pn: &PointName) -> Option<PointIndex> {
+        self.appended.read().unwrap().names_to_indexes.get(pn).cloned()
+    }
+
     /// Gets all names in the point cloud
     pub fn get_names(&self) -> Vec<PointName> {
-        self.names_to_indexes.keys().cloned().collect()
+        let mut names: Vec<PointName> = self.names_to_indexes.keys().cloned().collect();
+        names.extend(self.appended.read().unwrap().names_to_indexes.keys().cloned());
+        names
     }
 
     /// Gets a schema to use
@@ -333,21 +450,29 @@ impl<M: Metric> PointCloud<M> {
     /// This will be changed to return a label structure that can contain many different pieces of info.
     pub fn get_metadata(&self, pn: PointIndex) -> PointCloudResult<Metadata> {
         let (i,j) = self.get_address(pn)?;
-        self.label_sources[i].get(j)
+        match self.label_sources.get(i) {
+            Some(label_source) => label_source.get(j),
+            None => self.appended.read().unwrap().labels.get(j),
+        }
     }
 
     /// Returns a complex summary of a collection of metadatas associated to a point
     pub fn get_metasummary(&self, pns: &[PointIndex]) -> PointCloudResult<MetaSummary> {
-        let mut disk_splits: Vec<Vec<usize>> = vec![Vec::new(); self.label_sources.len()];
+        let mut disk_splits: Vec<Vec<usize>> = vec![Vec::new(); self.label_sources.len() + 1];
         for pn in pns.iter() {
             let (i,j) = self.get_address(*pn)?;
             disk_splits[i].push(j);
         }
-        let disk_summaries: Vec<MetaSummary> = disk_splits
+        let appended_indexes = disk_splits.pop().unwrap();
+        let mut disk_summaries: Vec<MetaSummary> = disk_splits
             .iter()
             .enumerate()
             .map(|(i, indexes)| self.label_sources[i].get_summary(indexes).unwrap())
             .collect();
+        if !appended_indexes.is_empty() {
+            let appended = self.appended.read().unwrap();
+            disk_summaries.push(appended.labels.get_summary(&appended_indexes)?);
+        }
         MetaSummary::combine(&disk_summaries)
     }
 
@@ -458,62 +583,136 @@ impl<M: Metric> PointCloud<M> {
     }
 }
 
-fn build_label_schema_yaml(label_scheme: &mut LabelScheme, schema_yaml: &Yaml) {
-    if let Some(schema_map) = schema_yaml.as_hash() {
-        for (k, v) in schema_map.iter() {
-            let key = k.as_str().unwrap().to_string();
-            match v.as_str().unwrap() {
-                "u32" => label_scheme.add_u32(key),
-                "f32" => label_scheme.add_f32(key),
-                "i32" => label_scheme.add_i32(key),
-                "bool" => label_scheme.add_bool(key),
-                "string" => label_scheme.add_string(key),
-                "name" => label_scheme.add_name_column(&key),
-                _ => panic!(
-                    "Unknown type in schema yaml, also it should be (VALUE: TYPE): {:?}",
-                    (k, v)
-                ),
-            }
-        }
-    } else {
-        panic!("Need to correctly edit the yaml");
-    }
-}
-
-fn get_file_list(files_reg: &str) -> Vec<PathBuf> {
+/// The files matching the glob, sorted. `None` if the glob is malformed or nothing matches it.
+fn get_file_list(files_reg: &str) -> Option<Vec<PathBuf>> {
     let options = MatchOptions {
         case_sensitive: false,
         ..Default::default()
     };
     let mut paths = Vec::new();
-    let glob_paths = match glob_with(files_reg, &options) {
-        Ok(expr) => expr,
-        Err(e) => panic!("Pattern reading error {:?}", e),
-    };
-    for entry in glob_paths {
-        let path = match entry {
-            Ok(expr) => expr,
-            Err(e) => panic!("Error reading path {:?}", e),
-        };
-        paths.push(path)
+    for entry in glob_with(files_reg, &options).ok()? {
+        paths.push(entry.ok()?);
+    }
+    if paths.is_empty() {
+        None
+    } else {
+        Some(paths)
     }
-    paths
 }
 
-/*
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    pub fn build_random_test(count: usize, data_dim: usize, labels_dim: usize) -> PointCloud<L2> {
-        let data: Vec<f32> = (0..count * data_dim)
-            .map(|_i| rand::random::<f32>())
-            .collect();
-        let labels: Vec<f32> = (0..count * labels_dim)
-            .map(|_i| rand::random::<f32>())
-            .collect();
-        PointCloud::<L2>::simple_from_ram(Box::from(data), data_dim, Box::from(labels), labels_dim)
-            .unwrap()
+    /// `count` points on a line in 2 dimensions, each labeled with its index.
+    fn build_line(count: usize) -> PointCloud<L2> {
+        let data: Vec<f32> = (0..2 * count).map(|i| (i / 2) as f32).collect();
+        let labels: Vec<f32> = (0..count).map(|i| i as f32).collect();
+        PointCloud::<L2>::simple_from_ram(Box::from(data), 2, Box::from(labels), 1).unwrap()
+    }
+
+    fn label(y: f32) -> Metadata {
+        let mut metadata = Metadata::new();
+        metadata.insert("y".to_string(), Value::Vector(Vector::Real(vec![y])));
+        metadata
+    }
+
+    #[test]
+    fn push_appends_after_the_original_points() {
+        let point_cloud = build_line(10);
+        let pi = point_cloud.push(&[10.0, 10.0], label(10.0)).unwrap();
+        assert!(pi == 10);
+        assert!(point_cloud.len() == 11);
+        assert!(point_cloud.get_point(pi).unwrap() == &[10.0, 10.0][..]);
+        assert!(point_cloud.reference_indexes().contains(&pi));
+        // Unnamed points are named by their index, but only the appended lookups know them.
+        assert!(point_cloud.get_name(&pi).is_none());
+        assert!(point_cloud.get_appended_name(&pi) == Some("10".to_string()));
+        assert!(point_cloud.get_appended_index(&"10".to_string()) == Some(pi));
+        assert!(point_cloud.get_name(&3) == Some(&"3".to_string()));
+        assert!(point_cloud.get_appended_name(&3).is_none());
+
+        let pi = point_cloud.push_named("far".to_string(), &[20.0, 20.0], label(20.0)).unwrap();
+        assert!(pi == 11);
+        assert!(point_cloud.get_appended_index(&"far".to_string()) == Some(11));
+        assert!(point_cloud.get_names().contains(&"far".to_string()));
+    }
+
+    #[test]
+    fn push_batch_reads_across_batches() {
+        let point_cloud = build_line(10);
+        for batch in 0..3 {
+            let start = 10 + 4 * batch;
+            let points: Vec<f32> = (2 * start..2 * (start + 4)).map(|i| (i / 2) as f32).collect();
+            let metadata = (start..start + 4).map(|i| label(i as f32)).collect();
+            let indexes = point_cloud.push_batch(&points, metadata, None).unwrap();
+            assert!(indexes == (start as PointIndex..(start + 4) as PointIndex).collect::<Vec<_>>());
+        }
+        assert!(point_cloud.len() == 22);
+        for pi in 0..22 {
+            let x = pi as f32;
+            assert!(point_cloud.get_point(pi).unwrap() == &[x, x][..]);
+            match point_cloud.get_metadata(pi).unwrap().get("y") {
+                Some(Value::Vector(Vector::Real(y))) => assert!(y[0] == x),
+                _ => panic!("missing label"),
+            }
+        }
+        assert!(point_cloud.get_point(22).is_err());
+        let dists = point_cloud.distances_to_point(&[0.0, 0.0], &[0, 9, 10, 21]).unwrap();
+        assert!(dists[3] > dists[2] && dists[2] > dists[1] && dists[1] > dists[0]);
+    }
+
+    #[test]
+    fn push_batch_rejects_bad_batches() {
+        let point_cloud = build_line(10);
+        let points = [10.0, 10.0, 11.0, 11.0];
+        let names = |a: &str, b: &str| Some(vec![a.to_string(), b.to_string()]);
+        let labels = || vec![label(10.0), label(11.0)];
+
+        // A name that's already taken, by an original point, a pushed point, or the same batch.
+        point_cloud.push_named("taken".to_string(), &[9.5, 9.5], label(9.5)).unwrap();
+        for (a, b) in [("3", "new"), ("taken", "new"), ("new", "new")] {
+            match point_cloud.push_batch(&points, labels(), names(a, b)) {
+                Err(PointCloudError::DuplicateName(name)) => assert!(name == a || name == b),
+                _ => panic!("a duplicate name should be rejected"),
+            }
+        }
+        match point_cloud.push_batch(&points[..3], labels(), None) {
+            Err(PointCloudError::BatchLengthMismatch { expected: 4, found: 3 }) => {}
+            _ => panic!("a batch with a partial point should be rejected"),
+        }
+        match point_cloud.push_batch(&points, labels(), Some(vec!["one".to_string()])) {
+            Err(PointCloudError::BatchLengthMismatch { expected: 2, found: 1 }) => {}
+            _ => panic!("a batch with too few names should be rejected"),
+        }
+        let mut mistyped = label(11.0);
+        mistyped.insert("y".to_string(), Value::Vector(Vector::Real(vec![1.0, 2.0])));
+        match point_cloud.push_batch(&points, vec![label(10.0), mistyped], None) {
+            Err(PointCloudError::MetadataMismatch(key)) => assert!(key == "y"),
+            _ => panic!("mistyped metadata should be rejected"),
+        }
+
+        // Nothing from the rejected batches went in.
+        assert!(point_cloud.len() == 11);
+        assert!(point_cloud.get_appended_index(&"new".to_string()).is_none());
+        assert!(point_cloud.push_batch(&points, labels(), names("new", "newer")).unwrap() == vec![11, 12]);
+    }
+
+    #[test]
+    fn metasummary_covers_pushed_points() {
+        let point_cloud = build_line(10);
+        let metadata = (10..14).map(|i| label(i as f32)).collect();
+        let points: Vec<f32> = (20..28).map(|i| (i / 2) as f32).collect();
+        let indexes = point_cloud.push_batch(&points, metadata, None).unwrap();
+
+        let mean_and_count = |indexes: &[PointIndex]| {
+            match point_cloud.get_metasummary(indexes).unwrap().summaries.get("y") {
+                Some(ValueSummary::VectorSummary(summary)) => (summary.mean()[0], summary.count()),
+                _ => panic!("missing summary"),
+            }
+        };
+        assert!(mean_and_count(&indexes) == (11.5, 4));
+        assert!(mean_and_count(&[0, 9, 10, 13]) == (8.0, 4));
+        assert!(point_cloud.get_metasummary(&[14]).is_err());
     }
 }
-*/
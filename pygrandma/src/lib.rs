@@ -75,6 +75,12 @@ impl PyGrandma {
             None => panic!("Set too late"),
         };
     }
+    pub fn set_seed(&mut self, x: u64) {
+        match &mut self.builder {
+            Some(builder) => builder.set_seed(x),
+            None => panic!("Set too late"),
+        };
+    }
 
     pub fn set_metric(&mut self, metric_name:String) {
         self.metric = metric_name;
@@ -131,6 +137,19 @@ impl PyGrandma {
         let results = self.reader.as_ref().unwrap().knn(point.as_slice().unwrap(),k).unwrap();
         results.iter().map(|(d,i)| *i).collect()
     }
+
+    pub fn knn_batch(&self,points:&PyArray2<f32>,k:usize) -> PyResult<(Py<PyArray2<f32>>,Py<PyArray2<u64>>)> {
+        let len = points.shape()[0];
+        let dim = points.shape()[1];
+        let reader = self.reader.as_ref().unwrap();
+        let (dists, indexes) = reader.knn_batch(points.as_slice().unwrap(),dim,k).unwrap();
+        let k = dists.len().checked_div(len).unwrap_or(0);
+        let py_dists = Array2::from_shape_vec((len,k), dists).unwrap();
+        let py_indexes = Array2::from_shape_vec((len,k), indexes).unwrap();
+        let gil = GILGuard::acquire();
+        let py = gil.python();
+        Ok((py_dists.into_pyarray(py).to_owned(),py_indexes.into_pyarray(py).to_owned()))
+    }
 }
 
 #[pyclass(module = "pygrandma")]